@@ -0,0 +1,495 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use crate::{
+    authorisation::Outcome,
+    card::CardNetwork,
+    currency::Currency,
+    key_store::{self, KeyStore},
+    mac::MacConfig,
+    messaging_specification::{BitField, BitMap, MessagingSpecification, OperationParser},
+    operation::{Operation, RequestType},
+    operation_field::{regex, MID_REGEX},
+    payment::PaymentType,
+    GatewayError, Result,
+};
+
+regex!(STFS_MID_REGEX, "^0001049[0-9]{8}$");
+
+/// Everything the gateway needs to know to talk to a bank
+pub trait Acquirer: Send + Sync {
+    /// The unique, lowercase name the acquirer is registered under
+    fn name(&self) -> &'static str;
+
+    fn spec(&self) -> MessagingSpecification;
+
+    /// The template requests are encoded with, override this to tweak the spec's template
+    fn template(&self) -> BitMap {
+        self.spec().get_template()
+    }
+
+    /// The pattern a merchant's MID must match to be processed by this acquirer
+    fn mid_pattern(&self) -> &'static regex::Regex {
+        &MID_REGEX
+    }
+
+    /// Maps the acquirer's raw response codes to their normalised outcome
+    fn response_codes(&self) -> &'static [(&'static str, Outcome)];
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// The key store name of the zone PIN key shared with the acquirer, PIN blocks must be
+    /// encrypted under it before they are sent
+    fn zone_key_name(&self) -> String {
+        format!("{}.zpk", self.name())
+    }
+
+    /// How requests and responses are authenticated, `None` when the acquirer does not use MACs
+    fn mac(&self) -> Option<MacConfig> {
+        None
+    }
+
+    /// Where the keys shared with the acquirer are kept, the installed key store unless overridden
+    fn key_store(&self) -> Result<Arc<dyn KeyStore>> {
+        key_store::installed()
+    }
+}
+
+/// What an acquirer is able to process
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub request_types: &'static [RequestType],
+    pub currencies: &'static [Currency],
+    pub payment_types: &'static [PaymentType],
+    /// Card networks accepted, only checked for card payments
+    pub networks: &'static [CardNetwork],
+    /// Whether Level 2 and 3 purchasing card data is sent, other banks get the transaction without it
+    pub purchasing_data: bool,
+    /// Whether dynamic currency conversion can be offered, operations with a DCC quote are refused otherwise
+    pub dcc: bool,
+}
+
+impl Capabilities {
+    /// Fails with `GatewayError::CapabilityError` if any part of the operation cannot be processed
+    pub fn check(&self, op: &Operation) -> Result<()> {
+        let unsupported = |what: String| Err(GatewayError::CapabilityError(what));
+        if let Some(request_type) = op.request_type {
+            if !self.request_types.contains(&request_type) {
+                return unsupported(format!("request type {request_type:?}"));
+            }
+        }
+        if let Some(transaction) = &op.transaction {
+            let currency = transaction.amount.currency();
+            if !self.currencies.contains(&currency) {
+                return unsupported(format!("currency {currency}"));
+            }
+        }
+        if let Some(payment) = &op.payment {
+            if !self.payment_types.contains(&payment.payment_type()) {
+                return unsupported(format!("payment type {:?}", payment.payment_type()));
+            }
+            if let Some(network) = payment.network() {
+                if !self.networks.contains(&network) {
+                    return unsupported(format!("network {network}"));
+                }
+            }
+        }
+        if op.dcc.is_some() && !self.dcc {
+            return unsupported("dynamic currency conversion".into());
+        }
+        Ok(())
+    }
+}
+
+/// Only the request types the ISO8853 encoder can build, refunds and account checks are to come
+const CARD_REQUEST_TYPES: &[RequestType] = &[RequestType::Auth, RequestType::Reversal];
+const CARD_NETWORKS: &[CardNetwork] = &[CardNetwork::Visa, CardNetwork::Mastercard];
+
+static REGISTRY: LazyLock<RwLock<HashMap<&'static str, Arc<dyn Acquirer>>>> =
+    LazyLock::new(|| {
+        let builtins: [Arc<dyn Acquirer>; 7] = [
+            Arc::new(Iso8583Acquirer {
+                name: "ems",
+                currencies: &[Currency::GBP, Currency::USD, Currency::EUR],
+                payment_types: &[PaymentType::Card, PaymentType::Iban, PaymentType::Wallet],
+                purchasing_data: true,
+                dcc: true,
+            }),
+            Arc::new(Iso8583Acquirer {
+                name: "fdms",
+                currencies: &[Currency::GBP, Currency::USD],
+                payment_types: &[PaymentType::Card, PaymentType::Wallet],
+                purchasing_data: true,
+                dcc: true,
+            }),
+            Arc::new(Iso8583Acquirer {
+                name: "cardnet",
+                currencies: &[Currency::GBP],
+                payment_types: &[PaymentType::Card, PaymentType::Wallet],
+                purchasing_data: false,
+                dcc: false,
+            }),
+            Arc::new(StfsAcquirer),
+            Arc::new(ApacsAcquirer { name: "hsbc" }),
+            Arc::new(ApacsAcquirer { name: "lloyds" }),
+            Arc::new(ApacsAcquirer { name: "barclays" }),
+        ];
+        RwLock::new(builtins.into_iter().map(|a| (a.name(), a)).collect())
+    });
+
+/// Makes an acquirer available as `Bank::Custom(acquirer.name())`
+pub fn register(acquirer: Arc<dyn Acquirer>) -> Result<()> {
+    let mut registry = REGISTRY.write().expect("acquirer registry poisoned");
+    if registry.contains_key(acquirer.name()) {
+        return Err(GatewayError::ValidationError(format!(
+            "An acquirer named '{}' is already registered",
+            acquirer.name()
+        )));
+    }
+    registry.insert(acquirer.name(), acquirer);
+    Ok(())
+}
+
+pub fn lookup(name: &str) -> Option<Arc<dyn Acquirer>> {
+    REGISTRY
+        .read()
+        .expect("acquirer registry poisoned")
+        .get(name)
+        .cloned()
+}
+
+/// ISO 8583 response codes, as returned by the ISO8853 acquirers
+static ISO8583_RESPONSE_CODES: &[(&str, Outcome)] = &[
+    ("00", Outcome::Approved),
+    ("08", Outcome::Approved),
+    ("01", Outcome::Referral),
+    ("02", Outcome::Referral),
+    ("04", Outcome::Declined),
+    ("05", Outcome::Declined),
+    ("14", Outcome::Declined),
+    ("41", Outcome::Declined),
+    ("43", Outcome::Declined),
+    ("51", Outcome::Declined),
+    ("54", Outcome::Declined),
+    ("57", Outcome::Declined),
+    ("61", Outcome::Declined),
+    ("30", Outcome::Error),
+    ("91", Outcome::Error),
+    ("96", Outcome::Error),
+];
+
+/// STFS also approves with 85 (no reason to decline) for account checks, the rest are the ISO 8583 codes
+static STFS_RESPONSE_CODES: LazyLock<Vec<(&str, Outcome)>> = LazyLock::new(|| {
+    [("85", Outcome::Approved)]
+        .into_iter()
+        .chain(ISO8583_RESPONSE_CODES.iter().copied())
+        .collect()
+});
+
+static APACS_RESPONSE_CODES: &[(&str, Outcome)] = &[
+    ("00", Outcome::Approved),
+    ("02", Outcome::Referral),
+    ("04", Outcome::Declined),
+    ("05", Outcome::Declined),
+    ("30", Outcome::Error),
+];
+
+/// The card acquirers that speak plain ISO8853
+struct Iso8583Acquirer {
+    name: &'static str,
+    currencies: &'static [Currency],
+    payment_types: &'static [PaymentType],
+    purchasing_data: bool,
+    dcc: bool,
+}
+
+impl Acquirer for Iso8583Acquirer {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn spec(&self) -> MessagingSpecification {
+        MessagingSpecification::Iso8853
+    }
+
+    fn response_codes(&self) -> &'static [(&'static str, Outcome)] {
+        ISO8583_RESPONSE_CODES
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            request_types: CARD_REQUEST_TYPES,
+            currencies: self.currencies,
+            payment_types: self.payment_types,
+            networks: CARD_NETWORKS,
+            purchasing_data: self.purchasing_data,
+            dcc: self.dcc,
+        }
+    }
+}
+
+struct StfsAcquirer;
+
+impl Acquirer for StfsAcquirer {
+    fn name(&self) -> &'static str {
+        "stfs"
+    }
+
+    fn spec(&self) -> MessagingSpecification {
+        MessagingSpecification::Iso8853
+    }
+
+    fn template(&self) -> BitMap {
+        // STFS is essentially the iso spec but with minor differences, so all we need to do is clone the template (any less expensive way?) and change one of the parser functions
+        let mut template = self.spec().get_template();
+        template.entry(1).and_modify(|bf| {
+            if let BitField::Single { parser, .. } = bf {
+                *parser = stfs_parsers::TransactionIdentifier as OperationParser;
+            }
+        });
+        template
+    }
+
+    fn mid_pattern(&self) -> &'static regex::Regex {
+        &STFS_MID_REGEX
+    }
+
+    fn response_codes(&self) -> &'static [(&'static str, Outcome)] {
+        &STFS_RESPONSE_CODES
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            request_types: CARD_REQUEST_TYPES,
+            currencies: &[Currency::GBP],
+            payment_types: &[PaymentType::Card, PaymentType::Wallet],
+            networks: CARD_NETWORKS,
+            purchasing_data: false,
+            dcc: false,
+        }
+    }
+}
+
+struct ApacsAcquirer {
+    name: &'static str,
+}
+
+impl Acquirer for ApacsAcquirer {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn spec(&self) -> MessagingSpecification {
+        MessagingSpecification::Apacs
+    }
+
+    fn response_codes(&self) -> &'static [(&'static str, Outcome)] {
+        APACS_RESPONSE_CODES
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            // APACS messages cannot be encoded yet, so nothing can be sent to these banks
+            request_types: &[],
+            currencies: &[Currency::GBP],
+            payment_types: &[PaymentType::Card, PaymentType::Account],
+            networks: CARD_NETWORKS,
+            purchasing_data: false,
+            dcc: false,
+        }
+    }
+}
+
+mod stfs_parsers {
+    use crate::{messaging_specification::OperationParseResult, operation::Operation};
+
+    #[allow(non_snake_case)]
+    pub fn TransactionIdentifier(_: &Operation) -> OperationParseResult {
+        Ok(Some("123".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bank::Bank,
+        key_store,
+        mac::{self, MacAlgorithm},
+        operation::{example_operation, Operation},
+        operation_field::{ctx, Mid, Validator},
+    };
+
+    use super::*;
+
+    regex!(ACME_MID_REGEX, "^ACME[0-9]+$");
+
+    /// An acquirer defined outside of the built-ins, as a downstream crate would
+    struct Acme;
+
+    impl Acquirer for Acme {
+        fn name(&self) -> &'static str {
+            "acme"
+        }
+
+        fn spec(&self) -> MessagingSpecification {
+            MessagingSpecification::Iso8853
+        }
+
+        fn mid_pattern(&self) -> &'static regex::Regex {
+            &ACME_MID_REGEX
+        }
+
+        fn response_codes(&self) -> &'static [(&'static str, Outcome)] {
+            &[("000", Outcome::Approved), ("100", Outcome::Declined)]
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                request_types: &[RequestType::Auth],
+                currencies: &[Currency::GBP],
+                payment_types: &[PaymentType::Card],
+                networks: &[CardNetwork::Visa],
+                purchasing_data: false,
+                dcc: false,
+            }
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        register(Arc::new(Acme)).unwrap();
+        assert_eq!(
+            Err(GatewayError::ValidationError(
+                "An acquirer named 'acme' is already registered".into()
+            )),
+            register(Arc::new(Acme))
+        );
+        assert_eq!(
+            Err(GatewayError::ValidationError(
+                "An acquirer named 'ems' is already registered".into()
+            )),
+            register(Arc::new(Iso8583Acquirer {
+                name: "ems",
+                currencies: &[],
+                payment_types: &[],
+                purchasing_data: true,
+                dcc: true,
+            }))
+        );
+
+        let bank: Bank = "acme".parse().unwrap();
+        assert_eq!(Bank::Custom("acme"), bank);
+        assert_eq!(Outcome::Declined, bank.outcome_for("100"));
+        assert_eq!(Outcome::Error, bank.outcome_for("05"));
+        let op = Operation {
+            bank: Some(bank),
+            ..example_operation()
+        };
+        assert_eq!(
+            Ok("0103abc0204AUTH0342011641111111111111110201V030612202404031230434011000000123450203GBP0309Ben Jones052001160000104912345678".to_string()),
+            op.encode()
+        );
+        assert_eq!(
+            Err(GatewayError::ValidationError(
+                "mid '000104912345678' does not match regex ^ACME[0-9]+$".into()
+            )),
+            Mid::validate("000104912345678", ctx!(bank))
+        );
+    }
+
+    /// An acquirer that authenticates its messages with AES-CMAC
+    struct Macquirer;
+
+    impl Acquirer for Macquirer {
+        fn name(&self) -> &'static str {
+            "macquirer"
+        }
+
+        fn spec(&self) -> MessagingSpecification {
+            MessagingSpecification::Iso8853
+        }
+
+        fn response_codes(&self) -> &'static [(&'static str, Outcome)] {
+            ISO8583_RESPONSE_CODES
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Acme.capabilities()
+        }
+
+        fn mac(&self) -> Option<MacConfig> {
+            Some(MacConfig {
+                algorithm: MacAlgorithm::AesCmac,
+                key_name: "aes".into(),
+                position: 64,
+            })
+        }
+
+        fn key_store(&self) -> Result<Arc<dyn KeyStore>> {
+            Ok(Arc::new(key_store::test_key_store()))
+        }
+    }
+
+    #[test]
+    fn test_mac() {
+        register(Arc::new(Macquirer)).unwrap();
+        let bank: Bank = "macquirer".parse().unwrap();
+        let op = Operation {
+            bank: Some(bank),
+            ..example_operation()
+        };
+        let encoded = op.encode().unwrap();
+        let (body, mac) = encoded.split_at(encoded.len() - 20);
+        assert_eq!(Ok(body.to_string()), Bank::Ems.encode_request(&op));
+        assert!(mac.starts_with("6416"));
+
+        let spec = MessagingSpecification::Iso8853;
+        let mut response = spec.encode_field(1, "abc").unwrap() + &spec.encode_field(2, "00").unwrap();
+        mac::append_mac(
+            spec,
+            &Macquirer.mac().unwrap(),
+            &*Macquirer.key_store().unwrap(),
+            &mut response,
+        )
+        .unwrap();
+        assert_eq!(
+            Some(&"00".to_string()),
+            bank.decode_response_string(&response).unwrap().get("responsecode")
+        );
+        let tests = [
+            (
+                response.replace("0200", "0205"),
+                "Message MAC does not match its contents",
+            ),
+            (
+                format!("{}64160000000000000000", &response[..response.len() - 20]),
+                "Message MAC does not match its contents",
+            ),
+            (response[..response.len() - 20].to_string(), "Message has no MAC"),
+        ];
+        for (i, (response, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(
+                Err(GatewayError::MacError(expected.into())),
+                bank.decode_response_string(&response),
+                "Case number {}",
+                i + 1
+            );
+        }
+    }
+
+    #[test]
+    fn test_unregistered() {
+        let bank = Bank::Custom("nobody");
+        assert_eq!(
+            Err(GatewayError::ValidationError("Unknown acquirer: nobody".into())),
+            bank.acquirer().map(|a| a.name())
+        );
+        assert_eq!(Outcome::Error, bank.outcome_for("00"));
+        assert_eq!(
+            Err(GatewayError::FieldError("Invalid bank: nobody".into())),
+            "nobody".parse::<Bank>()
+        );
+    }
+}
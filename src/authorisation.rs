@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use crate::{bank::Bank, messaging_specification::ResponseField, GatewayError, Result};
+
+/// The normalised result of a request, whichever bank processed it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Approved,
+    Declined,
+    Referral,
+    Error,
+}
+
+impl Outcome {
+    /// The code merchants see for this outcome
+    pub fn code(&self) -> &'static str {
+        match self {
+            Outcome::Approved => "00",
+            Outcome::Declined => "05",
+            Outcome::Referral => "02",
+            Outcome::Error => "96",
+        }
+    }
+}
+
+/// Result of an address or security code check
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CheckResult {
+    Matched,
+    NotMatched,
+    NotChecked,
+    NotProvided,
+}
+
+impl CheckResult {
+    fn from_code(code: &str) -> Self {
+        match code {
+            "M" => CheckResult::Matched,
+            "N" => CheckResult::NotMatched,
+            "P" => CheckResult::NotProvided,
+            _ => CheckResult::NotChecked,
+        }
+    }
+}
+
+/// The address verification result, which checks the first line of the address and the
+/// postcode separately
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AvsResult {
+    pub address: CheckResult,
+    pub postcode: CheckResult,
+}
+
+impl AvsResult {
+    /// Two character codes give the address then the postcode result, a single one covers both
+    fn from_code(code: &str) -> Self {
+        // a code that is not ASCII cannot be split by byte and is not one we recognise anyway
+        let (address, postcode) = match code.len() {
+            2 if code.is_ascii() => code.split_at(1),
+            _ => (code, code),
+        };
+        Self {
+            address: CheckResult::from_code(address),
+            postcode: CheckResult::from_code(postcode),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorisationResult {
+    pub outcome: Outcome,
+    /// The response code exactly as the bank sent it
+    pub response_code: String,
+    pub auth_code: Option<String>,
+    pub avs_result: Option<AvsResult>,
+    pub cvv_result: Option<CheckResult>,
+    pub bank_reference: Option<String>,
+}
+
+impl AuthorisationResult {
+    pub fn from_response(bank: &Bank, response: &HashMap<ResponseField, String>) -> Result<Self> {
+        let response_code = response
+            .get(&ResponseField::ResponseCode)
+            .ok_or(GatewayError::DecodingError("Missing responsecode".into()))?;
+        Ok(Self {
+            outcome: bank.outcome_for(response_code),
+            response_code: response_code.clone(),
+            auth_code: response.get(&ResponseField::AuthCode).cloned(),
+            avs_result: response
+                .get(&ResponseField::AvsResult)
+                .map(|code| AvsResult::from_code(code)),
+            cvv_result: response
+                .get(&ResponseField::CvvResult)
+                .map(|code| CheckResult::from_code(code)),
+            bank_reference: response.get(&ResponseField::BankReference).cloned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_authorisation() {
+        let tests = [
+            (
+                Bank::Ems,
+                "0103abc0202000306TEST010501M0601N0708REF12345",
+                Ok(AuthorisationResult {
+                    outcome: Outcome::Approved,
+                    response_code: "00".into(),
+                    auth_code: Some("TEST01".into()),
+                    avs_result: Some(AvsResult {
+                        address: CheckResult::Matched,
+                        postcode: CheckResult::Matched,
+                    }),
+                    cvv_result: Some(CheckResult::NotMatched),
+                    bank_reference: Some("REF12345".into()),
+                }),
+            ),
+            (
+                Bank::Ems,
+                "0202000502NM",
+                Ok(AuthorisationResult {
+                    outcome: Outcome::Approved,
+                    response_code: "00".into(),
+                    auth_code: None,
+                    avs_result: Some(AvsResult {
+                        address: CheckResult::NotMatched,
+                        postcode: CheckResult::Matched,
+                    }),
+                    cvv_result: None,
+                    bank_reference: None,
+                }),
+            ),
+            (
+                Bank::Cardnet,
+                "020251",
+                Ok(AuthorisationResult {
+                    outcome: Outcome::Declined,
+                    response_code: "51".into(),
+                    auth_code: None,
+                    avs_result: None,
+                    cvv_result: None,
+                    bank_reference: None,
+                }),
+            ),
+            (
+                Bank::Stfs,
+                "0202850501X",
+                Ok(AuthorisationResult {
+                    outcome: Outcome::Approved,
+                    response_code: "85".into(),
+                    auth_code: None,
+                    avs_result: Some(AvsResult {
+                        address: CheckResult::NotChecked,
+                        postcode: CheckResult::NotChecked,
+                    }),
+                    cvv_result: None,
+                    bank_reference: None,
+                }),
+            ),
+            (
+                Bank::Ems,
+                "0202000502é",
+                Ok(AuthorisationResult {
+                    outcome: Outcome::Approved,
+                    response_code: "00".into(),
+                    auth_code: None,
+                    avs_result: Some(AvsResult {
+                        address: CheckResult::NotChecked,
+                        postcode: CheckResult::NotChecked,
+                    }),
+                    cvv_result: None,
+                    bank_reference: None,
+                }),
+            ),
+            (
+                Bank::Fdms,
+                "020285",
+                Ok(AuthorisationResult {
+                    outcome: Outcome::Error,
+                    response_code: "85".into(),
+                    auth_code: None,
+                    avs_result: None,
+                    cvv_result: None,
+                    bank_reference: None,
+                }),
+            ),
+            (
+                Bank::Ems,
+                "0103abc",
+                Err(GatewayError::DecodingError("Missing responsecode".into())),
+            ),
+            (
+                Bank::Hsbc,
+                "020200",
+                Err(GatewayError::DecodingError(
+                    "APACS messages cannot be decoded yet".into(),
+                )),
+            ),
+        ];
+        for (i, (bank, encoded, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(expected, bank.decode_authorisation(encoded), "Case number {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_outcome_for() {
+        let tests = [
+            (Bank::Ems, "01", (Outcome::Referral, "02")),
+            (Bank::Stfs, "05", (Outcome::Declined, "05")),
+            (Bank::Stfs, "85", (Outcome::Approved, "00")),
+            (Bank::Barclays, "02", (Outcome::Referral, "02")),
+            (Bank::Hsbc, "01", (Outcome::Error, "96")),
+            (Bank::Lloyds, "00", (Outcome::Approved, "00")),
+        ];
+        for (i, (bank, code, (expected, merchant_code))) in tests.into_iter().enumerate() {
+            let outcome = bank.outcome_for(code);
+            assert_eq!(expected, outcome, "Case number {}", i + 1);
+            assert_eq!(merchant_code, outcome.code(), "Case number {}", i + 1);
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 
 use crate::{
-    Result,
-    messaging_specification::{BitField, MessagingSpecification, OperationParser},
+    GatewayError, Result,
+    acquirer::{self, Acquirer, Capabilities},
+    authorisation::{AuthorisationResult, Outcome},
+    mac,
+    messaging_specification::MessagingSpecification,
     operation::Operation,
 };
 
@@ -15,43 +18,116 @@ pub enum Bank {
     Stfs,
     Lloyds,
     Barclays,
+    /// An acquirer registered through `acquirer::register`
+    Custom(&'static str),
 }
 
 impl Bank {
-    pub fn encode_request(&self, op: &Operation) -> Result<String> {
-        let spec = self.spec();
+    pub fn name(&self) -> &'static str {
         match self {
-            Bank::Stfs => {
-                // STFS is essentially the iso spec but with minor differences, so all we need to do is clone the template (any less expensive way?) and change one of the parser functions
-                let mut template = spec.get_template();
-                template.entry(1).and_modify(|bf| {
-                    if let BitField::Single { parser, .. } = bf {
-                        *parser = stfs_parsers::TransactionIdentifier as OperationParser;
-                    }
-                });
-                spec.encode_using_template(&op, &template)
+            Bank::Ems => "ems",
+            Bank::Hsbc => "hsbc",
+            Bank::Fdms => "fdms",
+            Bank::Cardnet => "cardnet",
+            Bank::Stfs => "stfs",
+            Bank::Lloyds => "lloyds",
+            Bank::Barclays => "barclays",
+            Bank::Custom(name) => name,
+        }
+    }
+
+    pub fn acquirer(&self) -> Result<Arc<dyn Acquirer>> {
+        acquirer::lookup(self.name()).ok_or(GatewayError::ValidationError(format!(
+            "Unknown acquirer: {}",
+            self.name()
+        )))
+    }
+
+    pub fn encode_request(&self, op: &Operation) -> Result<String> {
+        let acquirer = self.acquirer()?;
+        let pin_block = op.card_present.as_ref().and_then(|cp| cp.pin_block.as_ref());
+        if let Some(pin_block) = pin_block {
+            if pin_block.key_name != acquirer.zone_key_name() {
+                return Err(GatewayError::EncodingError(format!(
+                    "PIN block is encrypted under {} rather than {}",
+                    pin_block.key_name,
+                    acquirer.zone_key_name()
+                )));
             }
-            _ => spec.encode_request(op),
         }
+        let mut encoded = acquirer.spec().encode_using_template(op, &acquirer.template())?;
+        if let Some(config) = acquirer.mac() {
+            mac::append_mac(acquirer.spec(), &config, &*acquirer.key_store()?, &mut encoded)?;
+        }
+        Ok(encoded)
     }
 
-    pub fn spec(&self) -> MessagingSpecification {
-        match self {
-            Bank::Ems | Bank::Fdms | Bank::Cardnet | Bank::Stfs => MessagingSpecification::Iso8853,
-            Bank::Hsbc | Bank::Lloyds | Bank::Barclays => MessagingSpecification::Apacs,
+    /// Strips the response's MAC once it is verified, responses from banks without MACs are left as they are
+    fn verify_response<'a>(&self, encoded_string: &'a str) -> Result<&'a str> {
+        let acquirer = self.acquirer()?;
+        match acquirer.mac() {
+            Some(config) => {
+                mac::verify_mac(acquirer.spec(), &config, &*acquirer.key_store()?, encoded_string)
+            }
+            None => Ok(encoded_string),
         }
     }
 
-    pub fn decode_response_string(&self, _encoded_string: &str) -> HashMap<String, String> {
-        todo!()
+    pub fn zone_key_name(&self) -> Result<String> {
+        Ok(self.acquirer()?.zone_key_name())
+    }
+
+    pub fn capabilities(&self) -> Result<Capabilities> {
+        Ok(self.acquirer()?.capabilities())
+    }
+
+    pub fn spec(&self) -> Result<MessagingSpecification> {
+        Ok(self.acquirer()?.spec())
+    }
+
+    /// Normalises one of this bank's raw response codes, anything unrecognised is an error
+    pub fn outcome_for(&self, response_code: &str) -> Outcome {
+        self.acquirer().map_or(Outcome::Error, |acquirer| {
+            acquirer
+                .response_codes()
+                .iter()
+                .find(|(code, _)| *code == response_code)
+                .map_or(Outcome::Error, |(_, outcome)| *outcome)
+        })
+    }
+
+    pub fn decode_authorisation(&self, encoded_string: &str) -> Result<AuthorisationResult> {
+        let encoded_string = self.verify_response(encoded_string)?;
+        AuthorisationResult::from_response(self, &self.spec()?.decode_response(encoded_string)?)
+    }
+
+    pub fn decode_response_string(&self, encoded_string: &str) -> Result<HashMap<String, String>> {
+        let encoded_string = self.verify_response(encoded_string)?;
+        Ok(self
+            .spec()?
+            .decode_response(encoded_string)?
+            .into_iter()
+            .map(|(field, value)| (field.name().to_string(), value))
+            .collect())
     }
 }
 
-mod stfs_parsers {
-    use crate::{messaging_specification::OperationParseResult, operation::Operation};
+impl FromStr for Bank {
+    type Err = GatewayError;
 
-    #[allow(non_snake_case)]
-    pub fn TransactionIdentifier(_: &Operation) -> OperationParseResult {
-        Ok(Some("123".into()))
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "ems" => Ok(Bank::Ems),
+            "hsbc" => Ok(Bank::Hsbc),
+            "fdms" => Ok(Bank::Fdms),
+            "cardnet" => Ok(Bank::Cardnet),
+            "stfs" => Ok(Bank::Stfs),
+            "lloyds" => Ok(Bank::Lloyds),
+            "barclays" => Ok(Bank::Barclays),
+            name => match acquirer::lookup(name) {
+                Some(acquirer) => Ok(Bank::Custom(acquirer.name())),
+                None => Err(GatewayError::FieldError(format!("Invalid bank: {name}"))),
+            },
+        }
     }
 }
@@ -0,0 +1,66 @@
+//! Local acquirer simulator.
+//!
+//! Usage: `simulator [address] [config file]`, listening on `127.0.0.1:8583` by default.
+//! Each request is a single encoded message terminated by a newline, answered the same way.
+
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+use gateway_rs::simulator::{Reply, Simulator, SimulatorConfig};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8583";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let address = args.next().unwrap_or(DEFAULT_ADDRESS.into());
+    let config = match args.next() {
+        Some(path) => SimulatorConfig::load(&path).unwrap_or_else(|err| {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }),
+        None => SimulatorConfig::default(),
+    };
+    let listener = TcpListener::bind(&address).unwrap_or_else(|err| {
+        eprintln!("cannot bind to {address}: {err}");
+        std::process::exit(1);
+    });
+    println!("simulating {:?} on {address}", config.bank);
+    let simulator = Arc::new(Simulator::new(config));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let simulator = Arc::clone(&simulator);
+                thread::spawn(move || handle(&simulator, stream));
+            }
+            Err(err) => eprintln!("connection failed: {err}"),
+        }
+    }
+}
+
+fn handle(simulator: &Simulator, stream: TcpStream) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(err) => return eprintln!("cannot write to connection: {err}"),
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(request) = line else { return };
+        println!("<- {}", simulator.masked(&request));
+        match simulator.respond(&request) {
+            Reply::Message(response) => {
+                println!("-> {response}");
+                if writeln!(writer, "{response}").is_err() {
+                    return;
+                }
+            }
+            Reply::NoReply(hold) => {
+                println!("-- holding for {hold:?}");
+                thread::sleep(hold);
+            }
+        }
+    }
+}
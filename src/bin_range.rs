@@ -0,0 +1,268 @@
+use std::{
+    fs,
+    str::FromStr,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use crate::{card::CardNetwork, GatewayError, Result};
+
+/// Used when no table has been installed, the ranges are broad so the card type and issuer
+/// details are unknown
+const DEFAULT_BIN_TABLE: &str = "\
+start,end,network,cardtype,country,issuer
+400000,499999,VISA,,,
+222100,272099,MASTERCARD,,,
+510000,559999,MASTERCARD,,,
+500000,509999,MAESTRO,,,
+560000,589999,MAESTRO,,,
+670000,679999,MAESTRO,,,
+340000,349999,AMEX,,,
+370000,379999,AMEX,,,
+601100,601199,DISCOVER,,,
+644000,659999,DISCOVER,,,
+";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CardType {
+    Credit,
+    Debit,
+    Prepaid,
+}
+
+impl FromStr for CardType {
+    type Err = GatewayError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "CREDIT" => Ok(CardType::Credit),
+            "DEBIT" => Ok(CardType::Debit),
+            "PREPAID" => Ok(CardType::Prepaid),
+            invalid => Err(GatewayError::FieldError(format!(
+                "Invalid card type: {invalid}"
+            ))),
+        }
+    }
+}
+
+/// What the BIN of a card tells us about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinInfo {
+    pub network: CardNetwork,
+    /// Only known when the BIN table says so, one range can hold credit, debit and prepaid cards
+    pub card_type: Option<CardType>,
+    /// ISO 3166 alpha-2 code of the issuing country, if known
+    pub country: Option<String>,
+    pub issuer: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct BinRange {
+    /// Number of leading PAN digits the range applies to, between 6 and 8
+    length: usize,
+    start: u32,
+    end: u32,
+    info: BinInfo,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinTable {
+    ranges: Vec<BinRange>,
+}
+
+impl Default for BinTable {
+    fn default() -> Self {
+        Self::parse(DEFAULT_BIN_TABLE).expect("default BIN table is invalid")
+    }
+}
+
+impl BinTable {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| GatewayError::FieldError(format!("Cannot read {path}: {err}")))?;
+        Self::parse(&contents)
+    }
+
+    /// Parses CSV with a header line followed by `start,end,network,cardtype,country,issuer` rows,
+    /// the last three may be left empty when unknown
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for (i, line) in contents.lines().enumerate().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |what: &str| {
+                GatewayError::FieldError(format!("Invalid BIN table line {}: {what}", i + 1))
+            };
+            let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [start, end, network, card_type, country, issuer] = columns[..] else {
+                return Err(invalid("expected 6 columns"));
+            };
+            if !(6..=8).contains(&start.len()) || start.len() != end.len() {
+                return Err(invalid("start and end must both be 6 to 8 digits"));
+            }
+            let start: u32 = start
+                .parse()
+                .map_err(|_| invalid("start is not a number"))?;
+            let end: u32 = end.parse().map_err(|_| invalid("end is not a number"))?;
+            if start > end {
+                return Err(invalid("start is after end"));
+            }
+            ranges.push(BinRange {
+                length: columns[0].len(),
+                start,
+                end,
+                info: BinInfo {
+                    network: network.parse()?,
+                    card_type: (!card_type.is_empty())
+                        .then(|| card_type.parse())
+                        .transpose()?,
+                    country: (!country.is_empty()).then(|| country.into()),
+                    issuer: (!issuer.is_empty()).then(|| issuer.into()),
+                },
+            });
+        }
+        Ok(Self { ranges })
+    }
+
+    /// Finds the most specific range containing the PAN: the longest prefix, then the narrowest range
+    pub fn lookup(&self, pan: &str) -> Result<BinInfo> {
+        self.ranges
+            .iter()
+            .filter(|range| {
+                pan.get(..range.length)
+                    .and_then(|prefix| prefix.parse::<u32>().ok())
+                    .is_some_and(|bin| (range.start..=range.end).contains(&bin))
+            })
+            .min_by_key(|range| (std::cmp::Reverse(range.length), range.end - range.start))
+            .map(|range| range.info.clone())
+            .ok_or(GatewayError::FieldError("Invalid pan: no BIN range matches it".into()))
+    }
+}
+
+static TABLE: LazyLock<RwLock<Arc<BinTable>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(BinTable::default())));
+
+/// Replaces the table used to look up cards, e.g. with one loaded from an issuer's BIN file
+pub fn install(table: BinTable) {
+    *TABLE.write().expect("BIN table poisoned") = Arc::new(table);
+}
+
+pub fn lookup(pan: &str) -> Result<BinInfo> {
+    let table = TABLE.read().expect("BIN table poisoned").clone();
+    table.lookup(pan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(
+        network: CardNetwork,
+        card_type: Option<CardType>,
+        country: Option<&str>,
+        issuer: Option<&str>,
+    ) -> BinInfo {
+        BinInfo {
+            network,
+            card_type,
+            country: country.map(String::from),
+            issuer: issuer.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_default_lookup() {
+        let table = BinTable::default();
+        let tests = [
+            (
+                "4000000000000000",
+                Ok(info(CardNetwork::Visa, None, None, None)),
+            ),
+            (
+                "5100000000000000",
+                Ok(info(CardNetwork::Mastercard, None, None, None)),
+            ),
+            (
+                "2221000000000009",
+                Ok(info(CardNetwork::Mastercard, None, None, None)),
+            ),
+            (
+                "6759000000000000",
+                Ok(info(CardNetwork::Maestro, None, None, None)),
+            ),
+            (
+                "340000000000009",
+                Ok(info(CardNetwork::Amex, None, None, None)),
+            ),
+            (
+                "6011000000000004",
+                Ok(info(CardNetwork::Discover, None, None, None)),
+            ),
+            (
+                "9000000000000000",
+                Err(GatewayError::FieldError("Invalid pan: no BIN range matches it".into())),
+            ),
+            (
+                "40",
+                Err(GatewayError::FieldError("Invalid pan: no BIN range matches it".into())),
+            ),
+        ];
+        for (pan, expected) in tests.into_iter() {
+            assert_eq!(expected, table.lookup(pan), "{pan}");
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let table = BinTable::parse(
+            "start,end,network,cardtype,country,issuer\n\
+             400000,499999,VISA,CREDIT,,\n\
+             475100,475199,VISA,DEBIT,GB,\n\
+             47511234,47511234,VISA,PREPAID,GB,Test Bank PLC\n\
+             47519999,47519999,VISA,,GB,\n",
+        )
+        .unwrap();
+        let tests = [
+            (
+                "4000000000000000",
+                info(CardNetwork::Visa, Some(CardType::Credit), None, None),
+            ),
+            (
+                "4751000000000000",
+                info(CardNetwork::Visa, Some(CardType::Debit), Some("GB"), None),
+            ),
+            (
+                "4751123400000000",
+                info(CardNetwork::Visa, Some(CardType::Prepaid), Some("GB"), Some("Test Bank PLC")),
+            ),
+            (
+                "4751999900000000",
+                info(CardNetwork::Visa, None, Some("GB"), None),
+            ),
+        ];
+        for (pan, expected) in tests.into_iter() {
+            assert_eq!(Ok(expected), table.lookup(pan), "{pan}");
+        }
+        let tests = [
+            (
+                "40000,49999,VISA,CREDIT,,",
+                "Invalid BIN table line 2: start and end must both be 6 to 8 digits",
+            ),
+            (
+                "400000,499999,VISA,CREDIT",
+                "Invalid BIN table line 2: expected 6 columns",
+            ),
+            (
+                "499999,400000,VISA,CREDIT,,",
+                "Invalid BIN table line 2: start is after end",
+            ),
+            ("400000,499999,VISA,CHARGE,,", "Invalid card type: CHARGE"),
+        ];
+        for (line, expected) in tests.into_iter() {
+            assert_eq!(
+                Err(GatewayError::FieldError(expected.into())),
+                BinTable::parse(&format!("header\n{line}"))
+            );
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{clock::Clock, secret::Secret, GatewayError, Result};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CardNetwork {
+    Visa,
+    Mastercard,
+    Maestro,
+    Amex,
+    Discover,
+}
+
+impl CardNetwork {
+    /// PAN lengths issued on this network
+    pub fn pan_lengths(&self) -> &'static [usize] {
+        match self {
+            CardNetwork::Visa => &[13, 16, 19],
+            CardNetwork::Mastercard => &[16],
+            CardNetwork::Maestro => &[12, 13, 14, 15, 16, 17, 18, 19],
+            CardNetwork::Amex => &[15],
+            CardNetwork::Discover => &[16, 17, 18, 19],
+        }
+    }
+
+    pub fn security_code_length(&self) -> usize {
+        match self {
+            CardNetwork::Amex => 4,
+            _ => 3,
+        }
+    }
+}
+
+impl Display for CardNetwork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CardNetwork::Visa => "VISA",
+            CardNetwork::Mastercard => "MASTERCARD",
+            CardNetwork::Maestro => "MAESTRO",
+            CardNetwork::Amex => "AMEX",
+            CardNetwork::Discover => "DISCOVER",
+        })
+    }
+}
+
+impl FromStr for CardNetwork {
+    type Err = GatewayError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "VISA" => Ok(CardNetwork::Visa),
+            "MASTERCARD" => Ok(CardNetwork::Mastercard),
+            "MAESTRO" => Ok(CardNetwork::Maestro),
+            "AMEX" => Ok(CardNetwork::Amex),
+            "DISCOVER" => Ok(CardNetwork::Discover),
+            invalid => Err(GatewayError::FieldError(format!("Invalid network: {invalid}"))),
+        }
+    }
+}
+
+/// A card is identified either by its PAN or by a vault token standing in for it
+#[derive(Debug, Clone, PartialEq)]
+pub enum CardNumber {
+    Pan(Secret),
+    Token(String),
+}
+
+/// Where a device PAN came from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WalletType {
+    ApplePay,
+    GooglePay,
+    /// A network token stored by the merchant rather than held in a device wallet
+    NetworkToken,
+}
+
+impl WalletType {
+    pub fn code(&self) -> &'static str {
+        match self {
+            WalletType::ApplePay => "A",
+            WalletType::GooglePay => "G",
+            WalletType::NetworkToken => "N",
+        }
+    }
+}
+
+impl FromStr for WalletType {
+    type Err = GatewayError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "APPLEPAY" => Ok(WalletType::ApplePay),
+            "GOOGLEPAY" => Ok(WalletType::GooglePay),
+            "NETWORKTOKEN" => Ok(WalletType::NetworkToken),
+            invalid => Err(GatewayError::FieldError(format!("Invalid wallettype: {invalid}"))),
+        }
+    }
+}
+
+/// The last month a card can be used in
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CardExpiry {
+    year: u16,
+    month: u8,
+}
+
+impl CardExpiry {
+    pub fn new(month: u8, year: u16) -> Result<Self> {
+        if !(1..=12).contains(&month) {
+            return Err(GatewayError::FieldError(
+                "Invalid expirydate: month must be between 01 and 12".into(),
+            ));
+        }
+        Ok(Self { year, month })
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    /// Cards are valid until the end of their expiry month
+    pub fn is_expired(&self, clock: &dyn Clock) -> bool {
+        let today = clock.today();
+        (self.year, self.month) < (today.year, today.month)
+    }
+
+    pub fn mmyy(&self) -> String {
+        format!("{:02}{:02}", self.month, self.year % 100)
+    }
+
+    pub fn yymm(&self) -> String {
+        format!("{:02}{:02}", self.year % 100, self.month)
+    }
+
+    pub fn mmyyyy(&self) -> String {
+        format!("{:02}{:04}", self.month, self.year)
+    }
+}
+
+impl Display for CardExpiry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}/{:04}", self.month, self.year)
+    }
+}
+
+impl FromStr for CardExpiry {
+    type Err = GatewayError;
+
+    /// Parses `MM/YY` or `MM/YYYY`
+    fn from_str(s: &str) -> Result<Self> {
+        let format_error =
+            || GatewayError::FieldError("Invalid expirydate: must be MM/YY or MM/YYYY".into());
+        let (month, year) = s.split_once('/').ok_or_else(format_error)?;
+        // checked before parsing, as integer parsing also accepts a leading '+'
+        if month.len() != 2
+            || !(year.len() == 2 || year.len() == 4)
+            || !month.chars().chain(year.chars()).all(|c| c.is_ascii_digit())
+        {
+            return Err(format_error());
+        }
+        let month: u8 = month.parse().map_err(|_| format_error())?;
+        let year: u16 = year.parse().map_err(|_| format_error())?;
+        let year = if year < 100 { 2000 + year } else { year };
+        Self::new(month, year)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::{Date, FixedClock};
+
+    use super::*;
+
+    #[test]
+    fn test_card_expiry() {
+        let tests = [
+            ("12/2024", Ok(("1224", "2412", "122024"))),
+            ("01/30", Ok(("0130", "3001", "012030"))),
+            (
+                "00/30",
+                Err("Invalid expirydate: month must be between 01 and 12"),
+            ),
+            ("1/2030", Err("Invalid expirydate: must be MM/YY or MM/YYYY")),
+            ("122030", Err("Invalid expirydate: must be MM/YY or MM/YYYY")),
+            ("12/203", Err("Invalid expirydate: must be MM/YY or MM/YYYY")),
+            ("+1/2024", Err("Invalid expirydate: must be MM/YY or MM/YYYY")),
+            ("12/+024", Err("Invalid expirydate: must be MM/YY or MM/YYYY")),
+        ];
+        for (s, expected) in tests.into_iter() {
+            let actual = s
+                .parse::<CardExpiry>()
+                .map(|e| (e.mmyy(), e.yymm(), e.mmyyyy()));
+            let expected = expected
+                .map(|(a, b, c)| (a.to_string(), b.to_string(), c.to_string()))
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "{s}");
+        }
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let expiry: CardExpiry = "12/24".parse().unwrap();
+        let tests = [
+            (Date::new(2024, 12, 31), false),
+            (Date::new(2025, 1, 1), true),
+        ];
+        for (today, expected) in tests.into_iter() {
+            assert_eq!(expected, expiry.is_expired(&FixedClock(today)));
+        }
+    }
+}
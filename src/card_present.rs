@@ -0,0 +1,540 @@
+use std::str::FromStr;
+
+use crate::{
+    card::CardExpiry, emv::EmvData, operation_field::regex, payment::luhn,
+    pin_block::EncryptedPinBlock, secret::Secret, GatewayError, Result,
+};
+
+regex!(TERMINAL_ID_REGEX, "^[A-Z0-9]{8}$");
+
+/// The longest track 2 allowed by ISO 7813, not counting the sentinels
+const TRACK2_MAX_LENGTH: usize = 37;
+
+fn invalid(field: &str, reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid {field}: {reason}"))
+}
+
+/// How the card details were read by the terminal
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PosEntryMode {
+    Chip,
+    Contactless,
+    Swipe,
+    Keyed,
+}
+
+impl PosEntryMode {
+    /// The ISO 8583 POS entry mode code
+    pub fn code(&self) -> &'static str {
+        match self {
+            PosEntryMode::Chip => "05",
+            PosEntryMode::Contactless => "07",
+            PosEntryMode::Swipe => "90",
+            PosEntryMode::Keyed => "01",
+        }
+    }
+
+    fn capability(&self) -> char {
+        match self {
+            PosEntryMode::Chip => 'C',
+            PosEntryMode::Contactless => 'L',
+            PosEntryMode::Swipe => 'S',
+            PosEntryMode::Keyed => 'K',
+        }
+    }
+}
+
+impl FromStr for PosEntryMode {
+    type Err = GatewayError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "CHIP" => Ok(PosEntryMode::Chip),
+            "CONTACTLESS" => Ok(PosEntryMode::Contactless),
+            "SWIPE" => Ok(PosEntryMode::Swipe),
+            "KEYED" => Ok(PosEntryMode::Keyed),
+            invalid => Err(GatewayError::FieldError(format!(
+                "Invalid posentrymode: {invalid}"
+            ))),
+        }
+    }
+}
+
+/// How the cardholder proved they are who they say they are
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Cvm {
+    OnlinePin,
+    OfflinePin,
+    Signature,
+    /// Verified on the cardholder's own device, e.g. a phone's biometrics
+    ConsumerDevice,
+    NoCvm,
+}
+
+impl Cvm {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Cvm::OnlinePin => "1",
+            Cvm::OfflinePin => "2",
+            Cvm::Signature => "3",
+            Cvm::ConsumerDevice => "4",
+            Cvm::NoCvm => "5",
+        }
+    }
+}
+
+impl FromStr for Cvm {
+    type Err = GatewayError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ONLINEPIN" => Ok(Cvm::OnlinePin),
+            "OFFLINEPIN" => Ok(Cvm::OfflinePin),
+            "SIGNATURE" => Ok(Cvm::Signature),
+            "CONSUMERDEVICE" => Ok(Cvm::ConsumerDevice),
+            "NOCVM" => Ok(Cvm::NoCvm),
+            invalid => Err(GatewayError::FieldError(format!("Invalid cvm: {invalid}"))),
+        }
+    }
+}
+
+/// What a terminal can read cards with
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerminalCapabilities {
+    pub entry_modes: Vec<PosEntryMode>,
+    pub pin_entry: bool,
+}
+
+impl TerminalCapabilities {
+    /// One letter per entry mode (`C`hip, contact`L`ess, `S`wipe, `K`eyed), then `P` if PINs can be entered
+    pub fn code(&self) -> String {
+        let mut code: String = self
+            .entry_modes
+            .iter()
+            .map(|mode| mode.capability())
+            .collect();
+        if self.pin_entry {
+            code.push('P');
+        }
+        code
+    }
+}
+
+impl FromStr for TerminalCapabilities {
+    type Err = GatewayError;
+
+    /// Parses the letters given by [`TerminalCapabilities::code`]
+    fn from_str(s: &str) -> Result<Self> {
+        let (modes, pin_entry) = match s.strip_suffix('P') {
+            Some(modes) => (modes, true),
+            None => (s, false),
+        };
+        let entry_modes = modes
+            .chars()
+            .map(|letter| match letter {
+                'C' => Ok(PosEntryMode::Chip),
+                'L' => Ok(PosEntryMode::Contactless),
+                'S' => Ok(PosEntryMode::Swipe),
+                'K' => Ok(PosEntryMode::Keyed),
+                _ => Err(invalid(
+                    "terminalcapabilities",
+                    &format!("{letter} is not an entry mode"),
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if entry_modes.is_empty() {
+            return Err(invalid("terminalcapabilities", "must have an entry mode"));
+        }
+        Ok(Self {
+            entry_modes,
+            pin_entry,
+        })
+    }
+}
+
+/// The track 2 data read from a magnetic stripe, or its equivalent read from a chip
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track2 {
+    pub pan: Secret,
+    pub expiry_date: CardExpiry,
+    pub service_code: String,
+    /// The whole track without sentinels, as sent to the bank
+    pub data: Secret,
+}
+
+impl Track2 {
+    /// Whether the card has a chip, in which case it should only be swiped as a fallback
+    pub fn has_chip(&self) -> bool {
+        self.service_code.starts_with(['2', '6'])
+    }
+}
+
+impl FromStr for Track2 {
+    type Err = GatewayError;
+
+    /// Parses `PAN=YYMM` followed by the service code and discretionary data, sentinels are optional
+    fn from_str(s: &str) -> Result<Self> {
+        let data = s.strip_prefix(';').unwrap_or(s);
+        let data = data.strip_suffix('?').unwrap_or(data);
+        if data.len() > TRACK2_MAX_LENGTH {
+            return Err(invalid("track2", "too long"));
+        }
+        let Some((pan, rest)) = data.split_once(['=', 'D']) else {
+            return Err(invalid("track2", "missing separator"));
+        };
+        if !(12..=19).contains(&pan.len()) || !pan.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid("track2", "pan must be 12 to 19 digits"));
+        }
+        if !luhn(pan) {
+            return Err(invalid("track2", "pan fails Luhn check"));
+        }
+        if rest.len() < 7 || !rest.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid(
+                "track2",
+                "expiry date and service code must follow the separator",
+            ));
+        }
+        let (year, month) = (&rest[..2], &rest[2..4]);
+        let expiry_date = CardExpiry::new(
+            month.parse().expect("checked digits"),
+            2000 + year.parse::<u16>().expect("checked digits"),
+        )?;
+        let service_code = &rest[4..7];
+        let mut digits = service_code.chars();
+        let valid_service_code = matches!(digits.next(), Some('1' | '2' | '5' | '6' | '7' | '9'))
+            && matches!(digits.next(), Some('0' | '2' | '4'))
+            && matches!(digits.next(), Some('0'..='7'));
+        if !valid_service_code {
+            return Err(invalid(
+                "track2",
+                &format!("{service_code} is not a valid service code"),
+            ));
+        }
+        Ok(Self {
+            pan: pan.into(),
+            expiry_date,
+            service_code: service_code.into(),
+            data: data.replace('D', "=").as_str().into(),
+        })
+    }
+}
+
+/// Details of a card-present transaction, read at a terminal
+#[derive(Debug, Clone, PartialEq)]
+pub struct CardPresent {
+    pub entry_mode: PosEntryMode,
+    /// Only absent when the card details were keyed
+    pub track2: Option<Track2>,
+    pub terminal_id: String,
+    pub terminal_capabilities: TerminalCapabilities,
+    pub cvm: Cvm,
+    /// The chip's EMV data, required when the chip was read by contact
+    pub emv: Option<EmvData>,
+    /// The encrypted PIN, only present when the CVM is online PIN
+    pub pin_block: Option<EncryptedPinBlock>,
+}
+
+impl CardPresent {
+    /// Checks the entry mode and CVM are possible on the terminal
+    pub fn new(
+        entry_mode: PosEntryMode,
+        track2: Option<Track2>,
+        terminal_id: &str,
+        terminal_capabilities: TerminalCapabilities,
+        cvm: Cvm,
+        emv: Option<EmvData>,
+    ) -> Result<Self> {
+        if !TERMINAL_ID_REGEX.is_match(terminal_id) {
+            return Err(invalid(
+                "terminalid",
+                "must be 8 upper case letters or digits",
+            ));
+        }
+        if !terminal_capabilities.entry_modes.contains(&entry_mode) {
+            return Err(invalid(
+                "posentrymode",
+                &format!("terminal {terminal_id} cannot read cards by {entry_mode:?}"),
+            ));
+        }
+        match (entry_mode, &track2) {
+            (PosEntryMode::Keyed, _) | (_, Some(_)) => (),
+            (_, None) => {
+                return Err(invalid(
+                    "track2",
+                    &format!("required when the card is read by {entry_mode:?}"),
+                ))
+            }
+        }
+        if matches!(cvm, Cvm::OnlinePin | Cvm::OfflinePin) && !terminal_capabilities.pin_entry {
+            return Err(invalid(
+                "cvm",
+                &format!("terminal {terminal_id} cannot accept PINs"),
+            ));
+        }
+        match (entry_mode, &emv) {
+            (_, Some(emv)) => emv.validate()?,
+            (PosEntryMode::Chip, None) => {
+                return Err(invalid("emvdata", "required when the card is read by Chip"))
+            }
+            _ => (),
+        }
+        Ok(Self {
+            entry_mode,
+            track2,
+            terminal_id: terminal_id.into(),
+            terminal_capabilities,
+            cvm,
+            emv,
+            pin_block: None,
+        })
+    }
+
+    /// Attaches the PIN the cardholder entered for online verification
+    pub fn with_pin_block(self, pin_block: EncryptedPinBlock) -> Result<Self> {
+        if self.cvm != Cvm::OnlinePin {
+            return Err(invalid(
+                "pinblock",
+                &format!("not expected when the CVM is {:?}", self.cvm),
+            ));
+        }
+        Ok(Self {
+            pin_block: Some(pin_block),
+            ..self
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{emv::TEST_EMV_DATA, pin_block::PinBlockFormat};
+
+    use super::*;
+
+    #[test]
+    fn test_track2() {
+        let tests = [
+            (
+                ";4111111111111111=24121011234567890?",
+                Ok(("4111111111111111", "122024", "101")),
+            ),
+            (
+                "4111111111111111D2412201",
+                Ok(("4111111111111111", "122024", "201")),
+            ),
+            ("4111111111111111", Err("missing separator")),
+            (
+                "4111111111111112=24121011234567890",
+                Err("pan fails Luhn check"),
+            ),
+            ("4111=24121011234567890", Err("pan must be 12 to 19 digits")),
+            (
+                "4111111111111111=241210",
+                Err("expiry date and service code must follow the separator"),
+            ),
+            (
+                "4111111111111111=2413101",
+                Err("month must be between 01 and 12"),
+            ),
+            (
+                "4111111111111111=2412301",
+                Err("301 is not a valid service code"),
+            ),
+            ("4111111111111111=24121011234567890123456", Err("too long")),
+        ];
+        for (i, (track2, expected)) in tests.into_iter().enumerate() {
+            let actual = track2.parse::<Track2>().map(|track2| {
+                (
+                    track2.pan.reveal().to_string(),
+                    track2.expiry_date.mmyyyy(),
+                    track2.service_code,
+                )
+            });
+            let expected = expected
+                .map(|(pan, expiry, service_code)| {
+                    (
+                        pan.to_string(),
+                        expiry.to_string(),
+                        service_code.to_string(),
+                    )
+                })
+                .map_err(|err| match err {
+                    "month must be between 01 and 12" => {
+                        GatewayError::FieldError(format!("Invalid expirydate: {err}"))
+                    }
+                    _ => GatewayError::FieldError(format!("Invalid track2: {err}")),
+                });
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+        let track2: Track2 = "4111111111111111D2412201".parse().unwrap();
+        assert!(track2.has_chip());
+        assert_eq!("4111111111111111=2412201", track2.data.reveal());
+    }
+
+    #[test]
+    fn test_terminal_capabilities() {
+        let tests = [
+            ("CLP", Ok((vec![PosEntryMode::Chip, PosEntryMode::Contactless], true))),
+            ("S", Ok((vec![PosEntryMode::Swipe], false))),
+            ("P", Err("must have an entry mode")),
+            ("CX", Err("X is not an entry mode")),
+        ];
+        for (i, (code, expected)) in tests.into_iter().enumerate() {
+            let actual = code
+                .parse::<TerminalCapabilities>()
+                .map(|capabilities| (capabilities.entry_modes, capabilities.pin_entry));
+            let expected = expected.map_err(|err| {
+                GatewayError::FieldError(format!("Invalid terminalcapabilities: {err}"))
+            });
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+            if let Ok(capabilities) = code.parse::<TerminalCapabilities>() {
+                assert_eq!(code, capabilities.code(), "Case number {}", i + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_card_present() {
+        let emv = EmvData::from_hex(TEST_EMV_DATA).unwrap();
+        let track2: Track2 = "4111111111111111=24122011234567890".parse().unwrap();
+        let capabilities = TerminalCapabilities {
+            entry_modes: vec![PosEntryMode::Chip, PosEntryMode::Contactless],
+            pin_entry: true,
+        };
+        let no_pin = TerminalCapabilities {
+            pin_entry: false,
+            ..capabilities.clone()
+        };
+        let tests = [
+            (
+                (
+                    PosEntryMode::Chip,
+                    Some(&track2),
+                    "TERM0001",
+                    &capabilities,
+                    Cvm::OfflinePin,
+                ),
+                Ok(()),
+            ),
+            (
+                (
+                    PosEntryMode::Contactless,
+                    Some(&track2),
+                    "TERM0001",
+                    &no_pin,
+                    Cvm::NoCvm,
+                ),
+                Ok(()),
+            ),
+            (
+                (
+                    PosEntryMode::Swipe,
+                    Some(&track2),
+                    "TERM0001",
+                    &capabilities,
+                    Cvm::Signature,
+                ),
+                Err("Invalid posentrymode: terminal TERM0001 cannot read cards by Swipe"),
+            ),
+            (
+                (
+                    PosEntryMode::Chip,
+                    None,
+                    "TERM0001",
+                    &capabilities,
+                    Cvm::OfflinePin,
+                ),
+                Err("Invalid track2: required when the card is read by Chip"),
+            ),
+            (
+                (
+                    PosEntryMode::Chip,
+                    Some(&track2),
+                    "TERM0001",
+                    &no_pin,
+                    Cvm::OnlinePin,
+                ),
+                Err("Invalid cvm: terminal TERM0001 cannot accept PINs"),
+            ),
+            (
+                (
+                    PosEntryMode::Chip,
+                    Some(&track2),
+                    "term1",
+                    &capabilities,
+                    Cvm::OfflinePin,
+                ),
+                Err("Invalid terminalid: must be 8 upper case letters or digits"),
+            ),
+        ];
+        for (i, ((entry_mode, track2, terminal_id, capabilities, cvm), expected)) in
+            tests.into_iter().enumerate()
+        {
+            let emv = (entry_mode == PosEntryMode::Chip).then(|| emv.clone());
+            let actual = CardPresent::new(
+                entry_mode,
+                track2.cloned(),
+                terminal_id,
+                capabilities.clone(),
+                cvm,
+                emv,
+            )
+            .map(|_| ());
+            let expected = expected.map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+        assert_eq!("CLP", capabilities.code());
+
+        let tests = [
+            (
+                None,
+                "Invalid emvdata: required when the card is read by Chip",
+            ),
+            (
+                Some(EmvData::from_hex(&TEST_EMV_DATA[..22]).unwrap()),
+                "Invalid emvdata: missing tag 9F27",
+            ),
+        ];
+        for (emv, expected) in tests.into_iter() {
+            assert_eq!(
+                Err(GatewayError::FieldError(expected.into())),
+                CardPresent::new(
+                    PosEntryMode::Chip,
+                    Some(track2.clone()),
+                    "TERM0001",
+                    capabilities.clone(),
+                    Cvm::OfflinePin,
+                    emv,
+                )
+            );
+        }
+
+        let pin_block = EncryptedPinBlock {
+            format: PinBlockFormat::Iso0,
+            key_name: "tdes".into(),
+            block: vec![0; 8],
+        };
+        let tests = [
+            (Cvm::OnlinePin, Ok(())),
+            (
+                Cvm::OfflinePin,
+                Err("Invalid pinblock: not expected when the CVM is OfflinePin"),
+            ),
+        ];
+        for (cvm, expected) in tests.into_iter() {
+            let card_present = CardPresent::new(
+                PosEntryMode::Chip,
+                Some(track2.clone()),
+                "TERM0001",
+                capabilities.clone(),
+                cvm,
+                Some(emv.clone()),
+            )
+            .unwrap();
+            let expected = expected.map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(
+                expected,
+                card_present.with_pin_block(pin_block.clone()).map(|_| ())
+            );
+        }
+    }
+}
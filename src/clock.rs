@@ -0,0 +1,124 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    pub fn new(year: u16, month: u8, day: u8) -> Self {
+        Self { year, month, day }
+    }
+
+    /// The (UTC) date `days` days after 1970-01-01
+    pub fn from_days_since_epoch(days: i64) -> Self {
+        // Howard Hinnant's civil_from_days
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        Self::new(year as u16, month as u8, day as u8)
+    }
+
+    /// How many days the date is after 1970-01-01, the inverse of `from_days_since_epoch`
+    pub fn days_since_epoch(&self) -> i64 {
+        // Howard Hinnant's days_from_civil
+        let (month, day) = (i64::from(self.month), i64::from(self.day));
+        let year = i64::from(self.year) - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+    }
+}
+
+/// Source of the current date, so anything date dependent can be tested
+pub trait Clock {
+    fn today(&self) -> Date;
+
+    /// Seconds since 1970-01-01 UTC, clocks that only know the date give its midnight
+    fn now(&self) -> u64 {
+        (self.today().days_since_epoch() * 86400) as u64
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn today(&self) -> Date {
+        Date::from_days_since_epoch((self.now() / 86400) as i64)
+    }
+
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+}
+
+/// Always returns the same date
+pub struct FixedClock(pub Date);
+
+impl Clock for FixedClock {
+    fn today(&self) -> Date {
+        self.0
+    }
+}
+
+/// Always returns the same time, in seconds since 1970-01-01 UTC
+pub struct FixedTime(pub u64);
+
+impl Clock for FixedTime {
+    fn today(&self) -> Date {
+        Date::from_days_since_epoch((self.0 / 86400) as i64)
+    }
+
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+pub fn test_clock() -> FixedClock {
+    FixedClock(Date::new(2024, 6, 15))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_days_since_epoch() {
+        let tests = [
+            (0, Date::new(1970, 1, 1)),
+            (59, Date::new(1970, 3, 1)),
+            (11016, Date::new(2000, 2, 29)),
+            (19889, Date::new(2024, 6, 15)),
+            (20745, Date::new(2026, 10, 19)),
+        ];
+        for (days, expected) in tests.into_iter() {
+            assert_eq!(expected, Date::from_days_since_epoch(days));
+            assert_eq!(days, expected.days_since_epoch());
+        }
+    }
+
+    #[test]
+    fn test_now() {
+        assert_eq!(1718409600, test_clock().now());
+        let clock = FixedTime(1718409600 + 86399);
+        assert_eq!(Date::new(2024, 6, 15), clock.today());
+        assert_eq!(
+            Date::new(2024, 6, 16),
+            FixedTime(1718409600 + 86400).today()
+        );
+    }
+}
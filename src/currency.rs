@@ -2,18 +2,85 @@ use std::{fmt::Display, str::FromStr};
 
 use crate::GatewayError;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Currency {
-    GBP,
-    USD,
+macro_rules! currencies {
+    ($($code:ident $numeric:literal $exponent:literal),+ $(,)?) => {
+        /// The ISO 4217 currencies, excluding precious metals and other units without minor units
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+        pub enum Currency {
+            $($code),+
+        }
+
+        pub const CURRENCIES: &[Currency] = &[$(Currency::$code),+];
+
+        impl Currency {
+            /// The three letter alphabetic code
+            pub fn code(&self) -> &'static str {
+                match self {
+                    $(Currency::$code => stringify!($code)),+
+                }
+            }
+
+            /// The three digit numeric code ISO 8583 hosts and EMV use
+            pub fn numeric(&self) -> u16 {
+                match self {
+                    $(Currency::$code => $numeric),+
+                }
+            }
+
+            /// How many decimal places the minor unit has, e.g. 2 for pence and 0 for yen
+            pub fn exponent(&self) -> u32 {
+                match self {
+                    $(Currency::$code => $exponent),+
+                }
+            }
+        }
+    };
+}
+
+currencies! {
+    AED 784 2, AFN 971 2, ALL 8 2, AMD 51 2, AOA 973 2, ARS 32 2, AUD 36 2, AWG 533 2,
+    AZN 944 2, BAM 977 2, BBD 52 2, BDT 50 2, BGN 975 2, BHD 48 3, BIF 108 0, BMD 60 2,
+    BND 96 2, BOB 68 2, BOV 984 2, BRL 986 2, BSD 44 2, BTN 64 2, BWP 72 2, BYN 933 2,
+    BZD 84 2, CAD 124 2, CDF 976 2, CHE 947 2, CHF 756 2, CHW 948 2, CLF 990 4, CLP 152 0,
+    CNY 156 2, COP 170 2, COU 970 2, CRC 188 2, CUP 192 2, CVE 132 2, CZK 203 2, DJF 262 0,
+    DKK 208 2, DOP 214 2, DZD 12 2, EGP 818 2, ERN 232 2, ETB 230 2, EUR 978 2, FJD 242 2,
+    FKP 238 2, GBP 826 2, GEL 981 2, GHS 936 2, GIP 292 2, GMD 270 2, GNF 324 0, GTQ 320 2,
+    GYD 328 2, HKD 344 2, HNL 340 2, HTG 332 2, HUF 348 2, IDR 360 2, ILS 376 2, INR 356 2,
+    IQD 368 3, IRR 364 2, ISK 352 0, JMD 388 2, JOD 400 3, JPY 392 0, KES 404 2, KGS 417 2,
+    KHR 116 2, KMF 174 0, KPW 408 2, KRW 410 0, KWD 414 3, KYD 136 2, KZT 398 2, LAK 418 2,
+    LBP 422 2, LKR 144 2, LRD 430 2, LSL 426 2, LYD 434 3, MAD 504 2, MDL 498 2, MGA 969 2,
+    MKD 807 2, MMK 104 2, MNT 496 2, MOP 446 2, MRU 929 2, MUR 480 2, MVR 462 2, MWK 454 2,
+    MXN 484 2, MXV 979 2, MYR 458 2, MZN 943 2, NAD 516 2, NGN 566 2, NIO 558 2, NOK 578 2,
+    NPR 524 2, NZD 554 2, OMR 512 3, PAB 590 2, PEN 604 2, PGK 598 2, PHP 608 2, PKR 586 2,
+    PLN 985 2, PYG 600 0, QAR 634 2, RON 946 2, RSD 941 2, RUB 643 2, RWF 646 0, SAR 682 2,
+    SBD 90 2, SCR 690 2, SDG 938 2, SEK 752 2, SGD 702 2, SHP 654 2, SLE 925 2, SOS 706 2,
+    SRD 968 2, SSP 728 2, STN 930 2, SVC 222 2, SYP 760 2, SZL 748 2, THB 764 2, TJS 972 2,
+    TMT 934 2, TND 788 3, TOP 776 2, TRY 949 2, TTD 780 2, TWD 901 2, TZS 834 2, UAH 980 2,
+    UGX 800 0, USD 840 2, USN 997 2, UYI 940 0, UYU 858 2, UYW 927 4, UZS 860 2, VED 926 2,
+    VES 928 2, VND 704 0, VUV 548 0, WST 882 2, XAF 950 0, XCD 951 2, XCG 532 2, XOF 952 0,
+    XPF 953 0, YER 886 2, ZAR 710 2, ZMW 967 2, ZWG 924 2,
+}
+
+impl Currency {
+    /// The numeric code zero padded to three digits, as ISO 8583 field 49 carries it
+    pub fn numeric_code(&self) -> String {
+        format!("{:03}", self.numeric())
+    }
+
+    pub fn from_numeric(numeric: u16) -> Result<Self, GatewayError> {
+        CURRENCIES
+            .iter()
+            .find(|currency| currency.numeric() == numeric)
+            .copied()
+            .ok_or(GatewayError::FieldError(format!(
+                "Invalid currency: {numeric:03}"
+            )))
+    }
 }
 
 impl Display for Currency {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(match self {
-            Currency::GBP => "GBP",
-            Currency::USD => "USD",
-        })
+        f.write_str(self.code())
     }
 }
 
@@ -21,10 +88,47 @@ impl FromStr for Currency {
     type Err = GatewayError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "GBP" => Ok(Self::GBP),
-            "USD" => Ok(Self::USD),
-            invalid => Err(GatewayError::FieldError(format!("Invalid currency: {invalid}")))
+        CURRENCIES
+            .iter()
+            .find(|currency| currency.code() == s)
+            .copied()
+            .ok_or(GatewayError::FieldError(format!("Invalid currency: {s}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_currency() {
+        let tests = [
+            ("GBP", Ok((Currency::GBP, "826", 2))),
+            ("USD", Ok((Currency::USD, "840", 2))),
+            ("JPY", Ok((Currency::JPY, "392", 0))),
+            ("BHD", Ok((Currency::BHD, "048", 3))),
+            ("ALL", Ok((Currency::ALL, "008", 2))),
+            ("CLF", Ok((Currency::CLF, "990", 4))),
+            ("gbp", Err("Invalid currency: gbp")),
+            ("XAU", Err("Invalid currency: XAU")),
+        ];
+        for (i, (code, expected)) in tests.into_iter().enumerate() {
+            let actual = code
+                .parse::<Currency>()
+                .map(|currency| (currency, currency.numeric_code(), currency.exponent()));
+            let expected = expected
+                .map(|(currency, numeric, exponent)| (currency, numeric.to_string(), exponent))
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+        assert_eq!(Ok(Currency::EUR), Currency::from_numeric(978));
+        assert_eq!(
+            Err(GatewayError::FieldError("Invalid currency: 999".into())),
+            Currency::from_numeric(999)
+        );
+        for currency in CURRENCIES {
+            assert_eq!(Ok(*currency), currency.code().parse());
+            assert_eq!(Ok(*currency), Currency::from_numeric(currency.numeric()));
         }
     }
-}
\ No newline at end of file
+}
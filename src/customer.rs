@@ -0,0 +1,261 @@
+use crate::{operation_field::regex, GatewayError, Result};
+
+regex!(EMAIL_REGEX, r"^[^@\s]+@[^@\s]+\.[^@\s]+$");
+regex!(PHONE_REGEX, r"^\+?[0-9]{6,15}$");
+regex!(
+    GB_POSTCODE_REGEX,
+    "^[A-Z]{1,2}[0-9][A-Z0-9]? ?[0-9][A-Z]{2}$"
+);
+regex!(US_POSTCODE_REGEX, "^[0-9]{5}(-[0-9]{4})?$");
+regex!(CA_POSTCODE_REGEX, "^[A-Z][0-9][A-Z] ?[0-9][A-Z][0-9]$");
+regex!(IE_POSTCODE_REGEX, "^([A-Z][0-9]{2}|D6W) ?[A-Z0-9]{4}$");
+regex!(NL_POSTCODE_REGEX, "^[0-9]{4} ?[A-Z]{2}$");
+regex!(FIVE_DIGIT_POSTCODE_REGEX, "^[0-9]{5}$");
+regex!(FOUR_DIGIT_POSTCODE_REGEX, "^[0-9]{4}$");
+// countries without a layout above only get a loose check
+regex!(POSTCODE_REGEX, "^[A-Z0-9][A-Z0-9 -]{0,9}$");
+
+/// The ISO 3166-1 alpha-2 country codes
+const COUNTRIES: &str = "\
+AD AE AF AG AI AL AM AO AQ AR AS AT AU AW AX AZ BA BB BD BE BF BG BH BI BJ BL BM BN BO BQ BR BS
+BT BV BW BY BZ CA CC CD CF CG CH CI CK CL CM CN CO CR CU CV CW CX CY CZ DE DJ DK DM DO DZ EC EE
+EG EH ER ES ET FI FJ FK FM FO FR GA GB GD GE GF GG GH GI GL GM GN GP GQ GR GS GT GU GW GY HK HM
+HN HR HT HU ID IE IL IM IN IO IQ IR IS IT JE JM JO JP KE KG KH KI KM KN KP KR KW KY KZ LA LB LC
+LI LK LR LS LT LU LV LY MA MC MD ME MF MG MH MK ML MM MN MO MP MQ MR MS MT MU MV MW MX MY MZ NA
+NC NE NF NG NI NL NO NP NR NU NZ OM PA PE PF PG PH PK PL PM PN PR PS PT PW PY QA RE RO RS RU RW
+SA SB SC SD SE SG SH SI SJ SK SL SM SN SO SR SS ST SV SX SY SZ TC TD TF TG TH TJ TK TL TM TN TO
+TR TT TV TW TZ UA UG UM US UY UZ VA VC VE VG VI VN VU WF WS YE YT ZA ZM ZW";
+
+fn invalid(field: &str, reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid {field}: {reason}"))
+}
+
+fn postcode_regex(country: &str) -> &'static regex::Regex {
+    match country {
+        "GB" | "GG" | "JE" | "IM" => &GB_POSTCODE_REGEX,
+        "US" => &US_POSTCODE_REGEX,
+        "CA" => &CA_POSTCODE_REGEX,
+        "IE" => &IE_POSTCODE_REGEX,
+        "NL" => &NL_POSTCODE_REGEX,
+        "DE" | "FR" | "ES" | "IT" => &FIVE_DIGIT_POSTCODE_REGEX,
+        "AU" | "AT" | "BE" | "CH" | "DK" | "NO" => &FOUR_DIGIT_POSTCODE_REGEX,
+        _ => &POSTCODE_REGEX,
+    }
+}
+
+fn text(field: &str, value: &str, max_length: usize) -> Result<String> {
+    let value = value.trim();
+    if value.chars().count() > max_length {
+        return Err(invalid(
+            field,
+            &format!("must be at most {max_length} characters"),
+        ));
+    }
+    Ok(value.into())
+}
+
+/// A postal address, used for address verification (AVS) when it is the billing address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    /// The house name or number
+    pub premises: String,
+    pub street: String,
+    pub town: String,
+    pub county: String,
+    /// Upper case, as the country formats it
+    pub postcode: String,
+    /// The ISO 3166-1 alpha-2 code
+    pub country: String,
+}
+
+impl Address {
+    /// Validates the postcode against the country's layout where the gateway knows it
+    pub fn new(
+        premises: &str,
+        street: Option<&str>,
+        town: Option<&str>,
+        county: Option<&str>,
+        postcode: &str,
+        country: &str,
+    ) -> Result<Self> {
+        let premises = text("premise", premises, 50)?;
+        if premises.is_empty() {
+            return Err(invalid("premise", "must not be empty"));
+        }
+        let country = country.trim().to_uppercase();
+        if country.len() != 2 || !COUNTRIES.split_whitespace().any(|code| code == country) {
+            return Err(invalid(
+                "countryiso2a",
+                &format!("{country} is not an ISO 3166 country"),
+            ));
+        }
+        let postcode = postcode.trim().to_uppercase();
+        if !postcode_regex(&country).is_match(&postcode) {
+            return Err(invalid(
+                "postcode",
+                &format!("{postcode} is not a valid {country} postcode"),
+            ));
+        }
+        Ok(Self {
+            premises,
+            street: text("street", street.unwrap_or_default(), 100)?,
+            town: text("town", town.unwrap_or_default(), 50)?,
+            county: text("county", county.unwrap_or_default(), 50)?,
+            postcode,
+            country,
+        })
+    }
+
+    /// The premises and street, AVS only checks the start of the address
+    pub fn first_line(&self) -> String {
+        [self.premises.as_str(), self.street.as_str()]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Who is paying and where the goods are going
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Customer {
+    pub email: Option<String>,
+    /// Digits with an optional leading `+`, spaces are removed
+    pub phone: Option<String>,
+    pub billing_address: Option<Address>,
+    pub shipping_address: Option<Address>,
+}
+
+impl Customer {
+    pub fn new(email: Option<&str>, phone: Option<&str>) -> Result<Self> {
+        let email = email.map(str::trim);
+        if let Some(email) = email {
+            if email.len() > 255 || !EMAIL_REGEX.is_match(email) {
+                return Err(invalid(
+                    "email",
+                    &format!("{email} is not an email address"),
+                ));
+            }
+        }
+        let phone = phone.map(|phone| phone.replace(' ', ""));
+        if let Some(phone) = &phone {
+            if !PHONE_REGEX.is_match(phone) {
+                return Err(invalid("telephone", "must be 6 to 15 digits"));
+            }
+        }
+        Ok(Self {
+            email: email.map(String::from),
+            phone,
+            billing_address: None,
+            shipping_address: None,
+        })
+    }
+
+    pub fn with_billing_address(self, address: Address) -> Self {
+        Self {
+            billing_address: Some(address),
+            ..self
+        }
+    }
+
+    pub fn with_shipping_address(self, address: Address) -> Self {
+        Self {
+            shipping_address: Some(address),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+pub fn test_address() -> Address {
+    Address::new(
+        "10",
+        Some("Downing Street"),
+        Some("London"),
+        None,
+        "sw1a 2aa",
+        "gb",
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_validation() {
+        let tests = [
+            (("10", "SW1A 2AA", "GB"), Ok(("SW1A 2AA", "GB"))),
+            (("10", "m1 1ae", "GB"), Ok(("M1 1AE", "GB"))),
+            (("1600", "20500-0003", "US"), Ok(("20500-0003", "US"))),
+            (("24", "K1A 0B1", "CA"), Ok(("K1A 0B1", "CA"))),
+            (("1", "D6W 1234", "IE"), Ok(("D6W 1234", "IE"))),
+            (("7", "1012 JS", "NL"), Ok(("1012 JS", "NL"))),
+            (("11", "10117", "DE"), Ok(("10117", "DE"))),
+            (("3", "100-0001", "JP"), Ok(("100-0001", "JP"))),
+            (
+                ("10", "SW1A", "GB"),
+                Err("Invalid postcode: SW1A is not a valid GB postcode"),
+            ),
+            (
+                ("1600", "2050", "US"),
+                Err("Invalid postcode: 2050 is not a valid US postcode"),
+            ),
+            (
+                ("10", "SW1A 2AA", "UK"),
+                Err("Invalid countryiso2a: UK is not an ISO 3166 country"),
+            ),
+            (
+                ("10", "SW1A 2AA", "GBR"),
+                Err("Invalid countryiso2a: GBR is not an ISO 3166 country"),
+            ),
+            (
+                ("", "SW1A 2AA", "GB"),
+                Err("Invalid premise: must not be empty"),
+            ),
+        ];
+        for (i, ((premises, postcode, country), expected)) in tests.into_iter().enumerate() {
+            let actual = Address::new(premises, None, None, None, postcode, country)
+                .map(|address| (address.postcode, address.country));
+            let expected = expected
+                .map(|(postcode, country)| (postcode.to_string(), country.to_string()))
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+        assert_eq!("10 Downing Street", test_address().first_line());
+        assert_eq!(
+            Err(GatewayError::FieldError(
+                "Invalid town: must be at most 50 characters".into()
+            )),
+            Address::new("1", None, Some(&"x".repeat(51)), None, "SW1A 2AA", "GB")
+        );
+    }
+
+    #[test]
+    fn test_customer() {
+        let tests = [
+            (
+                (Some("ben@example.com"), Some("+44 20 7946 0000")),
+                Ok((Some("ben@example.com"), Some("+442079460000"))),
+            ),
+            ((None, None), Ok((None, None))),
+            (
+                (Some("ben.example.com"), None),
+                Err("Invalid email: ben.example.com is not an email address"),
+            ),
+            (
+                (None, Some("call me")),
+                Err("Invalid telephone: must be 6 to 15 digits"),
+            ),
+        ];
+        for (i, ((email, phone), expected)) in tests.into_iter().enumerate() {
+            let actual =
+                Customer::new(email, phone).map(|customer| (customer.email, customer.phone));
+            let expected = expected
+                .map(|(email, phone)| (email.map(String::from), phone.map(String::from)))
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use std::str::FromStr;
+
+use des::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Des,
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    bank::Bank,
+    card_present::Track2,
+    key_store::{KeyAlgorithm, KeyStore, SoftwareKeyStore},
+    pin_block::{EncryptedPinBlock, PinBlockFormat},
+    secret::Secret,
+    GatewayError, Result,
+};
+
+/// Name the derived terminal key is held under while it is used
+const TERMINAL_KEY: &str = "dukpt";
+
+/// Masks the halves of a TDES key to make the second key of a key pair
+const TDES_KEY_MASK: [u8; 16] = [
+    0xC0, 0xC0, 0xC0, 0xC0, 0, 0, 0, 0, 0xC0, 0xC0, 0xC0, 0xC0, 0, 0, 0, 0,
+];
+
+/// The bits of a TDES KSN holding the transaction counter
+const TDES_COUNTER_BITS: u32 = 21;
+
+/// What a DUKPT working key is derived for
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum KeyUsage {
+    Pin,
+    Data,
+}
+
+impl KeyUsage {
+    /// XORed with a TDES transaction key to get the working key
+    fn tdes_variant(&self) -> [u8; 16] {
+        let mut variant = [0; 16];
+        let byte = match self {
+            KeyUsage::Pin => 7,
+            KeyUsage::Data => 5,
+        };
+        variant[byte] = 0xFF;
+        variant[byte + 8] = 0xFF;
+        variant
+    }
+
+    /// The X9.24-3 key usage indicator
+    fn aes_indicator(&self) -> u16 {
+        match self {
+            KeyUsage::Pin => 0x1000,
+            KeyUsage::Data => 0x3000,
+        }
+    }
+}
+
+/// A key serial number, identifying the terminal's initial key and the transaction counter.
+///
+/// TDES KSNs are 10 bytes with a 21 bit counter, AES KSNs are an 8 byte initial key ID followed
+/// by a 32 bit counter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ksn(Vec<u8>);
+
+impl Ksn {
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        match self.0.len() {
+            10 => KeyAlgorithm::Tdes,
+            _ => KeyAlgorithm::Aes,
+        }
+    }
+
+    pub fn counter(&self) -> u32 {
+        let length = self.0.len();
+        let counter = u32::from_be_bytes(self.0[length - 4..].try_into().unwrap());
+        match self.algorithm() {
+            KeyAlgorithm::Tdes => counter & ((1 << TDES_COUNTER_BITS) - 1),
+            KeyAlgorithm::Aes => counter,
+        }
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode_upper(&self.0)
+    }
+}
+
+impl FromStr for Ksn {
+    type Err = GatewayError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match hex::decode(s) {
+            Ok(bytes) if bytes.len() == 10 || bytes.len() == 12 => Ok(Self(bytes)),
+            _ => Err(GatewayError::FieldError(
+                "Invalid ksn: must be 20 (TDES) or 24 (AES) hex characters".into(),
+            )),
+        }
+    }
+}
+
+fn xor(block: &mut [u8], other: &[u8]) {
+    for (byte, other) in block.iter_mut().zip(other) {
+        *byte ^= other;
+    }
+}
+
+fn encrypt(algorithm: KeyAlgorithm, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut key_store = SoftwareKeyStore::new();
+    key_store.insert(TERMINAL_KEY, algorithm, key)?;
+    key_store.encrypt(TERMINAL_KEY, data)
+}
+
+/// ANSI X9.24-1 initial PIN encryption key, from the KSN with its counter cleared
+fn tdes_initial_key(bdk: &[u8], ksn: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let mut initial = ksn[..8].to_vec();
+    initial[7] &= 0xE0;
+    let mut masked = Zeroizing::new(bdk.to_vec());
+    xor(&mut masked, &TDES_KEY_MASK);
+    let mut key = Zeroizing::new(encrypt(KeyAlgorithm::Tdes, bdk, &initial)?);
+    key.extend(encrypt(KeyAlgorithm::Tdes, &masked, &initial)?);
+    Ok(key)
+}
+
+/// The non-reversible key generation process, one half with each of the key and its mask
+fn tdes_next_key(key: &[u8], data: &[u8]) -> Zeroizing<Vec<u8>> {
+    let half = |key: &[u8]| {
+        let mut block = GenericArray::clone_from_slice(data);
+        xor(&mut block, &key[8..]);
+        Des::new_from_slice(&key[..8])
+            .expect("DES keys are 8 bytes")
+            .encrypt_block(&mut block);
+        xor(&mut block, &key[8..]);
+        block
+    };
+    let mut masked = Zeroizing::new(key.to_vec());
+    xor(&mut masked, &TDES_KEY_MASK);
+    let mut next = Zeroizing::new(half(&masked).to_vec());
+    next.extend(half(key));
+    next
+}
+
+fn tdes_working_key(bdk: &[u8], ksn: &Ksn, usage: KeyUsage) -> Result<Zeroizing<Vec<u8>>> {
+    let mut key = tdes_initial_key(bdk, &ksn.0)?;
+    // the rightmost 8 bytes of the KSN, the counter is rebuilt a bit at a time
+    let mut register = u64::from_be_bytes(ksn.0[2..].try_into().unwrap());
+    register &= !((1 << TDES_COUNTER_BITS) - 1);
+    let counter = ksn.counter();
+    for bit in (0..TDES_COUNTER_BITS).rev().map(|shift| 1 << shift) {
+        if counter & bit != 0 {
+            register |= u64::from(bit);
+            key = tdes_next_key(&key, &register.to_be_bytes());
+        }
+    }
+    xor(&mut key, &usage.tdes_variant());
+    if usage == KeyUsage::Data {
+        // data keys are also passed through a one way function
+        let mut data_key = Zeroizing::new(encrypt(KeyAlgorithm::Tdes, &key, &key[..8])?);
+        data_key.extend(encrypt(KeyAlgorithm::Tdes, &key, &key[8..])?);
+        key = data_key;
+    }
+    Ok(key)
+}
+
+/// ANSI X9.24-3 key derivation, the derived key is as long as the key it is derived from
+fn aes_derive(key: &[u8], usage: u16, data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let algorithm_indicator: u16 = match key.len() {
+        16 => 2,
+        24 => 3,
+        _ => 4,
+    };
+    let bits = key.len() as u16 * 8;
+    let mut derived = Zeroizing::new(Vec::new());
+    for block in 1..=key.len().div_ceil(16) as u8 {
+        let mut derivation_data = vec![1, block];
+        derivation_data.extend(usage.to_be_bytes());
+        derivation_data.extend(algorithm_indicator.to_be_bytes());
+        derivation_data.extend(bits.to_be_bytes());
+        derivation_data.extend(data);
+        derived.extend(encrypt(KeyAlgorithm::Aes, key, &derivation_data)?);
+    }
+    derived.truncate(key.len());
+    Ok(derived)
+}
+
+fn aes_working_key(bdk: &[u8], ksn: &Ksn, usage: KeyUsage) -> Result<Zeroizing<Vec<u8>>> {
+    let (initial_key_id, counter) = (&ksn.0[..8], ksn.counter());
+    let derivation_data = |counter: u32| [&initial_key_id[4..], &counter.to_be_bytes()].concat();
+    let mut key = aes_derive(bdk, 0x8001, initial_key_id)?;
+    let mut working_counter = 0;
+    for bit in (0..32).rev().map(|shift| 1 << shift) {
+        if counter & bit != 0 {
+            working_counter |= bit;
+            key = aes_derive(&key, 0x8000, &derivation_data(working_counter))?;
+        }
+    }
+    aes_derive(&key, usage.aes_indicator(), &derivation_data(counter))
+}
+
+/// Key operations on data from DUKPT terminals, a hardware security module would do these
+/// without the derived keys or the clear PIN ever leaving it
+pub trait KeyManagement: KeyStore {
+    /// Re-encrypts a PIN block the terminal encrypted under the BDK named by its `key_name` so
+    /// it is under the zone key shared with the acquirer
+    fn translate_pin_block(
+        &self,
+        ksn: &Ksn,
+        pin_block: &EncryptedPinBlock,
+        pan: &Secret,
+        zone_key_name: &str,
+        format: PinBlockFormat,
+    ) -> Result<EncryptedPinBlock>;
+
+    /// Decrypts data the terminal encrypted in CBC mode with a zero IV under the BDK named
+    /// `bdk_name`
+    fn decrypt_data(&self, bdk_name: &str, ksn: &Ksn, data: &[u8]) -> Result<Zeroizing<Vec<u8>>>;
+}
+
+impl SoftwareKeyStore {
+    fn dukpt_key(&self, bdk_name: &str, ksn: &Ksn, usage: KeyUsage) -> Result<SoftwareKeyStore> {
+        let (algorithm, bdk) = self.key(bdk_name)?;
+        if *algorithm != ksn.algorithm() {
+            return Err(GatewayError::KeyError(format!(
+                "KSN {} is for {:?} DUKPT but {bdk_name} is {algorithm:?}",
+                ksn.to_hex(),
+                ksn.algorithm(),
+            )));
+        }
+        let key = match algorithm {
+            KeyAlgorithm::Tdes => tdes_working_key(bdk, ksn, usage)?,
+            KeyAlgorithm::Aes => aes_working_key(bdk, ksn, usage)?,
+        };
+        let mut key_store = SoftwareKeyStore::new();
+        key_store.insert(TERMINAL_KEY, *algorithm, &key)?;
+        Ok(key_store)
+    }
+}
+
+impl KeyManagement for SoftwareKeyStore {
+    fn translate_pin_block(
+        &self,
+        ksn: &Ksn,
+        pin_block: &EncryptedPinBlock,
+        pan: &Secret,
+        zone_key_name: &str,
+        format: PinBlockFormat,
+    ) -> Result<EncryptedPinBlock> {
+        let terminal_key = self.dukpt_key(&pin_block.key_name, ksn, KeyUsage::Pin)?;
+        let pin = EncryptedPinBlock {
+            key_name: TERMINAL_KEY.into(),
+            ..pin_block.clone()
+        }
+        .pin(&terminal_key, pan)?;
+        EncryptedPinBlock::new(self, zone_key_name, format, &pin, pan)
+    }
+
+    fn decrypt_data(&self, bdk_name: &str, ksn: &Ksn, data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let terminal_key = self.dukpt_key(bdk_name, ksn, KeyUsage::Data)?;
+        let block_size = ksn.algorithm().block_size();
+        let mut clear = Zeroizing::new(terminal_key.decrypt(TERMINAL_KEY, data)?);
+        for (i, block) in clear.chunks_mut(block_size).enumerate().skip(1) {
+            xor(block, &data[(i - 1) * block_size..i * block_size]);
+        }
+        Ok(clear)
+    }
+}
+
+/// Card data read by a terminal that encrypts under DUKPT
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerminalData {
+    /// The key store name of the base derivation key the terminal was injected from
+    pub bdk_name: String,
+    pub ksn: Ksn,
+    /// Track 2, padded with zeros to a whole number of blocks
+    pub track2: Vec<u8>,
+    pub pin_block: Option<(PinBlockFormat, Vec<u8>)>,
+}
+
+impl TerminalData {
+    /// Decrypts the track 2 and moves the PIN block to `bank`'s zone key, keeping the
+    /// terminal's PIN block format when the zone key can be used with it
+    pub fn decrypt(
+        &self,
+        key_management: &dyn KeyManagement,
+        bank: &Bank,
+    ) -> Result<(Track2, Option<EncryptedPinBlock>)> {
+        let clear = key_management.decrypt_data(&self.bdk_name, &self.ksn, &self.track2)?;
+        let length = clear
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(clear.len());
+        let track2: Track2 = std::str::from_utf8(&clear[..length])
+            .map_err(|_| GatewayError::FieldError("Invalid track2: not text".into()))?
+            .parse()?;
+        let Some((format, block)) = &self.pin_block else {
+            return Ok((track2, None));
+        };
+        let zone_key_name = bank.zone_key_name()?;
+        let zone_format = match key_management.algorithm(&zone_key_name)? {
+            algorithm if algorithm == format.algorithm() => *format,
+            KeyAlgorithm::Tdes => PinBlockFormat::Iso0,
+            KeyAlgorithm::Aes => PinBlockFormat::Iso4,
+        };
+        let pin_block = EncryptedPinBlock {
+            format: *format,
+            key_name: self.bdk_name.clone(),
+            block: block.clone(),
+        };
+        let pin_block = key_management.translate_pin_block(
+            &self.ksn,
+            &pin_block,
+            &track2.pan,
+            &zone_key_name,
+            zone_format,
+        )?;
+        Ok((track2, Some(pin_block)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_store::test_key_store;
+
+    const TDES_KSN: &str = "FFFF9876543210E00001";
+    const AES_KSN: &str = "123456789012345600000001";
+
+    #[test]
+    fn test_working_keys() {
+        let tdes_bdk = hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap();
+        let aes_bdk = hex::decode("FEDCBA9876543210F1F1F1F1F1F1F1F1").unwrap();
+        let tdes_ksn: Ksn = TDES_KSN.parse().unwrap();
+        let aes_ksn: Ksn = AES_KSN.parse().unwrap();
+        assert_eq!(
+            "6AC292FAA1315B4D858AB3A3D7D5933A",
+            hex::encode_upper(tdes_initial_key(&tdes_bdk, &tdes_ksn.0).unwrap().as_slice())
+        );
+        assert_eq!(
+            "1273671EA26AC29AFA4D1084127652A1",
+            hex::encode_upper(
+                aes_derive(&aes_bdk, 0x8001, &aes_ksn.0[..8])
+                    .unwrap()
+                    .as_slice()
+            )
+        );
+        let tests = [
+            (
+                tdes_working_key(&tdes_bdk, &tdes_ksn, KeyUsage::Pin),
+                "042666B49184CF5C68DE9628D0397B36",
+            ),
+            (
+                tdes_working_key(&tdes_bdk, &tdes_ksn, KeyUsage::Data),
+                "448D3F076D8304036A55A3D7E0055A78",
+            ),
+            (
+                tdes_working_key(
+                    &tdes_bdk,
+                    &"FFFF9876543210E00003".parse().unwrap(),
+                    KeyUsage::Pin,
+                ),
+                "0DF3D9422ACA561A47676D07AD6BAD05",
+            ),
+            (
+                aes_working_key(&aes_bdk, &aes_ksn, KeyUsage::Pin),
+                "AF8CB133A78F8DC2D1359F18527593FB",
+            ),
+            (
+                aes_working_key(&aes_bdk, &aes_ksn, KeyUsage::Data),
+                "A35C412EFD41FDB98B69797C02DCD08F",
+            ),
+        ];
+        for (i, (actual, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(
+                expected,
+                hex::encode_upper(actual.unwrap().as_slice()),
+                "Case number {}",
+                i + 1
+            );
+        }
+    }
+
+    #[test]
+    fn test_ksn() {
+        let ksn: Ksn = TDES_KSN.parse().unwrap();
+        assert_eq!((KeyAlgorithm::Tdes, 1), (ksn.algorithm(), ksn.counter()));
+        let ksn: Ksn = "FFFF9876543210FFFFFF".parse().unwrap();
+        assert_eq!(0x1FFFFF, ksn.counter());
+        let ksn: Ksn = AES_KSN.parse().unwrap();
+        assert_eq!((KeyAlgorithm::Aes, 1), (ksn.algorithm(), ksn.counter()));
+        assert_eq!(
+            Err(GatewayError::FieldError(
+                "Invalid ksn: must be 20 (TDES) or 24 (AES) hex characters".into()
+            )),
+            "FFFF9876543210E000".parse::<Ksn>()
+        );
+    }
+
+    #[test]
+    fn test_terminal_data() {
+        let key_store = test_key_store();
+        let pan = Secret::new("4111111111111111");
+        let tests = [
+            (
+                TerminalData {
+                    bdk_name: "bdk".into(),
+                    ksn: TDES_KSN.parse().unwrap(),
+                    track2: hex::decode(
+                        "7B779F3C0277D9733FEF2488997A7FB1556D26F4CB82805F6E050FEF1C556D6973D0A6DA6FBF4D12",
+                    )
+                    .unwrap(),
+                    pin_block: Some((
+                        PinBlockFormat::Iso0,
+                        hex::decode("E6256C75DD53796C").unwrap(),
+                    )),
+                },
+                PinBlockFormat::Iso0,
+            ),
+            (
+                TerminalData {
+                    bdk_name: "aesbdk".into(),
+                    ksn: AES_KSN.parse().unwrap(),
+                    track2: hex::decode(
+                        "87D45DC64A786FCC6B44187CFD2DFA79212964323E351F3181E53299F4BF6031B5559C0DFDE2B16B39A45CBC866A6194",
+                    )
+                    .unwrap(),
+                    pin_block: Some((
+                        PinBlockFormat::Iso4,
+                        hex::decode("97692041B2263BEFA69B131A469FBCE8").unwrap(),
+                    )),
+                },
+                PinBlockFormat::Iso0,
+            ),
+        ];
+        for (i, (terminal_data, format)) in tests.into_iter().enumerate() {
+            let (track2, pin_block) = terminal_data.decrypt(&key_store, &Bank::Ems).unwrap();
+            assert_eq!(
+                "4111111111111111=24122011234567890",
+                track2.data.reveal(),
+                "Case number {}",
+                i + 1
+            );
+            let pin_block = pin_block.unwrap();
+            assert_eq!(
+                (format, "ems.zpk"),
+                (pin_block.format, pin_block.key_name.as_str()),
+                "Case number {}",
+                i + 1
+            );
+            assert_eq!(
+                Ok(Secret::new("1234")),
+                pin_block.pin(&key_store, &pan),
+                "Case number {}",
+                i + 1
+            );
+        }
+
+        let mismatched = TerminalData {
+            bdk_name: "aesbdk".into(),
+            ksn: TDES_KSN.parse().unwrap(),
+            track2: vec![0; 8],
+            pin_block: None,
+        };
+        assert_eq!(
+            Err(GatewayError::KeyError(
+                "KSN FFFF9876543210E00001 is for Tdes DUKPT but aesbdk is Aes".into()
+            )),
+            mismatched.decrypt(&key_store, &Bank::Ems)
+        );
+    }
+}
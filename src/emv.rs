@@ -0,0 +1,319 @@
+use crate::{GatewayError, Result};
+
+/// Tags the acquirer needs to authorise a chip transaction, with their fixed length in bytes
+/// (`None` when the length varies)
+const MANDATORY_TAGS: &[(u32, Option<usize>)] = &[
+    (tag::APPLICATION_CRYPTOGRAM, Some(8)),
+    (tag::CRYPTOGRAM_INFORMATION_DATA, Some(1)),
+    (tag::ISSUER_APPLICATION_DATA, None),
+    (tag::UNPREDICTABLE_NUMBER, Some(4)),
+    (tag::TERMINAL_VERIFICATION_RESULTS, Some(5)),
+    (tag::TRANSACTION_DATE, Some(3)),
+    (tag::TRANSACTION_TYPE, Some(1)),
+    (tag::TRANSACTION_CURRENCY_CODE, Some(2)),
+    (tag::APPLICATION_INTERCHANGE_PROFILE, Some(2)),
+    (tag::APPLICATION_TRANSACTION_COUNTER, Some(2)),
+    (tag::AMOUNT_AUTHORISED, Some(6)),
+];
+
+pub mod tag {
+    pub const APPLICATION_INTERCHANGE_PROFILE: u32 = 0x82;
+    pub const TERMINAL_VERIFICATION_RESULTS: u32 = 0x95;
+    pub const TRANSACTION_DATE: u32 = 0x9A;
+    pub const TRANSACTION_TYPE: u32 = 0x9C;
+    pub const TRANSACTION_CURRENCY_CODE: u32 = 0x5F2A;
+    pub const AMOUNT_AUTHORISED: u32 = 0x9F02;
+    pub const ISSUER_APPLICATION_DATA: u32 = 0x9F10;
+    pub const APPLICATION_CRYPTOGRAM: u32 = 0x9F26;
+    pub const CRYPTOGRAM_INFORMATION_DATA: u32 = 0x9F27;
+    pub const APPLICATION_TRANSACTION_COUNTER: u32 = 0x9F36;
+    pub const UNPREDICTABLE_NUMBER: u32 = 0x9F37;
+}
+
+fn invalid(reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid emvdata: {reason}"))
+}
+
+/// A single BER-TLV data object
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tlv {
+    pub tag: u32,
+    pub value: Vec<u8>,
+}
+
+impl Tlv {
+    pub fn new(tag: u32, value: &[u8]) -> Self {
+        Self {
+            tag,
+            value: value.to_vec(),
+        }
+    }
+
+    /// Constructed objects contain further TLVs rather than a primitive value
+    pub fn is_constructed(&self) -> bool {
+        tag_bytes(self.tag)[0] & 0x20 != 0
+    }
+
+    pub fn children(&self) -> Result<Vec<Tlv>> {
+        if !self.is_constructed() {
+            return Err(invalid(&format!("tag {:X} is not constructed", self.tag)));
+        }
+        parse(&self.value)
+    }
+
+    pub fn encode(&self, output: &mut Vec<u8>) {
+        output.extend(tag_bytes(self.tag));
+        match self.value.len() {
+            length @ 0..=0x7F => output.push(length as u8),
+            length @ 0x80..=0xFF => output.extend([0x81, length as u8]),
+            length => output.extend([0x82, (length >> 8) as u8, length as u8]),
+        }
+        output.extend(&self.value);
+    }
+}
+
+fn tag_bytes(tag: u32) -> Vec<u8> {
+    let bytes = tag.to_be_bytes();
+    let first = bytes.iter().position(|b| *b != 0).unwrap_or(3);
+    bytes[first..].to_vec()
+}
+
+/// Parses a sequence of BER-TLV objects, constructed objects are left for [`Tlv::children`]
+pub fn parse(mut bytes: &[u8]) -> Result<Vec<Tlv>> {
+    let truncated = || invalid("truncated TLV");
+    let mut tlvs = Vec::new();
+    while let Some(&first) = bytes.first() {
+        // padding allowed between objects
+        if first == 0x00 || first == 0xFF {
+            bytes = &bytes[1..];
+            continue;
+        }
+        let mut tag_length = 1;
+        if first & 0x1F == 0x1F {
+            // subsequent tag bytes have their top bit set, apart from the last
+            loop {
+                let byte = *bytes.get(tag_length).ok_or_else(truncated)?;
+                tag_length += 1;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+        }
+        if tag_length > 4 {
+            return Err(invalid("tag longer than 4 bytes"));
+        }
+        let tag = bytes[..tag_length]
+            .iter()
+            .fold(0, |tag, byte| tag << 8 | u32::from(*byte));
+        let rest = &bytes[tag_length..];
+        let (length, rest) = match *rest.first().ok_or_else(truncated)? {
+            short @ 0..=0x7F => (usize::from(short), &rest[1..]),
+            long @ 0x81..=0x82 => {
+                let count = usize::from(long & 0x7F);
+                let length_bytes = rest.get(1..1 + count).ok_or_else(truncated)?;
+                let length = length_bytes
+                    .iter()
+                    .fold(0, |length, byte| length << 8 | usize::from(*byte));
+                (length, &rest[1 + count..])
+            }
+            _ => return Err(invalid(&format!("unsupported length for tag {tag:X}"))),
+        };
+        let value = rest.get(..length).ok_or_else(truncated)?;
+        tlvs.push(Tlv::new(tag, value));
+        bytes = &rest[length..];
+    }
+    Ok(tlvs)
+}
+
+/// The ICC data read from a chip, forwarded to the acquirer in template field 10 (ISO 8583
+/// banks know it as field 55)
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmvData {
+    tlvs: Vec<Tlv>,
+}
+
+impl EmvData {
+    pub fn new(tlvs: Vec<Tlv>) -> Self {
+        Self { tlvs }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        Ok(Self::new(parse(bytes)?))
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let bytes = hex::decode(hex).map_err(|_| invalid("not hex"))?;
+        Self::parse(&bytes)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        for tlv in &self.tlvs {
+            tlv.encode(&mut output);
+        }
+        output
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode_upper(self.to_bytes())
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .find(|tlv| tlv.tag == tag)
+            .map(|tlv| tlv.value.as_slice())
+    }
+
+    /// The ARQC, TC or AAC generated by the card
+    pub fn application_cryptogram(&self) -> Option<&[u8]> {
+        self.get(tag::APPLICATION_CRYPTOGRAM)
+    }
+
+    pub fn cryptogram_information_data(&self) -> Option<&[u8]> {
+        self.get(tag::CRYPTOGRAM_INFORMATION_DATA)
+    }
+
+    pub fn issuer_application_data(&self) -> Option<&[u8]> {
+        self.get(tag::ISSUER_APPLICATION_DATA)
+    }
+
+    pub fn unpredictable_number(&self) -> Option<&[u8]> {
+        self.get(tag::UNPREDICTABLE_NUMBER)
+    }
+
+    pub fn terminal_verification_results(&self) -> Option<&[u8]> {
+        self.get(tag::TERMINAL_VERIFICATION_RESULTS)
+    }
+
+    /// BCD encoded `YYMMDD`
+    pub fn transaction_date(&self) -> Option<&[u8]> {
+        self.get(tag::TRANSACTION_DATE)
+    }
+
+    pub fn transaction_type(&self) -> Option<&[u8]> {
+        self.get(tag::TRANSACTION_TYPE)
+    }
+
+    /// BCD encoded ISO 4217 numeric code
+    pub fn transaction_currency_code(&self) -> Option<&[u8]> {
+        self.get(tag::TRANSACTION_CURRENCY_CODE)
+    }
+
+    pub fn application_interchange_profile(&self) -> Option<&[u8]> {
+        self.get(tag::APPLICATION_INTERCHANGE_PROFILE)
+    }
+
+    pub fn application_transaction_counter(&self) -> Option<&[u8]> {
+        self.get(tag::APPLICATION_TRANSACTION_COUNTER)
+    }
+
+    /// BCD encoded amount in minor units
+    pub fn amount_authorised(&self) -> Option<&[u8]> {
+        self.get(tag::AMOUNT_AUTHORISED)
+    }
+
+    /// Checks every tag the acquirer needs is present with the right length
+    pub fn validate(&self) -> Result<()> {
+        for (tag, length) in MANDATORY_TAGS {
+            let value = self
+                .get(*tag)
+                .ok_or_else(|| invalid(&format!("missing tag {tag:X}")))?;
+            match length {
+                Some(length) if value.len() != *length => {
+                    return Err(invalid(&format!("tag {tag:X} must be {length} bytes")))
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub const TEST_EMV_DATA: &str = "9F2608C3A5B2D1E4F607189F2701809F100706010A03A000009F3704A1B2C3D4950500000080009A032406159C01005F2A0208268202180\
+09F360200019F0206000000012345";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let tests = [
+            (
+                "9F260811223344556677889F270180",
+                Ok(vec![
+                    Tlv::new(0x9F26, &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]),
+                    Tlv::new(0x9F27, &[0x80]),
+                ]),
+            ),
+            ("5F2A020826", Ok(vec![Tlv::new(0x5F2A, &[0x08, 0x26])])),
+            (
+                "0095050000008000",
+                Ok(vec![Tlv::new(0x95, &[0, 0, 0, 0x80, 0])]),
+            ),
+            ("9F2608112233", Err("Invalid emvdata: truncated TLV")),
+            ("9F", Err("Invalid emvdata: truncated TLV")),
+            (
+                "9F2683000000",
+                Err("Invalid emvdata: unsupported length for tag 9F26"),
+            ),
+        ];
+        for (i, (hex, expected)) in tests.into_iter().enumerate() {
+            let expected = expected.map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(
+                expected,
+                parse(&hex::decode(hex).unwrap()),
+                "Case number {}",
+                i + 1
+            );
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let long = Tlv::new(0x9F10, &[0xAB; 200]);
+        let mut encoded = Vec::new();
+        long.encode(&mut encoded);
+        assert_eq!([0x9F, 0x10, 0x81, 200], encoded[..4]);
+        assert_eq!(Ok(vec![long]), parse(&encoded));
+
+        let template = Tlv::new(
+            0x70,
+            &hex::decode("5A0841111111111111115F2403241231").unwrap(),
+        );
+        assert!(template.is_constructed());
+        assert_eq!(
+            Ok(vec![
+                Tlv::new(0x5A, &hex::decode("4111111111111111").unwrap()),
+                Tlv::new(0x5F24, &[0x24, 0x12, 0x31]),
+            ]),
+            template.children()
+        );
+    }
+
+    #[test]
+    fn test_emv_data() {
+        let emv = EmvData::from_hex(TEST_EMV_DATA).unwrap();
+        assert_eq!(Ok(()), emv.validate());
+        assert_eq!(TEST_EMV_DATA, emv.to_hex());
+        assert_eq!(Some(&[0x80][..]), emv.cryptogram_information_data());
+        assert_eq!(Some(&[0x08, 0x26][..]), emv.transaction_currency_code());
+        assert_eq!(Some(&[0x24, 0x06, 0x15][..]), emv.transaction_date());
+
+        let tests = [
+            (&TEST_EMV_DATA[..22], "Invalid emvdata: missing tag 9F27"),
+            (
+                "9F2604C3A5B2D19F2701809F100706010A03A00000",
+                "Invalid emvdata: tag 9F26 must be 8 bytes",
+            ),
+        ];
+        for (hex, expected) in tests.into_iter() {
+            assert_eq!(
+                Err(GatewayError::FieldError(expected.into())),
+                EmvData::from_hex(hex).unwrap().validate()
+            );
+        }
+    }
+}
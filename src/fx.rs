@@ -0,0 +1,480 @@
+use std::{collections::HashMap, fs, time::Duration};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    currency::Currency,
+    money::Money,
+    GatewayError, Result,
+};
+
+/// ISO 8583 conversion rates are 8 digits, the first says how many of the other 7 are decimals
+const RATE_DIGITS: u128 = 9_999_999;
+const MAX_RATE_DECIMALS: u32 = 7;
+/// How long a DCC quote stays acceptable, schemes expect the rate to be current when the cardholder agrees to it
+pub const QUOTE_VALIDITY: Duration = Duration::from_secs(15 * 60);
+
+fn invalid(field: &str, reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid {field}: {reason}"))
+}
+
+/// How many units of `to` one unit of `from` buys, held as a decimal so conversions are exact
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ExchangeRate {
+    pub from: Currency,
+    pub to: Currency,
+    mantissa: u64,
+    decimals: u32,
+}
+
+impl ExchangeRate {
+    /// Parses a positive decimal rate such as `1.1732`
+    pub fn parse(from: Currency, to: Currency, rate: &str) -> Result<Self> {
+        let not_a_rate = || invalid("rate", &format!("{rate} is not a positive decimal number"));
+        let (whole, fraction) = rate.split_once('.').unwrap_or((rate, ""));
+        let digits = format!("{whole}{fraction}");
+        if whole.is_empty()
+            || rate.ends_with('.')
+            || digits.len() > 18
+            || !digits.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(not_a_rate());
+        }
+        let mantissa: u64 = digits.parse().map_err(|_| not_a_rate())?;
+        if mantissa == 0 {
+            return Err(not_a_rate());
+        }
+        Ok(Self {
+            from,
+            to,
+            mantissa,
+            decimals: fraction.len() as u32,
+        })
+    }
+
+    /// Rounds to the 7 significant digits and 7 decimals ISO 8583 can carry
+    fn normalise(from: Currency, to: Currency, mantissa: u128, decimals: u32) -> Result<Self> {
+        for dropped in 0..=decimals {
+            let divisor = 10u128.pow(dropped);
+            let rounded = (mantissa + divisor / 2) / divisor;
+            if decimals - dropped <= MAX_RATE_DECIMALS && rounded <= RATE_DIGITS {
+                if rounded == 0 {
+                    break;
+                }
+                return Ok(Self {
+                    from,
+                    to,
+                    mantissa: rounded as u64,
+                    decimals: decimals - dropped,
+                });
+            }
+        }
+        Err(invalid(
+            "rate",
+            &format!("{from} to {to} cannot be sent with 7 significant digits"),
+        ))
+    }
+
+    /// The rate increased by `basis_points` hundredths of a percent, rounded so it can be sent as is
+    pub fn with_markup(&self, basis_points: u32) -> Result<Self> {
+        Self::normalise(
+            self.from,
+            self.to,
+            u128::from(self.mantissa) * u128::from(10_000 + basis_points),
+            self.decimals + 4,
+        )
+    }
+
+    /// Converts an amount in `from` to `to`, rounding half up to `to`'s minor unit
+    pub fn convert(&self, amount: &Money) -> Result<Money> {
+        if amount.currency() != self.from {
+            return Err(GatewayError::ValidationError(format!(
+                "Cannot convert {} with a {} to {} rate",
+                amount.currency(),
+                self.from,
+                self.to
+            )));
+        }
+        let overflow = || GatewayError::ValidationError(format!("{} amount overflowed", self.to));
+        let numerator = i128::from(amount.minor_units().unsigned_abs())
+            .checked_mul(i128::from(self.mantissa))
+            .and_then(|product| product.checked_mul(10i128.pow(self.to.exponent())))
+            .ok_or_else(overflow)?;
+        let denominator = 10i128.pow(self.decimals + self.from.exponent());
+        let converted =
+            i64::try_from((numerator + denominator / 2) / denominator).map_err(|_| overflow())?;
+        Ok(Money::new(
+            if amount.is_negative() {
+                -converted
+            } else {
+                converted
+            },
+            self.to,
+        ))
+    }
+
+    pub fn to_decimal_string(&self) -> String {
+        let decimals = self.decimals as usize;
+        let digits = format!("{:0>width$}", self.mantissa, width = decimals + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - decimals);
+        match decimals {
+            0 => whole.into(),
+            _ => format!("{whole}.{fraction}"),
+        }
+    }
+
+    /// The rate as ISO 8583 fields 10 and 11 send it, e.g. `61173200` for 1.1732
+    pub fn to_iso8583(&self) -> Result<String> {
+        let rate = Self::normalise(self.from, self.to, self.mantissa.into(), self.decimals)?;
+        let (mut mantissa, mut decimals) = (rate.mantissa, rate.decimals);
+        while decimals < MAX_RATE_DECIMALS && u128::from(mantissa) * 10 <= RATE_DIGITS {
+            mantissa *= 10;
+            decimals += 1;
+        }
+        Ok(format!("{decimals}{mantissa:07}"))
+    }
+}
+
+/// Where the gateway gets the wholesale rates DCC quotes are built from
+pub trait RateProvider: Send + Sync {
+    fn rate(&self, from: Currency, to: Currency) -> Result<ExchangeRate>;
+}
+
+/// Rates loaded from a file, e.g. the daily table an acquirer publishes
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileRateProvider {
+    rates: HashMap<(Currency, Currency), ExchangeRate>,
+}
+
+impl FileRateProvider {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| GatewayError::FieldError(format!("Cannot read {path}: {err}")))?;
+        Self::parse(&contents)
+    }
+
+    /// Parses CSV with a header line followed by `from,to,rate` rows
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut rates = HashMap::new();
+        for (i, line) in contents.lines().enumerate().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |what: &str| {
+                GatewayError::FieldError(format!("Invalid rate table line {}: {what}", i + 1))
+            };
+            let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [from, to, rate] = columns[..] else {
+                return Err(invalid("expected 3 columns"));
+            };
+            let currency = |column: &str| {
+                column
+                    .parse::<Currency>()
+                    .map_err(|_| invalid(&format!("unknown currency {column}")))
+            };
+            let (from, to) = (currency(from)?, currency(to)?);
+            let rate = ExchangeRate::parse(from, to, rate)
+                .map_err(|_| invalid(&format!("{rate} is not a positive decimal number")))?;
+            rates.insert((from, to), rate);
+        }
+        Ok(Self { rates })
+    }
+}
+
+impl RateProvider for FileRateProvider {
+    fn rate(&self, from: Currency, to: Currency) -> Result<ExchangeRate> {
+        self.rates
+            .get(&(from, to))
+            .copied()
+            .ok_or(GatewayError::ValidationError(format!(
+                "No {from} to {to} exchange rate"
+            )))
+    }
+}
+
+/// An offer to let the cardholder pay in their own currency, shown to them before they accept it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DccQuote {
+    pub merchant_amount: Money,
+    pub cardholder_amount: Money,
+    /// The provider's rate with the markup applied, the one the cardholder pays
+    pub rate: ExchangeRate,
+    /// The markup on the provider's rate in hundredths of a percent, disclosed to the cardholder
+    pub markup_basis_points: u32,
+    /// When the quote was made, in seconds since 1970-01-01 UTC
+    pub quoted_at: u64,
+    /// How long the cardholder has to accept the quote before the rate is stale
+    pub valid_for: Duration,
+}
+
+impl DccQuote {
+    pub fn new(
+        provider: &dyn RateProvider,
+        merchant_amount: Money,
+        cardholder_currency: Currency,
+        markup_basis_points: u32,
+    ) -> Result<Self> {
+        Self::new_at(
+            provider,
+            merchant_amount,
+            cardholder_currency,
+            markup_basis_points,
+            &SystemClock,
+        )
+    }
+
+    /// Quotes `merchant_amount` in the cardholder's currency, valid for [`QUOTE_VALIDITY`]
+    pub fn new_at(
+        provider: &dyn RateProvider,
+        merchant_amount: Money,
+        cardholder_currency: Currency,
+        markup_basis_points: u32,
+        clock: &dyn Clock,
+    ) -> Result<Self> {
+        if cardholder_currency == merchant_amount.currency() {
+            return Err(GatewayError::ValidationError(format!(
+                "Cannot offer DCC in the merchant's own currency {cardholder_currency}"
+            )));
+        }
+        if markup_basis_points >= 10_000 {
+            return Err(invalid("markup", "must be under 10000 basis points"));
+        }
+        let rate = provider
+            .rate(merchant_amount.currency(), cardholder_currency)?
+            .with_markup(markup_basis_points)?;
+        Ok(Self {
+            merchant_amount,
+            cardholder_amount: rate.convert(&merchant_amount)?,
+            rate,
+            markup_basis_points,
+            quoted_at: clock.now(),
+            valid_for: QUOTE_VALIDITY,
+        })
+    }
+
+    pub fn is_expired(&self, clock: &dyn Clock) -> bool {
+        clock.now() >= self.quoted_at.saturating_add(self.valid_for.as_secs())
+    }
+}
+
+#[cfg(test)]
+pub fn test_rate_provider() -> FileRateProvider {
+    FileRateProvider::parse(
+        "from,to,rate\n\
+         GBP,EUR,1.17\n\
+         GBP,USD,1.2734\n\
+         GBP,JPY,190.12345\n\
+         JPY,GBP,0.0052\n",
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{test_clock, FixedTime};
+
+    fn rate(from: Currency, to: Currency, rate: &str) -> ExchangeRate {
+        ExchangeRate::parse(from, to, rate).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        let tests = [
+            ("1.17", Ok(("1.17", "61170000"))),
+            ("1.2734", Ok(("1.2734", "61273400"))),
+            ("190.12345", Ok(("190.12345", "41901235"))),
+            ("0.0052", Ok(("0.0052", "70052000"))),
+            ("25000", Ok(("25000", "22500000"))),
+            ("1.123456789", Ok(("1.123456789", "61123457"))),
+            ("0", Err("Invalid rate: 0 is not a positive decimal number")),
+            (
+                "-1.2",
+                Err("Invalid rate: -1.2 is not a positive decimal number"),
+            ),
+            (
+                "1.",
+                Err("Invalid rate: 1. is not a positive decimal number"),
+            ),
+            (
+                ".5",
+                Err("Invalid rate: .5 is not a positive decimal number"),
+            ),
+        ];
+        for (i, (value, expected)) in tests.into_iter().enumerate() {
+            let actual = ExchangeRate::parse(Currency::GBP, Currency::EUR, value)
+                .and_then(|rate| Ok((rate.to_decimal_string(), rate.to_iso8583()?)));
+            let expected = expected
+                .map(|(decimal, iso8583)| (decimal.to_string(), iso8583.to_string()))
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+        assert_eq!(
+            Err(GatewayError::FieldError(
+                "Invalid rate: GBP to EUR cannot be sent with 7 significant digits".into()
+            )),
+            rate(Currency::GBP, Currency::EUR, "0.00000001").to_iso8583()
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let tests = [
+            (
+                rate(Currency::GBP, Currency::EUR, "1.17"),
+                Money::new(12345, Currency::GBP),
+                Ok(Money::new(14444, Currency::EUR)),
+            ),
+            (
+                rate(Currency::GBP, Currency::JPY, "190.5"),
+                Money::new(12345, Currency::GBP),
+                Ok(Money::new(23517, Currency::JPY)),
+            ),
+            (
+                rate(Currency::JPY, Currency::GBP, "0.0052"),
+                Money::new(12345, Currency::JPY),
+                Ok(Money::new(6419, Currency::GBP)),
+            ),
+            (
+                rate(Currency::GBP, Currency::BHD, "0.4781"),
+                Money::new(12345, Currency::GBP),
+                Ok(Money::new(59021, Currency::BHD)),
+            ),
+            (
+                rate(Currency::GBP, Currency::EUR, "1.17"),
+                Money::new(-100, Currency::GBP),
+                Ok(Money::new(-117, Currency::EUR)),
+            ),
+            (
+                rate(Currency::GBP, Currency::EUR, "1.17"),
+                Money::new(100, Currency::USD),
+                Err(GatewayError::ValidationError(
+                    "Cannot convert USD with a GBP to EUR rate".into(),
+                )),
+            ),
+            (
+                rate(Currency::GBP, Currency::EUR, "1000"),
+                Money::new(i64::MAX, Currency::GBP),
+                Err(GatewayError::ValidationError(
+                    "EUR amount overflowed".into(),
+                )),
+            ),
+        ];
+        for (i, (rate, amount, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(expected, rate.convert(&amount), "Case number {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_with_markup() {
+        let tests = [
+            (rate(Currency::GBP, Currency::EUR, "1.17"), 0, "1.170000"),
+            (rate(Currency::GBP, Currency::EUR, "1.17"), 350, "1.210950"),
+            (
+                rate(Currency::GBP, Currency::JPY, "190.12345"),
+                250,
+                "194.8765",
+            ),
+            (
+                rate(Currency::JPY, Currency::GBP, "0.0052"),
+                300,
+                "0.0053560",
+            ),
+        ];
+        for (i, (rate, markup, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(
+                expected,
+                rate.with_markup(markup).unwrap().to_decimal_string(),
+                "Case number {}",
+                i + 1
+            );
+        }
+    }
+
+    #[test]
+    fn test_file_rate_provider() {
+        let provider = test_rate_provider();
+        assert_eq!(
+            Ok(rate(Currency::GBP, Currency::USD, "1.2734")),
+            provider.rate(Currency::GBP, Currency::USD)
+        );
+        assert_eq!(
+            Err(GatewayError::ValidationError(
+                "No USD to GBP exchange rate".into()
+            )),
+            provider.rate(Currency::USD, Currency::GBP)
+        );
+        let tests = [
+            ("GBP,EUR", "Invalid rate table line 2: expected 3 columns"),
+            (
+                "GBP,XYZ,1.17",
+                "Invalid rate table line 2: unknown currency XYZ",
+            ),
+            (
+                "GBP,EUR,1,17",
+                "Invalid rate table line 2: expected 3 columns",
+            ),
+            (
+                "GBP,EUR,abc",
+                "Invalid rate table line 2: abc is not a positive decimal number",
+            ),
+        ];
+        for (i, (line, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(
+                Err(GatewayError::FieldError(expected.into())),
+                FileRateProvider::parse(&format!("from,to,rate\n{line}\n")),
+                "Case number {}",
+                i + 1
+            );
+        }
+    }
+
+    #[test]
+    fn test_dcc_quote() {
+        let provider = test_rate_provider();
+        let quoted_at = 1718452800;
+        let quote = DccQuote::new_at(
+            &provider,
+            Money::new(12345, Currency::GBP),
+            Currency::EUR,
+            350,
+            &FixedTime(quoted_at),
+        )
+        .unwrap();
+        assert_eq!(Money::new(14949, Currency::EUR), quote.cardholder_amount);
+        assert_eq!("1.210950", quote.rate.to_decimal_string());
+        assert_eq!(quoted_at, quote.quoted_at);
+        assert!(!quote.is_expired(&FixedTime(quoted_at)));
+        assert!(!quote.is_expired(&FixedTime(quoted_at + 899)));
+        assert!(quote.is_expired(&FixedTime(quoted_at + 900)));
+
+        let tests = [
+            (
+                Currency::GBP,
+                350,
+                GatewayError::ValidationError(
+                    "Cannot offer DCC in the merchant's own currency GBP".into(),
+                ),
+            ),
+            (
+                Currency::EUR,
+                10_000,
+                GatewayError::FieldError("Invalid markup: must be under 10000 basis points".into()),
+            ),
+            (
+                Currency::CHF,
+                350,
+                GatewayError::ValidationError("No GBP to CHF exchange rate".into()),
+            ),
+        ];
+        for (i, (currency, markup, expected)) in tests.into_iter().enumerate() {
+            let actual = DccQuote::new_at(
+                &provider,
+                Money::new(12345, Currency::GBP),
+                currency,
+                markup,
+                &test_clock(),
+            );
+            assert_eq!(Err(expected), actual, "Case number {}", i + 1);
+        }
+    }
+}
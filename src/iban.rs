@@ -0,0 +1,214 @@
+use crate::{operation_field::regex, GatewayError, Result};
+
+regex!(BIC_REGEX, "^[A-Z]{4}[A-Z]{2}[A-Z0-9]{2}([A-Z0-9]{3})?$");
+
+/// BBAN layouts from the ISO 13616 registry for the SEPA scheme countries, each part is a length
+/// followed by `n` (digits), `a` (upper case letters) or `c` (either)
+const BBAN_FORMATS: &[(&str, &str)] = &[
+    ("AD", "4n,4n,12c"),
+    ("AL", "8n,16c"),
+    ("AT", "5n,11n"),
+    ("BE", "3n,7n,2n"),
+    ("BG", "4a,4n,2n,8c"),
+    ("CH", "5n,12c"),
+    ("CY", "3n,5n,16c"),
+    ("CZ", "4n,6n,10n"),
+    ("DE", "8n,10n"),
+    ("DK", "4n,9n,1n"),
+    ("EE", "2n,2n,11n,1n"),
+    ("ES", "4n,4n,1n,1n,10n"),
+    ("FI", "3n,11n"),
+    ("FR", "5n,5n,11c,2n"),
+    ("GB", "4a,6n,8n"),
+    ("GI", "4a,15c"),
+    ("GR", "3n,4n,16c"),
+    ("HR", "7n,10n"),
+    ("HU", "3n,4n,1n,15n,1n"),
+    ("IE", "4a,6n,8n"),
+    ("IS", "4n,2n,6n,10n"),
+    ("IT", "1a,5n,5n,12c"),
+    ("LI", "5n,12c"),
+    ("LT", "5n,11n"),
+    ("LU", "3n,13c"),
+    ("LV", "4a,13c"),
+    ("MC", "5n,5n,11c,2n"),
+    ("MD", "2c,18c"),
+    ("ME", "3n,13n,2n"),
+    ("MK", "3n,10c,2n"),
+    ("MT", "4a,5n,18c"),
+    ("NL", "4a,10n"),
+    ("NO", "4n,6n,1n"),
+    ("PL", "8n,16n"),
+    ("PT", "4n,4n,11n,2n"),
+    ("RO", "4a,16c"),
+    ("SE", "3n,16n,1n"),
+    ("SI", "5n,8n,2n"),
+    ("SK", "4n,6n,10n"),
+    ("SM", "1a,5n,5n,12c"),
+    ("VA", "3n,15n"),
+];
+
+fn invalid(field: &str, reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid {field}: {reason}"))
+}
+
+/// Checks an IBAN's country layout and check digits, returning it without spaces
+pub fn validate_iban(iban: &str) -> Result<String> {
+    let iban: String = iban.chars().filter(|c| *c != ' ').collect();
+    if iban.len() < 4
+        || !iban
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+    {
+        return Err(invalid("iban", "must be upper case letters and digits"));
+    }
+    let (country, bban) = (&iban[..2], &iban[4..]);
+    if !iban[2..4].chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid("iban", "check digits must be 2 digits"));
+    }
+    let Some((_, format)) = BBAN_FORMATS.iter().find(|(code, _)| *code == country) else {
+        return Err(invalid(
+            "iban",
+            &format!("country {country} is not supported"),
+        ));
+    };
+    let parts: Vec<(usize, char)> = format
+        .split(',')
+        .map(|part| {
+            let (length, kind) = part.split_at(part.len() - 1);
+            (
+                length.parse().expect("invalid BBAN format"),
+                kind.chars().next().unwrap(),
+            )
+        })
+        .collect();
+    let length: usize = 4 + parts.iter().map(|(length, _)| length).sum::<usize>();
+    if iban.len() != length {
+        return Err(invalid(
+            "iban",
+            &format!(
+                "length {} is not valid for {country}, expected {length}",
+                iban.len()
+            ),
+        ));
+    }
+    let mut rest = bban;
+    for (length, kind) in parts {
+        let (part, remaining) = rest.split_at(length);
+        let valid = match kind {
+            'n' => part.chars().all(|c| c.is_ascii_digit()),
+            'a' => part.chars().all(|c| c.is_ascii_uppercase()),
+            _ => true,
+        };
+        if !valid {
+            return Err(invalid(
+                "iban",
+                &format!("account details are not valid for {country}"),
+            ));
+        }
+        rest = remaining;
+    }
+    if mod97(&iban) != 1 {
+        return Err(invalid("iban", "fails checksum"));
+    }
+    Ok(iban)
+}
+
+/// ISO 13616 checksum, the first four characters are moved to the end and letters become 10 to 35
+fn mod97(iban: &str) -> u32 {
+    iban[4..]
+        .chars()
+        .chain(iban[..4].chars())
+        .filter_map(|c| c.to_digit(36))
+        .fold(0, |remainder, value| {
+            let shift = if value < 10 { 10 } else { 100 };
+            (remainder * shift + value) % 97
+        })
+}
+
+/// Checks a BIC is 8 or 11 characters: bank, country, location and optional branch code
+pub fn validate_bic(bic: &str) -> Result<String> {
+    if !BIC_REGEX.is_match(bic) {
+        return Err(invalid("bic", "must be 8 or 11 characters"));
+    }
+    Ok(bic.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_iban() {
+        let tests = [
+            ("GB82WEST12345698765432", Ok("GB82WEST12345698765432")),
+            ("GB82 WEST 1234 5698 7654 32", Ok("GB82WEST12345698765432")),
+            ("DE89370400440532013000", Ok("DE89370400440532013000")),
+            (
+                "FR1420041010050500013M02606",
+                Ok("FR1420041010050500013M02606"),
+            ),
+            ("NL91ABNA0417164300", Ok("NL91ABNA0417164300")),
+            (
+                "IT60X0542811101000000123456",
+                Ok("IT60X0542811101000000123456"),
+            ),
+            ("AD1200012030200359100100", Ok("AD1200012030200359100100")),
+            (
+                "AL47212110090000000235698741",
+                Ok("AL47212110090000000235698741"),
+            ),
+            ("GI75NWBK000000007099453", Ok("GI75NWBK000000007099453")),
+            ("MD24AG000225100013104168", Ok("MD24AG000225100013104168")),
+            ("ME25505000012345678951", Ok("ME25505000012345678951")),
+            ("MK07250120000058984", Ok("MK07250120000058984")),
+            ("VA59001123000012345678", Ok("VA59001123000012345678")),
+            (
+                "GB8AWEST12345698765432",
+                Err("Invalid iban: check digits must be 2 digits"),
+            ),
+            (
+                "GB82WEST12345698765433",
+                Err("Invalid iban: fails checksum"),
+            ),
+            (
+                "gb82west12345698765432",
+                Err("Invalid iban: must be upper case letters and digits"),
+            ),
+            (
+                "DE8937040044053201300",
+                Err("Invalid iban: length 21 is not valid for DE, expected 22"),
+            ),
+            (
+                "GB821EST12345698765432",
+                Err("Invalid iban: account details are not valid for GB"),
+            ),
+            (
+                "US64SVBKUS6S3300958879",
+                Err("Invalid iban: country US is not supported"),
+            ),
+        ];
+        for (i, (iban, expected)) in tests.into_iter().enumerate() {
+            let expected = expected
+                .map(String::from)
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, validate_iban(iban), "Case number {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_validate_bic() {
+        let tests = [
+            ("DEUTDEFF", true),
+            ("NWBKGB2L", true),
+            ("DEUTDEFF500", true),
+            ("DEUTDEF", false),
+            ("DEUTDEFF50", false),
+            ("deutdeff", false),
+            ("1EUTDEFF", false),
+        ];
+        for (bic, expected) in tests.into_iter() {
+            assert_eq!(expected, validate_bic(bic).is_ok(), "{bic}");
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use aes::{Aes128, Aes192, Aes256};
+use des::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    TdesEde2, TdesEde3,
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    mac::{self, MacAlgorithm},
+    GatewayError, Result,
+};
+
+/// The block cipher a key is used with
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyAlgorithm {
+    /// Double or triple length triple DES
+    Tdes,
+    Aes,
+}
+
+impl KeyAlgorithm {
+    pub fn block_size(&self) -> usize {
+        match self {
+            KeyAlgorithm::Tdes => 8,
+            KeyAlgorithm::Aes => 16,
+        }
+    }
+
+    fn key_lengths(&self) -> &'static [usize] {
+        match self {
+            KeyAlgorithm::Tdes => &[16, 24],
+            KeyAlgorithm::Aes => &[16, 24, 32],
+        }
+    }
+}
+
+/// Holds keys by name and encrypts with them without ever revealing them, the interface a
+/// hardware security module offers
+pub trait KeyStore: Send + Sync {
+    fn algorithm(&self, key_name: &str) -> Result<KeyAlgorithm>;
+
+    /// Encrypts whole blocks in ECB mode
+    fn encrypt(&self, key_name: &str, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// Decrypts whole blocks in ECB mode
+    fn decrypt(&self, key_name: &str, data: &[u8]) -> Result<Vec<u8>>;
+
+    fn generate_mac(&self, key_name: &str, algorithm: MacAlgorithm, data: &[u8])
+        -> Result<Vec<u8>>;
+}
+
+/// Keeps clear keys in memory, for tests and for deployments without an HSM
+#[derive(Default)]
+pub struct SoftwareKeyStore {
+    keys: HashMap<String, (KeyAlgorithm, Zeroizing<Vec<u8>>)>,
+}
+
+impl SoftwareKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the key called `key_name`
+    pub fn insert(&mut self, key_name: &str, algorithm: KeyAlgorithm, key: &[u8]) -> Result<()> {
+        if !algorithm.key_lengths().contains(&key.len()) {
+            return Err(GatewayError::KeyError(format!(
+                "{} byte keys cannot be used with {algorithm:?}",
+                key.len()
+            )));
+        }
+        self.keys
+            .insert(key_name.into(), (algorithm, Zeroizing::new(key.to_vec())));
+        Ok(())
+    }
+
+    pub(crate) fn key(&self, key_name: &str) -> Result<&(KeyAlgorithm, Zeroizing<Vec<u8>>)> {
+        self.keys
+            .get(key_name)
+            .ok_or_else(|| GatewayError::KeyError(format!("Unknown key {key_name}")))
+    }
+
+    fn apply(&self, key_name: &str, data: &[u8], encrypt: bool) -> Result<Vec<u8>> {
+        let (algorithm, key) = self.key(key_name)?;
+        if !data.len().is_multiple_of(algorithm.block_size()) {
+            return Err(GatewayError::KeyError(format!(
+                "Data must be a multiple of {} bytes for {algorithm:?}",
+                algorithm.block_size()
+            )));
+        }
+        let mut data = data.to_vec();
+        match (algorithm, key.len()) {
+            (KeyAlgorithm::Tdes, 16) => ecb::<TdesEde2>(key, &mut data, encrypt),
+            (KeyAlgorithm::Tdes, _) => ecb::<TdesEde3>(key, &mut data, encrypt),
+            (KeyAlgorithm::Aes, 16) => ecb::<Aes128>(key, &mut data, encrypt),
+            (KeyAlgorithm::Aes, 24) => ecb::<Aes192>(key, &mut data, encrypt),
+            (KeyAlgorithm::Aes, _) => ecb::<Aes256>(key, &mut data, encrypt),
+        }
+        Ok(data)
+    }
+}
+
+fn ecb<C: KeyInit + BlockEncrypt + BlockDecrypt>(key: &[u8], data: &mut [u8], encrypt: bool) {
+    let cipher = C::new_from_slice(key).expect("key length is checked on insert");
+    for block in data.chunks_mut(C::block_size()) {
+        let block = GenericArray::from_mut_slice(block);
+        if encrypt {
+            cipher.encrypt_block(block);
+        } else {
+            cipher.decrypt_block(block);
+        }
+    }
+}
+
+impl KeyStore for SoftwareKeyStore {
+    fn algorithm(&self, key_name: &str) -> Result<KeyAlgorithm> {
+        Ok(self.key(key_name)?.0)
+    }
+
+    fn encrypt(&self, key_name: &str, data: &[u8]) -> Result<Vec<u8>> {
+        self.apply(key_name, data, true)
+    }
+
+    fn decrypt(&self, key_name: &str, data: &[u8]) -> Result<Vec<u8>> {
+        self.apply(key_name, data, false)
+    }
+
+    fn generate_mac(
+        &self,
+        key_name: &str,
+        algorithm: MacAlgorithm,
+        data: &[u8],
+    ) -> Result<Vec<u8>> {
+        let (key_algorithm, key) = self.key(key_name)?;
+        if *key_algorithm != algorithm.algorithm() {
+            return Err(GatewayError::KeyError(format!(
+                "Key {key_name} is {key_algorithm:?} but {algorithm:?} needs {:?}",
+                algorithm.algorithm()
+            )));
+        }
+        mac::compute(algorithm, key, data)
+    }
+}
+
+static KEY_STORE: LazyLock<RwLock<Option<Arc<dyn KeyStore>>>> = LazyLock::new(|| RwLock::new(None));
+
+/// Sets the key store used to authenticate messages to and from acquirers
+pub fn install(key_store: Arc<dyn KeyStore>) {
+    *KEY_STORE.write().expect("key store poisoned") = Some(key_store);
+}
+
+pub(crate) fn installed() -> Result<Arc<dyn KeyStore>> {
+    KEY_STORE
+        .read()
+        .expect("key store poisoned")
+        .clone()
+        .ok_or(GatewayError::KeyError("No key store installed".into()))
+}
+
+/// A key store holding TDES keys named `tdes` and `ems.zpk`, an AES-128 key named `aes`, and
+/// the X9.24 test BDKs as `bdk` (TDES) and `aesbdk`
+#[cfg(test)]
+pub fn test_key_store() -> SoftwareKeyStore {
+    let mut key_store = SoftwareKeyStore::new();
+    let keys = [
+        (
+            "tdes",
+            KeyAlgorithm::Tdes,
+            "0123456789ABCDEFFEDCBA9876543210",
+        ),
+        (
+            "ems.zpk",
+            KeyAlgorithm::Tdes,
+            "0123456789ABCDEFFEDCBA9876543210",
+        ),
+        ("aes", KeyAlgorithm::Aes, "000102030405060708090A0B0C0D0E0F"),
+        (
+            "bdk",
+            KeyAlgorithm::Tdes,
+            "0123456789ABCDEFFEDCBA9876543210",
+        ),
+        (
+            "aesbdk",
+            KeyAlgorithm::Aes,
+            "FEDCBA9876543210F1F1F1F1F1F1F1F1",
+        ),
+    ];
+    for (key_name, algorithm, key) in keys {
+        key_store
+            .insert(key_name, algorithm, &hex::decode(key).unwrap())
+            .unwrap();
+    }
+    key_store
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_software_key_store() {
+        let key_store = test_key_store();
+        let tests = [
+            ("tdes", "041225EEEEEEEEEE", "2A3D408A1977DDE9"),
+            (
+                "aes",
+                "00112233445566778899AABBCCDDEEFF",
+                "69C4E0D86A7B0430D8CDB78070B4C55A",
+            ),
+        ];
+        for (i, (key_name, clear, encrypted)) in tests.into_iter().enumerate() {
+            let clear = hex::decode(clear).unwrap();
+            let actual = key_store.encrypt(key_name, &clear).unwrap();
+            assert_eq!(
+                encrypted,
+                hex::encode_upper(&actual),
+                "Case number {}",
+                i + 1
+            );
+            assert_eq!(Ok(clear), key_store.decrypt(key_name, &actual));
+        }
+
+        let tests = [
+            (key_store.encrypt("zpk", &[0; 8]), "Unknown key zpk"),
+            (
+                key_store.encrypt("aes", &[0; 8]),
+                "Data must be a multiple of 16 bytes for Aes",
+            ),
+            (
+                SoftwareKeyStore::new()
+                    .insert("tdes", KeyAlgorithm::Tdes, &[0; 8])
+                    .map(|_| vec![]),
+                "8 byte keys cannot be used with Tdes",
+            ),
+        ];
+        for (actual, expected) in tests.into_iter() {
+            assert_eq!(Err(GatewayError::KeyError(expected.into())), actual);
+        }
+    }
+}
@@ -1,11 +1,36 @@
+pub mod acquirer;
+pub mod authorisation;
 pub mod bank;
+pub mod bin_range;
+pub mod card;
+pub mod card_present;
+pub mod clock;
+pub mod iban;
+pub mod key_store;
+pub mod mac;
 pub mod merchant;
 pub mod messaging_specification;
+pub mod modulus;
+pub mod money;
 pub mod operation;
 pub mod operation_field;
 pub mod payment;
+pub mod pin_block;
+pub mod purchasing;
 pub mod transaction;
 pub mod currency;
+pub mod customer;
+pub mod dukpt;
+pub mod emv;
+pub mod fx;
+pub mod reversal;
+pub mod secret;
+pub mod simulator;
+pub mod soft_descriptor;
+pub mod sort_code;
+pub mod three_d_secure;
+pub mod transport;
+pub mod vault;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum GatewayError {
@@ -16,6 +41,34 @@ pub enum GatewayError {
 
     /// Raised when a value for an Operation field is invalid
     FieldError(String),
+
+    /// Raised when a message received from (or sent to) a bank cannot be decoded
+    DecodingError(String),
+
+    /// Raised when a bank does not respond within the allowed time
+    TimeoutError(String),
+
+    /// Raised when a request cannot be delivered to a bank
+    TransportError(String),
+
+    /// Raised when the connection to a bank fails after a request was sent, so the bank may
+    /// have processed it
+    ConnectionError(String),
+
+    /// Raised when an operation asks a bank for something it cannot process
+    CapabilityError(String),
+
+    /// Raised when a card cannot be tokenised or detokenised
+    VaultError(String),
+
+    /// Raised when a key is missing from a key store or cannot be used for an operation
+    KeyError(String),
+
+    /// Raised when a message's MAC is missing or does not match its contents
+    MacError(String),
+
+    /// Raised when a bank answers a request without approving it
+    DeclinedError(String),
 }
 type Result<T> = std::result::Result<T, GatewayError>;
 
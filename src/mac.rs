@@ -0,0 +1,215 @@
+use aes::{Aes128, Aes192, Aes256};
+use cmac::{Cmac, Mac};
+use des::{
+    cipher::{consts::U8, generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Des, TdesEde2, TdesEde3,
+};
+
+use crate::{
+    key_store::{KeyAlgorithm, KeyStore},
+    messaging_specification::MessagingSpecification,
+    GatewayError, Result,
+};
+
+type Block = GenericArray<u8, U8>;
+
+/// MACs are truncated to 8 bytes, the size of ISO 8583 fields 64 and 128
+pub const MAC_LENGTH: usize = 8;
+
+/// The algorithms acquirers authenticate messages with
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MacAlgorithm {
+    /// ISO 9797-1 algorithm 1, a CBC-MAC using TDES for every block
+    Iso9797Alg1,
+    /// ISO 9797-1 algorithm 3, the ANSI X9.19 retail MAC, single DES apart from the last block
+    Iso9797Alg3,
+    AesCmac,
+}
+
+impl MacAlgorithm {
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        match self {
+            MacAlgorithm::AesCmac => KeyAlgorithm::Aes,
+            _ => KeyAlgorithm::Tdes,
+        }
+    }
+}
+
+/// Where and how an acquirer wants its messages authenticated
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacConfig {
+    pub algorithm: MacAlgorithm,
+    /// The key store name of the MAC key shared with the acquirer
+    pub key_name: String,
+    /// The trailing field the MAC is sent in, after every field of the template
+    pub position: usize,
+}
+
+/// Computes the MAC of `data` with a clear `key`, ISO 9797-1 algorithms pad with zeros
+pub(crate) fn compute(algorithm: MacAlgorithm, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    if algorithm == MacAlgorithm::AesCmac {
+        let mac = match key.len() {
+            16 => <Cmac<Aes128> as Mac>::new_from_slice(key)
+                .map(|mac| mac.chain_update(data).finalize().into_bytes().to_vec()),
+            24 => <Cmac<Aes192> as Mac>::new_from_slice(key)
+                .map(|mac| mac.chain_update(data).finalize().into_bytes().to_vec()),
+            _ => <Cmac<Aes256> as Mac>::new_from_slice(key)
+                .map(|mac| mac.chain_update(data).finalize().into_bytes().to_vec()),
+        }
+        .map_err(|_| GatewayError::KeyError("Invalid AES-CMAC key".into()))?;
+        return Ok(mac[..MAC_LENGTH].to_vec());
+    }
+    let mut padded = data.to_vec();
+    padded.resize(data.len().div_ceil(8).max(1) * 8, 0);
+    let mac = match (algorithm, key.len()) {
+        (MacAlgorithm::Iso9797Alg1, 16) => {
+            let cipher = TdesEde2::new_from_slice(key).expect("key length is checked");
+            cbc_mac(&padded, |block| cipher.encrypt_block(block))
+        }
+        (MacAlgorithm::Iso9797Alg1, _) => {
+            let cipher = TdesEde3::new_from_slice(key).expect("key length is checked");
+            cbc_mac(&padded, |block| cipher.encrypt_block(block))
+        }
+        (_, 16) => {
+            let left = Des::new_from_slice(&key[..8]).expect("DES keys are 8 bytes");
+            let right = Des::new_from_slice(&key[8..]).expect("DES keys are 8 bytes");
+            let mut mac = cbc_mac(&padded, |block| left.encrypt_block(block));
+            right.decrypt_block(&mut mac);
+            left.encrypt_block(&mut mac);
+            mac
+        }
+        _ => {
+            return Err(GatewayError::KeyError(
+                "ISO 9797-1 algorithm 3 needs a double length key".into(),
+            ))
+        }
+    };
+    Ok(mac.to_vec())
+}
+
+fn cbc_mac(padded: &[u8], encrypt: impl Fn(&mut Block)) -> Block {
+    let mut mac = Block::default();
+    for block in padded.chunks(8) {
+        for (byte, other) in mac.iter_mut().zip(block) {
+            *byte ^= other;
+        }
+        encrypt(&mut mac);
+    }
+    mac
+}
+
+fn mac_field(
+    spec: MessagingSpecification,
+    config: &MacConfig,
+    key_store: &dyn KeyStore,
+    data: &str,
+) -> Result<String> {
+    let mac = key_store.generate_mac(
+        &config.key_name,
+        config.algorithm,
+        data.as_bytes(),
+    )?;
+    spec.encode_field(config.position, &hex::encode_upper(mac))
+}
+
+/// Appends the MAC of everything already encoded as the trailing field
+pub fn append_mac(
+    spec: MessagingSpecification,
+    config: &MacConfig,
+    key_store: &dyn KeyStore,
+    encoded: &mut String,
+) -> Result<()> {
+    let field = mac_field(spec, config, key_store, encoded)?;
+    encoded.push_str(&field);
+    Ok(())
+}
+
+/// Checks the trailing MAC field of a message, returning the message without it
+pub fn verify_mac<'a>(
+    spec: MessagingSpecification,
+    config: &MacConfig,
+    key_store: &dyn KeyStore,
+    encoded: &'a str,
+) -> Result<&'a str> {
+    // every MAC field has the same length, so the one we expect shows where the MAC starts
+    let field_length = spec
+        .encode_field(config.position, &"0".repeat(MAC_LENGTH * 2))?
+        .len();
+    let Some(split) = encoded.len().checked_sub(field_length) else {
+        return Err(GatewayError::MacError("Message has no MAC".into()));
+    };
+    if !encoded.is_char_boundary(split) {
+        return Err(GatewayError::MacError("Message has no MAC".into()));
+    }
+    let (data, received) = encoded.split_at(split);
+    let expected = mac_field(spec, config, key_store, data)?;
+    let matches = expected.len() == received.len()
+        && expected
+            .bytes()
+            .zip(received.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0;
+    if !matches {
+        return Err(GatewayError::MacError(
+            "Message MAC does not match its contents".into(),
+        ));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute() {
+        let tdes = hex::decode("0123456789ABCDEFFEDCBA9876543210").unwrap();
+        let aes = hex::decode("2B7E151628AED2A6ABF7158809CF4F3C").unwrap();
+        let tests = [
+            (
+                MacAlgorithm::Iso9797Alg3,
+                &tdes,
+                &b"Now is the time for all "[..],
+                "A1C72E74EA3FA9B6",
+            ),
+            (
+                MacAlgorithm::Iso9797Alg3,
+                &tdes,
+                b"0103abc0204AUTH",
+                "38706487CDEAA847",
+            ),
+            (
+                MacAlgorithm::Iso9797Alg1,
+                &tdes,
+                b"Now is the time for all ",
+                "93462A6DB9B4A4D1",
+            ),
+            (
+                MacAlgorithm::Iso9797Alg1,
+                &tdes,
+                b"0103abc0204AUTH",
+                "A1114DC628855D55",
+            ),
+            (
+                MacAlgorithm::AesCmac,
+                &aes,
+                &hex::decode("6BC1BEE22E409F96E93D7E117393172A").unwrap(),
+                "070A16B46B4D4144",
+            ),
+        ];
+        for (i, (algorithm, key, data, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(
+                expected,
+                hex::encode_upper(compute(algorithm, key, data).unwrap()),
+                "Case number {}",
+                i + 1
+            );
+        }
+        assert_eq!(
+            Err(GatewayError::KeyError(
+                "ISO 9797-1 algorithm 3 needs a double length key".into()
+            )),
+            compute(MacAlgorithm::Iso9797Alg3, &[0; 24], b"data")
+        );
+    }
+}
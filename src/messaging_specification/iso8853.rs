@@ -1,12 +1,18 @@
 #![allow(non_snake_case)]
 
+use std::collections::HashMap;
+
 use super::{
-    bitmap, string_field, BitField, EncodingContext, OperationParseResult, OperationParser,
+    bitmap, string_field, BitField, BitMap, EncodingContext, OperationParseResult,
+    OperationParser, ResponseField,
 };
 use crate::{
+    card::{CardNetwork, CardNumber},
     map,
     operation::{Operation, RequestType},
     payment::Payment,
+    purchasing::MAX_ADDENDUM_LENGTH,
+    vault, GatewayError, Result,
 };
 
 bitmap! {ISO8853_BITMAP_TEMPLATE,
@@ -17,6 +23,9 @@ bitmap! {ISO8853_BITMAP_TEMPLATE,
         2 => (Network as OperationParser, 1, 1, None),
         3 => (ExpiryDate as OperationParser, 4, 6, Some('0')),
         4 => (CVV as OperationParser, 3, 4, Some('0')),
+        5 => (Iban as OperationParser, 15, 34, None),
+        6 => (Bic as OperationParser, 8, 11, None),
+        7 => (WalletType as OperationParser, 1, 1, None),
     },
     4 => map!{ // Transaction details
         1 => (TransactionAmount as OperationParser, 10, 20, Some('0')),
@@ -26,8 +35,57 @@ bitmap! {ISO8853_BITMAP_TEMPLATE,
     5 => map!{ // Merchant details
         1 => (MerchantID as OperationParser, 16, 16, Some('0')),
     },
+    6 => (TraceNumber as OperationParser, 6, 6, Some('0')),
+    7 => (OriginalTraceNumber as OperationParser, 6, 6, Some('0')),
+    8 => map!{ // Authentication, from 3-D Secure or a wallet cryptogram
+        1 => (AuthenticationStatus as OperationParser, 1, 1, None),
+        2 => (Eci as OperationParser, 2, 2, None),
+        3 => (Cavv as OperationParser, 28, 28, None),
+        4 => (DsTransactionId as OperationParser, 36, 36, None),
+        5 => (ThreeDSecureVersion as OperationParser, 5, 5, None),
+    },
+    9 => map!{ // Card present
+        1 => (PosEntryMode as OperationParser, 2, 2, None),
+        2 => (Track2 as OperationParser, 0, 37, None),
+        3 => (TerminalId as OperationParser, 8, 8, None),
+        4 => (TerminalCapabilities as OperationParser, 1, 5, None),
+        5 => (Cvm as OperationParser, 1, 1, None),
+    },
+    10 => BitField::Tlv { parser: IccData as OperationParser, max_length: 510 },
+    11 => map!{ // Online PIN
+        1 => (PinBlockFormat as OperationParser, 1, 1, None),
+        2 => (PinBlock as OperationParser, 16, 32, None),
+    },
+    12 => map!{ // Dynamic currency conversion
+        1 => (CardholderAmount as OperationParser, 10, 20, Some('0')),
+        2 => (CardholderCurrency as OperationParser, 3, 3, None),
+        3 => (ConversionRate as OperationParser, 8, 8, None),
+        4 => (DccMarkup as OperationParser, 4, 4, Some('0')),
+    },
+    13 => map!{ // Address verification
+        1 => (AvsPostcode as OperationParser, 1, 10, None),
+        2 => (AvsAddress as OperationParser, 1, 20, None),
+        3 => (AvsCountry as OperationParser, 2, 2, None),
+    },
+    14 => BitField::Tlv { parser: PurchasingAddendum as OperationParser, max_length: MAX_ADDENDUM_LENGTH },
+    15 => map!{ // Statement details
+        1 => (OrderReference as OperationParser, 1, 25, None),
+        2 => (DescriptorName as OperationParser, 1, 25, None),
+        3 => (DescriptorCity as OperationParser, 1, 13, None),
+        4 => (DescriptorPhone as OperationParser, 1, 13, None),
+    },
 }
 
+pub static ISO8853_RESPONSE_LAYOUT: &[(usize, ResponseField)] = &[
+    (1, ResponseField::TransactionIdentifier),
+    (2, ResponseField::ResponseCode),
+    (3, ResponseField::AuthCode),
+    (4, ResponseField::Message),
+    (5, ResponseField::AvsResult),
+    (6, ResponseField::CvvResult),
+    (7, ResponseField::BankReference),
+];
+
 type Iso8853BitField = (OperationParser, usize, usize, Option<char>);
 
 impl From<Iso8853BitField> for BitField {
@@ -41,13 +99,71 @@ impl From<Iso8853BitField> for BitField {
     }
 }
 
-pub fn iso8853_string_field(data: &mut String, ctx: EncodingContext, _field: &BitField) {
+pub(super) fn iso8853_string_field(data: &mut String, ctx: EncodingContext, field: &BitField) {
     let pos = ctx.position.unwrap();
-    string_field(data, ctx, _field);
-    data.insert_str(0, &format!("{:0>2}", data.len()));
+    string_field(data, ctx, field);
+    data.insert_str(0, &format!("{:0>1$}", data.len(), length_digits(field)));
     data.insert_str(0, &format!("{:0>2}", pos));
 }
 
+/// TLV fields are LLLVAR as they can be longer than 99 characters
+fn length_digits(field: &BitField) -> usize {
+    match field {
+        BitField::Tlv { .. } => 3,
+        _ => 2,
+    }
+}
+
+pub fn iso8853_decode(encoded: &str, template: &BitMap) -> Result<HashMap<String, String>> {
+    let mut decoded = HashMap::new();
+    _decode(encoded, template, None, &mut decoded)?;
+    Ok(decoded)
+}
+
+fn _decode(
+    encoded: &str,
+    template: &BitMap,
+    prefix: Option<usize>,
+    decoded: &mut HashMap<String, String>,
+) -> Result<()> {
+    let mut rest = encoded;
+    while !rest.is_empty() {
+        let truncated = || GatewayError::DecodingError(format!("truncated field header '{rest}'"));
+        if rest.len() < 4 {
+            return Err(truncated());
+        }
+        // network input may not be ASCII, so slicing by byte index could split a character
+        let pos = rest.get(0..2).ok_or_else(truncated)?;
+        let pos: usize = pos.parse().map_err(|_| {
+            GatewayError::DecodingError(format!("invalid field position '{pos}'"))
+        })?;
+        let header_length = 2 + template.get(&pos).map_or(2, length_digits);
+        let len = rest.get(2..header_length).ok_or_else(truncated)?;
+        let len: usize = len.parse().map_err(|_| {
+            GatewayError::DecodingError(format!("invalid length '{len}' for field '{pos}'"))
+        })?;
+        let data = rest
+            .get(header_length..header_length + len)
+            .ok_or(GatewayError::DecodingError(format!(
+                "field '{pos}' shorter than its length ({len})"
+            )))?;
+        let key = match prefix {
+            Some(prefix) => format!("{prefix}.{pos}"),
+            None => pos.to_string(),
+        };
+        match template.get(&pos) {
+            Some(BitField::Map(nested)) if prefix.is_none() => {
+                _decode(data, nested, Some(pos), decoded)?
+            }
+            _ => {
+                decoded.insert(key, data.to_string());
+            }
+        }
+        rest = &rest[header_length + len..];
+    }
+    Ok(())
+}
+
 pub fn TransactionIdentifier(_: &Operation) -> OperationParseResult {
     Ok(Some("abc".into()))
 }
@@ -61,37 +177,170 @@ pub fn MerchantID(op: &Operation) -> OperationParseResult {
 pub fn RequestType(op: &Operation) -> OperationParseResult {
     let rt = match op.request_type.expect("TODO handle") {
         RequestType::Auth => "AUTH",
-        RequestType::Refund => todo!(),
-        RequestType::AccountCheck => todo!(),
+        RequestType::Reversal => "RVSL",
+        request_type => {
+            return Err(GatewayError::EncodingError(format!(
+                "{request_type:?} requests cannot be encoded yet"
+            )))
+        }
     }
     .into();
     Ok(Some(rt))
 }
 
+pub fn TraceNumber(op: &Operation) -> OperationParseResult {
+    Ok(op.trace_number.clone())
+}
+
+pub fn OriginalTraceNumber(op: &Operation) -> OperationParseResult {
+    Ok(op.original_trace_number.clone())
+}
+
 pub fn AccountNumber(op: &Operation) -> OperationParseResult {
     match op.payment.as_ref().expect("TODO handle") {
-        Payment::Card { pan, .. } => Ok(Some(pan.into())),
-        Payment::Account { account_number, .. } => Ok(Some(account_number.into())),
+        Payment::Card { number, .. } => match number {
+            CardNumber::Pan(pan) => Ok(Some(pan.reveal().into())),
+            // tokens are only swapped back for the PAN at the last moment
+            CardNumber::Token(token) => Ok(Some(vault::detokenise(
+                token,
+                &op.merchant.as_ref().expect("TODO handle").mid,
+            )?)),
+        },
+        Payment::Account { account_number, .. } => Ok(Some(account_number.reveal().into())),
+        Payment::Wallet { dpan, .. } => Ok(Some(dpan.reveal().into())),
+        Payment::Iban { .. } => Ok(None),
+    }
+}
+
+pub fn WalletType(op: &Operation) -> OperationParseResult {
+    match op.payment.as_ref().expect("TODO handle") {
+        Payment::Wallet { wallet, .. } => Ok(Some(wallet.code().into())),
+        _ => Ok(None),
+    }
+}
+
+pub fn AuthenticationStatus(op: &Operation) -> OperationParseResult {
+    Ok(op.three_d_secure.as_ref().map(|tds| tds.status.code().into()))
+}
+
+pub fn Eci(op: &Operation) -> OperationParseResult {
+    match op.payment.as_ref() {
+        Some(Payment::Wallet { eci, .. }) => Ok(Some(eci.clone())),
+        _ => Ok(op.three_d_secure.as_ref().map(|tds| tds.eci.clone())),
+    }
+}
+
+/// The wallet's cryptogram goes where the 3-D Secure CAVV would
+pub fn Cavv(op: &Operation) -> OperationParseResult {
+    match op.payment.as_ref() {
+        Some(Payment::Wallet { cryptogram, .. }) => Ok(Some(cryptogram.reveal().into())),
+        _ => Ok(op.three_d_secure.as_ref().and_then(|tds| tds.cavv.clone())),
+    }
+}
+
+pub fn DsTransactionId(op: &Operation) -> OperationParseResult {
+    Ok(op.three_d_secure.as_ref().map(|tds| tds.ds_transaction_id.clone()))
+}
+
+pub fn ThreeDSecureVersion(op: &Operation) -> OperationParseResult {
+    Ok(op.three_d_secure.as_ref().map(|tds| tds.protocol_version.clone()))
+}
+
+pub fn PosEntryMode(op: &Operation) -> OperationParseResult {
+    Ok(op.card_present.as_ref().map(|cp| cp.entry_mode.code().into()))
+}
+
+pub fn Track2(op: &Operation) -> OperationParseResult {
+    Ok(op
+        .card_present
+        .as_ref()
+        .and_then(|cp| cp.track2.as_ref())
+        .map(|track2| track2.data.reveal().into()))
+}
+
+pub fn TerminalId(op: &Operation) -> OperationParseResult {
+    Ok(op.card_present.as_ref().map(|cp| cp.terminal_id.clone()))
+}
+
+pub fn TerminalCapabilities(op: &Operation) -> OperationParseResult {
+    Ok(op.card_present.as_ref().map(|cp| cp.terminal_capabilities.code()))
+}
+
+pub fn Cvm(op: &Operation) -> OperationParseResult {
+    Ok(op.card_present.as_ref().map(|cp| cp.cvm.code().into()))
+}
+
+/// The chip's EMV tags as hex, sent in field 10 where ISO 8583 would use field 55
+pub fn IccData(op: &Operation) -> OperationParseResult {
+    Ok(op
+        .card_present
+        .as_ref()
+        .and_then(|cp| cp.emv.as_ref())
+        .map(|emv| emv.to_hex()))
+}
+
+pub fn PinBlockFormat(op: &Operation) -> OperationParseResult {
+    Ok(op
+        .card_present
+        .as_ref()
+        .and_then(|cp| cp.pin_block.as_ref())
+        .map(|pin_block| pin_block.format.code().into()))
+}
+
+/// The encrypted PIN block as hex, ISO 8583 field 52
+pub fn PinBlock(op: &Operation) -> OperationParseResult {
+    Ok(op
+        .card_present
+        .as_ref()
+        .and_then(|cp| cp.pin_block.as_ref())
+        .map(|pin_block| pin_block.to_hex()))
+}
+
+pub fn Iban(op: &Operation) -> OperationParseResult {
+    match op.payment.as_ref().expect("TODO handle") {
+        Payment::Iban { iban, .. } => Ok(Some(iban.reveal().into())),
+        _ => Ok(None),
+    }
+}
+
+pub fn Bic(op: &Operation) -> OperationParseResult {
+    match op.payment.as_ref().expect("TODO handle") {
+        Payment::Iban { bic, .. } => Ok(bic.clone()),
+        _ => Ok(None),
     }
 }
 
 pub fn Network(op: &Operation) -> OperationParseResult {
     match op.payment.as_ref().expect("TODO handle") {
-        Payment::Card { network, .. } => Ok(Some(network[..1].into())),
+        Payment::Card { bin, .. } | Payment::Wallet { bin, .. } => Ok(Some(
+            match bin.network {
+                CardNetwork::Visa => "V",
+                CardNetwork::Mastercard => "M",
+                CardNetwork::Maestro => "E",
+                CardNetwork::Amex => "A",
+                CardNetwork::Discover => "D",
+            }
+            .into(),
+        )),
         _ => Ok(None),
     }
 }
 
 pub fn ExpiryDate(op: &Operation) -> OperationParseResult {
     match op.payment.as_ref().expect("TODO handle") {
-        Payment::Card { expiry_date, .. } => Ok(Some(expiry_date.replace("/", ""))),
+        Payment::Card { expiry_date, .. } | Payment::Wallet { expiry_date, .. } => {
+            Ok(Some(expiry_date.mmyyyy()))
+        }
         _ => Ok(None),
     }
 }
 
 pub fn CVV(op: &Operation) -> OperationParseResult {
     match op.payment.as_ref().expect("TODO handle") {
-        Payment::Card { security_code, .. } => Ok(Some(security_code.into())),
+        // swiped cards have no security code
+        Payment::Card { security_code, .. } => {
+            Ok(security_code.as_ref().map(|code| code.reveal().into()))
+        }
         _ => Ok(None),
     }
 }
@@ -102,6 +351,7 @@ pub fn TransactionAmount(op: &Operation) -> OperationParseResult {
             .as_ref()
             .expect("TODO handle")
             .amount
+            .minor_units()
             .to_string(),
     ))
 }
@@ -121,26 +371,509 @@ pub fn Currency(op: &Operation) -> OperationParseResult {
         op.transaction
             .as_ref()
             .expect("TODO handle")
-            .currency
+            .amount
+            .currency()
             .to_string(),
     ))
 }
 
+/// The ISO 4217 numeric code, for acquirer templates that send it in place of the alphabetic code
+pub fn CurrencyNumeric(op: &Operation) -> OperationParseResult {
+    Ok(op
+        .transaction
+        .as_ref()
+        .map(|transaction| transaction.amount.currency().numeric_code()))
+}
+
+/// Whether the operation's bank can take it, anything a bank does not support is left out of the message
+fn bank_supports(op: &Operation, supports: fn(&crate::acquirer::Capabilities) -> bool) -> bool {
+    op.bank
+        .and_then(|bank| bank.capabilities().ok())
+        .is_some_and(|capabilities| supports(&capabilities))
+}
+
+/// The accepted DCC quote, for the banks that offer DCC
+fn dcc_quote(op: &Operation) -> Option<&crate::fx::DccQuote> {
+    op.dcc
+        .as_ref()
+        .filter(|_| bank_supports(op, |capabilities| capabilities.dcc))
+}
+
+pub fn CardholderAmount(op: &Operation) -> OperationParseResult {
+    Ok(dcc_quote(op).map(|dcc| dcc.cardholder_amount.minor_units().to_string()))
+}
+
+pub fn CardholderCurrency(op: &Operation) -> OperationParseResult {
+    Ok(dcc_quote(op).map(|dcc| dcc.cardholder_amount.currency().to_string()))
+}
+
+/// The applied rate in the ISO 8583 field 10 layout
+pub fn ConversionRate(op: &Operation) -> OperationParseResult {
+    dcc_quote(op).map(|dcc| dcc.rate.to_iso8583()).transpose()
+}
+
+/// The markup in basis points, which card schemes require to be disclosed
+pub fn DccMarkup(op: &Operation) -> OperationParseResult {
+    Ok(dcc_quote(op).map(|dcc| dcc.markup_basis_points.to_string()))
+}
+
+fn billing_address(op: &Operation) -> Option<&crate::customer::Address> {
+    op.transaction
+        .as_ref()
+        .and_then(|transaction| transaction.customer.as_ref())
+        .and_then(|customer| customer.billing_address.as_ref())
+}
+
+pub fn AvsPostcode(op: &Operation) -> OperationParseResult {
+    Ok(billing_address(op).map(|address| address.postcode.clone()))
+}
+
+/// The start of the billing address, only the first 20 characters are verified
+pub fn AvsAddress(op: &Operation) -> OperationParseResult {
+    Ok(billing_address(op).map(|address| address.first_line().chars().take(20).collect()))
+}
+
+pub fn AvsCountry(op: &Operation) -> OperationParseResult {
+    Ok(billing_address(op).map(|address| address.country.clone()))
+}
+
+/// Level 2 and 3 data, for the banks that take it
+pub fn PurchasingAddendum(op: &Operation) -> OperationParseResult {
+    let Some(data) = op
+        .transaction
+        .as_ref()
+        .and_then(|transaction| transaction.purchasing_data.as_ref())
+    else {
+        return Ok(None);
+    };
+    if !bank_supports(op, |capabilities| capabilities.purchasing_data) {
+        return Ok(None);
+    }
+    data.to_iso8583().map(Some)
+}
+
+pub fn OrderReference(op: &Operation) -> OperationParseResult {
+    Ok(op.order_reference.clone())
+}
+
+pub fn DescriptorName(op: &Operation) -> OperationParseResult {
+    Ok(op.soft_descriptor.as_ref().map(|descriptor| descriptor.name.clone()))
+}
+
+pub fn DescriptorCity(op: &Operation) -> OperationParseResult {
+    Ok(op.soft_descriptor.as_ref().and_then(|descriptor| descriptor.city.clone()))
+}
+
+pub fn DescriptorPhone(op: &Operation) -> OperationParseResult {
+    Ok(op.soft_descriptor.as_ref().and_then(|descriptor| descriptor.phone.clone()))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::operation::example_operation;
+    use std::sync::Arc;
+
+    use crate::{
+        clock::test_clock, messaging_specification::MessagingSpecification,
+        operation::example_operation, three_d_secure::ThreeDSecure, vault::test_vault,
+    };
 
     use super::*;
 
+    #[test]
+    fn test_AccountNumber() {
+        let vault = Arc::new(test_vault("iso8853"));
+        let mut op = example_operation();
+        op.tokenise(vault.as_ref()).unwrap();
+        assert_ne!(example_operation().payment, op.payment);
+        assert_eq!(
+            Ok(Some("4111111111111111".to_string())),
+            vault::with_vault(vault.clone(), || AccountNumber(&op))
+        );
+        assert_eq!(example_operation().encode(), op.encode_with_vault(vault));
+        assert_eq!(
+            Err(GatewayError::VaultError("No vault installed".into())),
+            AccountNumber(&op)
+        );
+    }
+
+    #[test]
+    fn test_Iban_and_Bic() {
+        let op = Operation {
+            payment: Some(
+                Payment::iban("DE89370400440532013000", Some("DEUTDEFF"), "Ben Jones").unwrap(),
+            ),
+            ..example_operation()
+        };
+        assert_eq!(Ok(None), AccountNumber(&op));
+        assert_eq!(Ok(Some("DE89370400440532013000".into())), Iban(&op));
+        assert_eq!(Ok(Some("DEUTDEFF".into())), Bic(&op));
+        assert_eq!(
+            Ok("0103abc0204AUTH03380522DE893704004405320130000608DEUTDEFF0434011000000123450203GBP0309Ben Jones052001160000104912345678".to_string()),
+            op.encode()
+        );
+    }
+
+    #[test]
+    fn test_ThreeDSecure() {
+        let op = Operation {
+            three_d_secure: Some(
+                ThreeDSecure::new(
+                    CardNetwork::Visa,
+                    crate::three_d_secure::AuthenticationStatus::Authenticated,
+                    "05",
+                    Some("AAABBEg0VhI0VniQEjRWAAAAAAA="),
+                    "f25084f0-5b16-4c0a-ae5d-b24808a95e4b",
+                    "2.2.0",
+                )
+                .unwrap(),
+            ),
+            ..example_operation()
+        };
+        assert_eq!(
+            Ok("0103abc0204AUTH0342011641111111111111110201V030612202404031230434011000000123450203GBP0309Ben Jones05200116000010491234567808920101Y0202050328AAABBEg0VhI0VniQEjRWAAAAAAA=0436f25084f0-5b16-4c0a-ae5d-b24808a95e4b05052.2.0".to_string()),
+            op.encode()
+        );
+    }
+
+    #[test]
+    fn test_Wallet() {
+        let op = Operation {
+            payment: Some(
+                Payment::wallet_at(
+                    "4111111111111111",
+                    "12/24",
+                    "AgAAAAAABk4DWZ4C28yUQAAAAAA=",
+                    "05",
+                    crate::card::WalletType::ApplePay,
+                    &test_clock(),
+                )
+                .unwrap(),
+            ),
+            ..example_operation()
+        };
+        assert_eq!(Ok(None), CVV(&op));
+        assert_eq!(
+            Ok("0103abc0204AUTH0340011641111111111111110201V03061220240701A0434011000000123450203GBP0309Ben Jones05200116000010491234567808380202050328AgAAAAAABk4DWZ4C28yUQAAAAAA=".to_string()),
+            op.encode()
+        );
+    }
+
+    #[test]
+    fn test_CardPresent() {
+        use crate::card_present;
+
+        let track2: card_present::Track2 = "4111111111111111=24122011234567890".parse().unwrap();
+        let op = Operation {
+            payment: Some(Payment::swiped_card_at(&track2, "Ben Jones", &test_clock()).unwrap()),
+            card_present: Some(
+                card_present::CardPresent::new(
+                    card_present::PosEntryMode::Chip,
+                    Some(track2),
+                    "TERM0001",
+                    card_present::TerminalCapabilities {
+                        entry_modes: vec![card_present::PosEntryMode::Chip],
+                        pin_entry: true,
+                    },
+                    card_present::Cvm::OfflinePin,
+                    Some(crate::emv::EmvData::from_hex(crate::emv::TEST_EMV_DATA).unwrap()),
+                )
+                .unwrap(),
+            ),
+            ..example_operation()
+        };
+        assert_eq!(Ok(None), CVV(&op));
+        assert_eq!(
+            Ok("0103abc0204AUTH0335011641111111111111110201V03061220240434011000000123450203GBP0309Ben Jones052001160000104912345678096701020502344111111111111111=241220112345678900308TERM00010402CP05012101409F2608C3A5B2D1E4F607189F2701809F100706010A03A000009F3704A1B2C3D4950500000080009A032406159C01005F2A020826820218009F360200019F0206000000012345".to_string()),
+            op.encode()
+        );
+        let decoded = iso8853_decode(&op.encode().unwrap(), &ISO8853_BITMAP_TEMPLATE).unwrap();
+        assert_eq!(Some(&crate::emv::TEST_EMV_DATA.to_string()), decoded.get("10"));
+        assert_eq!(Some(&"TERM0001".to_string()), decoded.get("9.3"));
+        assert_eq!(None, decoded.get("11.2"));
+
+        // the ICC data needs a 3 digit length, so cannot go inside a map
+        let template = crate::map! {
+            9 => BitField::from(crate::map! {
+                1 => BitField::Tlv { parser: IccData as OperationParser, max_length: 510 },
+            }),
+        };
+        assert_eq!(
+            Err(GatewayError::EncodingError(
+                "TLV field '9.1' cannot be nested".into()
+            )),
+            MessagingSpecification::Iso8853.encode_using_template(&op, &template)
+        );
+    }
+
+    #[test]
+    fn test_PinBlock() {
+        use crate::{card_present, key_store::test_key_store, pin_block, secret::Secret};
+
+        let track2: card_present::Track2 = "4111111111111111=24122011234567890".parse().unwrap();
+        let pin_block = pin_block::EncryptedPinBlock::new(
+            &test_key_store(),
+            "ems.zpk",
+            pin_block::PinBlockFormat::Iso0,
+            &Secret::new("1234"),
+            &track2.pan,
+        )
+        .unwrap();
+        let op = Operation {
+            payment: Some(Payment::swiped_card_at(&track2, "Ben Jones", &test_clock()).unwrap()),
+            card_present: Some(
+                card_present::CardPresent::new(
+                    card_present::PosEntryMode::Swipe,
+                    Some(track2),
+                    "TERM0001",
+                    card_present::TerminalCapabilities {
+                        entry_modes: vec![card_present::PosEntryMode::Swipe],
+                        pin_entry: true,
+                    },
+                    card_present::Cvm::OnlinePin,
+                    None,
+                )
+                .unwrap()
+                .with_pin_block(pin_block)
+                .unwrap(),
+            ),
+            ..example_operation()
+        };
+        assert_eq!(Ok(Some("0".into())), PinBlockFormat(&op));
+        assert_eq!(Ok(Some("2A3D408A1977DDE9".into())), PinBlock(&op));
+        let decoded = iso8853_decode(&op.encode().unwrap(), &ISO8853_BITMAP_TEMPLATE).unwrap();
+        assert_eq!(Some(&"2A3D408A1977DDE9".to_string()), decoded.get("11.2"));
+        assert_eq!(None, decoded.get("10"));
+
+        let mut op = op;
+        if let Some(pin_block) = op.card_present.as_mut().and_then(|cp| cp.pin_block.as_mut()) {
+            pin_block.key_name = "tdes".into();
+        }
+        assert_eq!(
+            Err(GatewayError::EncodingError(
+                "PIN block is encrypted under tdes rather than ems.zpk".into()
+            )),
+            op.encode()
+        );
+    }
+
     #[test]
     fn test_Currency() {
-        let tests = [(example_operation(), "GBP".to_string())];
-        for (op, expected) in tests.into_iter() {
-            let actual = Currency(&op).unwrap().unwrap();
+        let mut yen = example_operation();
+        yen.transaction.as_mut().unwrap().amount =
+            crate::money::Money::new(12345, crate::currency::Currency::JPY);
+        let tests = [
+            (example_operation(), ("GBP", "826")),
+            (yen, ("JPY", "392")),
+        ];
+        for (op, (code, numeric)) in tests.into_iter() {
+            assert_eq!(Ok(Some(code.to_string())), Currency(&op));
+            assert_eq!(Ok(Some(numeric.to_string())), CurrencyNumeric(&op));
+        }
+    }
+
+    #[test]
+    fn test_Dcc() {
+        let quote = crate::fx::DccQuote::new_at(
+            &crate::fx::test_rate_provider(),
+            crate::money::Money::new(12345, crate::currency::Currency::GBP),
+            crate::currency::Currency::EUR,
+            350,
+            &test_clock(),
+        )
+        .unwrap();
+        let op = example_operation().with_dcc_at(quote, &test_clock()).unwrap();
+        assert_eq!(Ok(Some("14949".into())), CardholderAmount(&op));
+        assert_eq!(Ok(Some("EUR".into())), CardholderCurrency(&op));
+        assert_eq!(Ok(Some("61210950".into())), ConversionRate(&op));
+        assert_eq!(Ok(Some("350".into())), DccMarkup(&op));
+        let encoded = op.encode_at(&test_clock()).unwrap();
+        let decoded = iso8853_decode(&encoded, &ISO8853_BITMAP_TEMPLATE).unwrap();
+        assert_eq!(Some(&"0000012345".to_string()), decoded.get("4.1"));
+        assert_eq!(Some(&"0000014949".to_string()), decoded.get("12.1"));
+        assert_eq!(Some(&"EUR".to_string()), decoded.get("12.2"));
+        assert_eq!(Some(&"61210950".to_string()), decoded.get("12.3"));
+        assert_eq!(Some(&"0350".to_string()), decoded.get("12.4"));
+        assert_eq!(Ok(None), ConversionRate(&example_operation()));
+
+        let cardnet = Operation { bank: Some(crate::bank::Bank::Cardnet), ..op };
+        assert_eq!(Ok(None), CardholderAmount(&cardnet));
+        assert_eq!(Ok(None), CardholderCurrency(&cardnet));
+        assert_eq!(Ok(None), ConversionRate(&cardnet));
+        assert_eq!(Ok(None), DccMarkup(&cardnet));
+    }
+
+    #[test]
+    fn test_Avs() {
+        use crate::customer::{test_address, Address, Customer};
+
+        let with_address = |address: Address| {
+            let mut op = example_operation();
+            let transaction = op.transaction.take().unwrap();
+            op.transaction = Some(transaction.with_customer(
+                Customer::new(Some("ben@example.com"), None)
+                    .unwrap()
+                    .with_billing_address(address)
+                    .with_shipping_address(test_address()),
+            ));
+            op
+        };
+        let long_street = Address::new(
+            "Flat 3",
+            Some("Buckingham Palace Road"),
+            None,
+            None,
+            "SW1W 0PP",
+            "GB",
+        )
+        .unwrap();
+        let tests = [
+            (with_address(test_address()), Some(("SW1A 2AA", "10 Downing Street", "GB"))),
+            (with_address(long_street), Some(("SW1W 0PP", "Flat 3 Buckingham Pa", "GB"))),
+            (example_operation(), None),
+        ];
+        for (i, (op, expected)) in tests.into_iter().enumerate() {
+            let decoded = iso8853_decode(&op.encode().unwrap(), &ISO8853_BITMAP_TEMPLATE).unwrap();
+            let actual = ["13.1", "13.2", "13.3"].map(|key| decoded.get(key).cloned());
+            let expected = match expected {
+                Some((postcode, address, country)) => {
+                    [postcode, address, country].map(|value| Some(value.to_string()))
+                }
+                None => [None, None, None],
+            };
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_PurchasingAddendum() {
+        use crate::{bank::Bank, purchasing::test_purchasing_data};
+
+        let mut op = example_operation();
+        op.transaction = op.transaction.map(|transaction| {
+            transaction.with_purchasing_data(test_purchasing_data()).unwrap()
+        });
+        let addendum = PurchasingAddendum(&op).unwrap().unwrap();
+        assert_eq!(
+            "0107PO-12340205CC-420304205810450108141115070208A4 paper03015040410000504100011420108441031030205Toner030110404528705041058",
+            addendum
+        );
+        let decoded = iso8853_decode(&op.encode().unwrap(), &ISO8853_BITMAP_TEMPLATE).unwrap();
+        assert_eq!(Some(&addendum), decoded.get("14"));
+        let fields = iso8853_decode(&addendum, &BitMap::new()).unwrap();
+        assert_eq!(Some(&"PO-1234".to_string()), fields.get("1"));
+        let item = iso8853_decode(&fields["11"], &BitMap::new()).unwrap();
+        assert_eq!(Some(&"Toner".to_string()), item.get("2"));
+        assert_eq!(Some(&"5287".to_string()), item.get("4"));
+
+        // banks that cannot take the addendum still get the transaction
+        op.bank = Some(Bank::Cardnet);
+        assert_eq!(Ok(None), PurchasingAddendum(&op));
+        assert_eq!(Ok(None), PurchasingAddendum(&example_operation()));
+    }
+
+    #[test]
+    fn test_SoftDescriptor() {
+        use crate::soft_descriptor::SoftDescriptor;
+
+        let op = Operation {
+            order_reference: Some("ORD-2024-0001".into()),
+            soft_descriptor: Some(
+                SoftDescriptor::new(
+                    Some(CardNetwork::Visa),
+                    "Acme*Order 42",
+                    None,
+                    Some("02079460000"),
+                )
+                .unwrap(),
+            ),
+            ..example_operation()
+        };
+        assert_eq!(
+            Ok("0103abc0204AUTH0342011641111111111111110201V030612202404031230434011000000123450203GBP0309Ben Jones05200116000010491234567815490113ORD-2024-00010213ACME*ORDER 42041102079460000".to_string()),
+            op.encode()
+        );
+        let decoded = iso8853_decode(&op.encode().unwrap(), &ISO8853_BITMAP_TEMPLATE).unwrap();
+        assert_eq!(Some(&"ACME*ORDER 42".to_string()), decoded.get("15.2"));
+        assert_eq!(None, decoded.get("15.3"));
+        assert_eq!(Ok(None), OrderReference(&example_operation()));
+    }
+
+    #[test]
+    fn test_iso8853_decode() {
+        let tests = [
+            (
+                "0103abc0204AUTH0342011651000000000000000201M030612202404031230434011000000123450203GBP0309Ben Jones052001160000104912345678",
+                Ok(map! {
+                    "1".to_string() => "abc".to_string(),
+                    "2".to_string() => "AUTH".to_string(),
+                    "3.1".to_string() => "5100000000000000".to_string(),
+                    "3.2".to_string() => "M".to_string(),
+                    "3.3".to_string() => "122024".to_string(),
+                    "3.4".to_string() => "123".to_string(),
+                    "4.1".to_string() => "0000012345".to_string(),
+                    "4.2".to_string() => "GBP".to_string(),
+                    "4.3".to_string() => "Ben Jones".to_string(),
+                    "5.1".to_string() => "0000104912345678".to_string(),
+                }),
+            ),
+            (
+                "0103ab",
+                Err(GatewayError::DecodingError(
+                    "field '1' shorter than its length (3)".into(),
+                )),
+            ),
+            (
+                "01",
+                Err(GatewayError::DecodingError("truncated field header '01'".into())),
+            ),
+            (
+                "0x03abc",
+                Err(GatewayError::DecodingError("invalid field position '0x'".into())),
+            ),
+            (
+                "0é03abc",
+                Err(GatewayError::DecodingError("truncated field header '0é03abc'".into())),
+            ),
+            (
+                "01é",
+                Err(GatewayError::DecodingError("invalid length 'é' for field '1'".into())),
+            ),
+            (
+                "0102aé",
+                Err(GatewayError::DecodingError(
+                    "field '1' shorter than its length (2)".into(),
+                )),
+            ),
+        ];
+        for (encoded, expected) in tests.into_iter() {
+            let actual = iso8853_decode(encoded, &ISO8853_BITMAP_TEMPLATE);
             assert_eq!(expected, actual);
         }
     }
 
+    #[test]
+    fn test_Network_and_ExpiryDate() {
+        let tests = [
+            ("4111111111111111", "12/24", ("V", "122024")),
+            ("5555555555554444", "01/2030", ("M", "012030")),
+            ("6759649826438453", "06/25", ("E", "062025")),
+            ("371449635398431", "12/24", ("A", "122024")),
+            ("6011111111111117", "12/24", ("D", "122024")),
+        ];
+        for (pan, expiry_date, (network, expiry)) in tests.into_iter() {
+            let security_code = if pan.len() == 15 { "1234" } else { "123" };
+            let op = Operation {
+                payment: Some(
+                    Payment::card_at(pan, expiry_date, security_code, "Ben Jones", &test_clock())
+                        .unwrap(),
+                ),
+                ..example_operation()
+            };
+            assert_eq!(Some(network.to_string()), Network(&op).unwrap());
+            assert_eq!(Some(expiry.to_string()), ExpiryDate(&op).unwrap());
+        }
+    }
+
     #[test]
     fn test_TransactionIdentifier() {
         let tests = [(example_operation(), "abc".to_string())];
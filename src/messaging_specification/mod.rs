@@ -1,4 +1,4 @@
-mod iso8853;
+pub mod iso8853;
 
 use std::collections::HashMap;
 
@@ -9,27 +9,58 @@ pub type BitMap = HashMap<usize, BitField>;
 pub type OperationParseResult = Result<Option<String>>;
 pub type OperationParser = fn(&Operation) -> OperationParseResult;
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum MessagingSpecification {
     Iso8853,
     Apacs,
 }
 
+/// The fields a bank can send back in response to a request
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ResponseField {
+    TransactionIdentifier,
+    ResponseCode,
+    AuthCode,
+    Message,
+    AvsResult,
+    CvvResult,
+    BankReference,
+}
+
+impl ResponseField {
+    /// The key used for this field in a decoded response
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResponseField::TransactionIdentifier => "transactionidentifier",
+            ResponseField::ResponseCode => "responsecode",
+            ResponseField::AuthCode => "authcode",
+            ResponseField::Message => "message",
+            ResponseField::AvsResult => "avsresult",
+            ResponseField::CvvResult => "cvvresult",
+            ResponseField::BankReference => "bankreference",
+        }
+    }
+}
+
 impl MessagingSpecification {
     pub fn encode_request(&self, op: &Operation) -> Result<String> {
-        self.encode_using_template(&op, &self.get_template())
+        self.encode_using_template(op, &self.get_template())
     }
 
     pub fn get_template(&self) -> BitMap {
         match self {
             MessagingSpecification::Iso8853 => ISO8853_BITMAP_TEMPLATE.clone(),
-            MessagingSpecification::Apacs => todo!(),
+            // no APACS fields are implemented yet, encoding fails before the template is used
+            MessagingSpecification::Apacs => BitMap::new(),
         }
     }
 
-    fn get_formatter(&self) -> StringFormatter {
+    fn get_formatter(&self) -> Result<StringFormatter> {
         match self {
-            MessagingSpecification::Iso8853 => iso8853_string_field,
-            MessagingSpecification::Apacs => todo!(),
+            MessagingSpecification::Iso8853 => Ok(iso8853_string_field),
+            MessagingSpecification::Apacs => Err(GatewayError::EncodingError(
+                "APACS messages cannot be encoded yet".into(),
+            )),
         }
     }
 
@@ -38,8 +69,69 @@ impl MessagingSpecification {
         op: &Operation,
         template: &BitMap,
     ) -> Result<String> {
-        let formatter = Some(self.get_formatter());
-        encode(&op, &template, formatter, formatter)
+        let formatter = Some(self.get_formatter()?);
+        encode(op, template, formatter, formatter)
+    }
+
+    /// Decodes a request encoded with `template`, keyed by field position (nested fields are keyed like `3.1`)
+    pub fn decode_using_template(
+        &self,
+        encoded: &str,
+        template: &BitMap,
+    ) -> Result<HashMap<String, String>> {
+        match self {
+            MessagingSpecification::Iso8853 => iso8853_decode(encoded, template),
+            MessagingSpecification::Apacs => Err(GatewayError::DecodingError(
+                "APACS messages cannot be decoded yet".into(),
+            )),
+        }
+    }
+
+    pub fn decode_request(&self, encoded: &str) -> Result<HashMap<String, String>> {
+        self.decode_using_template(encoded, &self.get_template())
+    }
+
+    fn get_response_layout(&self) -> &'static [(usize, ResponseField)] {
+        match self {
+            MessagingSpecification::Iso8853 => ISO8853_RESPONSE_LAYOUT,
+            // no APACS fields are implemented yet, encoding and decoding fail before this is used
+            MessagingSpecification::Apacs => &[],
+        }
+    }
+
+    /// Formats a single top level field that is not part of a template, e.g. a trailing MAC
+    pub fn encode_field(&self, position: usize, value: &str) -> Result<String> {
+        let mut data = value.to_string();
+        let ctx = EncodingContext {
+            position: Some(position),
+            padding: None,
+        };
+        self.get_formatter()?(&mut data, ctx, &BitField::Map(BitMap::new()));
+        Ok(data)
+    }
+
+    pub fn encode_response(&self, fields: &HashMap<ResponseField, String>) -> Result<String> {
+        // checked up front so a spec that cannot be encoded fails even without a layout
+        self.get_formatter()?;
+        let mut output = String::new();
+        for (pos, field) in self.get_response_layout() {
+            if let Some(value) = fields.get(field) {
+                output.push_str(&self.encode_field(*pos, value)?);
+            }
+        }
+        Ok(output)
+    }
+
+    pub fn decode_response(&self, encoded: &str) -> Result<HashMap<ResponseField, String>> {
+        // responses are flat, so there is no template to guide the decoding
+        let decoded = self.decode_using_template(encoded, &BitMap::new())?;
+        let mut fields = HashMap::new();
+        for (pos, field) in self.get_response_layout() {
+            if let Some(value) = decoded.get(&pos.to_string()) {
+                fields.insert(*field, value.clone());
+            }
+        }
+        Ok(fields)
     }
 }
 
@@ -52,6 +144,11 @@ pub enum BitField {
         padding_char: Option<char>,
     },
     Map(BitMap),
+    /// Binary TLV data such as EMV tags, sent as hex with a longer length prefix than other fields
+    Tlv {
+        parser: OperationParser,
+        max_length: usize,
+    },
 }
 
 impl<T: Into<BitField>> From<HashMap<usize, T>> for BitField {
@@ -62,7 +159,7 @@ impl<T: Into<BitField>> From<HashMap<usize, T>> for BitField {
 
 macro_rules! bitmap {
     ($name:ident, $($key:expr => $value:expr),+ $(,)?) => {
-        pub const $name: std::sync::LazyLock<crate::messaging_specification::BitMap> = std::sync::LazyLock::new(|| ::std::collections::HashMap::from([ $(($key, crate::messaging_specification::BitField::from($value))),* ]));
+        pub static $name: std::sync::LazyLock<crate::messaging_specification::BitMap> = std::sync::LazyLock::new(|| ::std::collections::HashMap::from([ $(($key, crate::messaging_specification::BitField::from($value))),* ]));
     };
 }
 pub(crate) use bitmap;
@@ -75,8 +172,8 @@ fn encode(
 ) -> Result<String> {
     let mut output = String::new();
     _format(
-        &op,
-        &template,
+        op,
+        template,
         &mut output,
         single_field_transform,
         map_field_transform,
@@ -108,9 +205,9 @@ fn _format(
                 min_length,
                 max_length,
             } => {
-                if let Some(mut data) = parser(&op)? {
+                if let Some(mut data) = parser(op)? {
                     if data.len() > *max_length {
-                        return Err(GatewayError::EncodingError(format!("value '{data}' too long ({}) for bitfield '{pos}' ({})", data.len(), *max_length)));
+                        return Err(GatewayError::EncodingError(format!("value too long ({}) for bitfield '{pos}' ({})", data.len(), *max_length)));
                     }
                     if let Some(transformer) = single_field_transform {
                         let ctx = EncodingContext {
@@ -122,6 +219,21 @@ fn _format(
                     output.push_str(&data);
                 }
             }
+            BitField::Tlv { parser, max_length } => {
+                if let Some(mut data) = parser(op)? {
+                    if data.len() > *max_length {
+                        return Err(GatewayError::EncodingError(format!("TLV data too long ({}) for bitfield '{pos}' ({})", data.len(), *max_length)));
+                    }
+                    if let Some(transformer) = single_field_transform {
+                        let ctx = EncodingContext {
+                            position: Some(*pos),
+                            padding: None,
+                        };
+                        transformer(&mut data, ctx, field);
+                    }
+                    output.push_str(&data);
+                }
+            }
             BitField::Map(map) => {
                 let mut sorted_nested: Vec<(&usize, &BitField)> = map.iter().collect();
                 sorted_nested.sort_by(|a, b| a.0.cmp(b.0));
@@ -134,9 +246,9 @@ fn _format(
                             min_length,
                             max_length,
                         } => {
-                            if let Some(mut nested_data) = parser(&op)? {
+                            if let Some(mut nested_data) = parser(op)? {
                                 if nested_data.len() > *max_length {
-                                    return Err(GatewayError::EncodingError(format!("value '{nested_data}' too long ({}) for bitfield '{pos}.{nested_pos}' ({})", nested_data.len(), *max_length)));
+                                    return Err(GatewayError::EncodingError(format!("value too long ({}) for bitfield '{pos}.{nested_pos}' ({})", nested_data.len(), *max_length)));
                                 }
                                 if let Some(transformer) = single_field_transform {
                                     let ctx = EncodingContext {
@@ -149,8 +261,18 @@ fn _format(
                             }
                         }
                         BitField::Map(_map) => panic!("cannot handle more than 1 nested map"), // TODO will this ever be needed?
+                        // nested fields have 2 digit lengths, which cannot fit a TLV
+                        BitField::Tlv { .. } => {
+                            return Err(GatewayError::EncodingError(format!(
+                                "TLV field '{pos}.{nested_pos}' cannot be nested"
+                            )))
+                        }
                     }
                 }
+                // optional sections are left out entirely when none of their fields apply
+                if nested.is_empty() {
+                    continue;
+                }
                 if let Some(transformer) = map_field_transform {
                     let ctx = EncodingContext {
                         position: Some(*pos),
@@ -165,9 +287,9 @@ fn _format(
     Ok(())
 }
 
-fn string_field(mut data: &mut String, ctx: EncodingContext, _field: &BitField) {
+fn string_field(data: &mut String, ctx: EncodingContext, _field: &BitField) {
     if let Some((length, pad)) = ctx.padding {
-        pad_string(&mut data, length, pad);
+        pad_string(data, length, pad);
     }
 }
 
@@ -0,0 +1,399 @@
+use std::{
+    collections::HashMap,
+    fs,
+    str::FromStr,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use crate::{GatewayError, Result};
+
+/// Sort code used in place of the real one by exception 8
+const EXCEPTION_8_SORT_CODE: [u32; 6] = [0, 9, 0, 1, 2, 6];
+/// Sort code used in place of the real one by exception 9, when the exception 2 check fails
+const EXCEPTION_9_SORT_CODE: [u32; 6] = [3, 0, 9, 6, 3, 4];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Method {
+    Mod10,
+    Mod11,
+    DoubleAlternate,
+}
+
+impl FromStr for Method {
+    type Err = GatewayError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "MOD10" => Ok(Method::Mod10),
+            "MOD11" => Ok(Method::Mod11),
+            "DBLAL" => Ok(Method::DoubleAlternate),
+            invalid => Err(GatewayError::FieldError(format!(
+                "Invalid modulus method: {invalid}"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct WeightRow {
+    start: u32,
+    end: u32,
+    method: Method,
+    /// Applied to the sort code followed by the account number, `u v w x y z a b c d e f g h`
+    weights: [u32; 14],
+    exception: Option<u8>,
+}
+
+/// The Vocalink modulus weight table (`valacdos.txt`) used to check UK account numbers.
+///
+/// Accounts at sort codes the table doesn't cover cannot be checked, so are always accepted.
+/// Exception 5 checks some sort codes as another one, load those from the sort code
+/// substitution table (`scsubtab.txt`) with [`ModulusTable::with_substitutions`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModulusTable {
+    rows: Vec<WeightRow>,
+    /// Sort code to the one exception 5 checks it as
+    substitutions: HashMap<u32, [u32; 6]>,
+}
+
+impl ModulusTable {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| GatewayError::FieldError(format!("Cannot read {path}: {err}")))?;
+        Self::parse(&contents)
+    }
+
+    /// Parses whitespace separated `start end method` followed by 14 weights and an optional exception
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut rows = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |what: &str| {
+                GatewayError::FieldError(format!("Invalid modulus table line {}: {what}", i + 1))
+            };
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if !(17..=18).contains(&columns.len()) {
+                return Err(invalid("expected 17 or 18 columns"));
+            }
+            let sort_code = |column: &str| {
+                (column.len() == 6)
+                    .then(|| column.parse::<u32>().ok())
+                    .flatten()
+                    .ok_or_else(|| invalid("sort codes must be 6 digits"))
+            };
+            let mut weights = [0; 14];
+            for (weight, column) in weights.iter_mut().zip(&columns[3..17]) {
+                // weights can be negative in the published table but only ever multiply zeroes
+                *weight = column
+                    .parse::<i32>()
+                    .map_err(|_| invalid("weights must be numbers"))?
+                    .unsigned_abs();
+            }
+            rows.push(WeightRow {
+                start: sort_code(columns[0])?,
+                end: sort_code(columns[1])?,
+                method: columns[2].parse()?,
+                weights,
+                exception: columns
+                    .get(17)
+                    .map(|exception| {
+                        exception
+                            .parse()
+                            .map_err(|_| invalid("exception must be a number"))
+                    })
+                    .transpose()?,
+            });
+        }
+        Ok(Self {
+            rows,
+            substitutions: HashMap::new(),
+        })
+    }
+
+    pub fn load_substitutions(self, path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| GatewayError::FieldError(format!("Cannot read {path}: {err}")))?;
+        self.with_substitutions(&contents)
+    }
+
+    /// Adds exception 5's substitutions, whitespace separated `sort_code substitute` lines
+    pub fn with_substitutions(mut self, contents: &str) -> Result<Self> {
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = || {
+                GatewayError::FieldError(format!(
+                    "Invalid sort code substitution line {}: expected two 6 digit sort codes",
+                    i + 1
+                ))
+            };
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            let [sort_code, substitute] = columns[..] else {
+                return Err(invalid());
+            };
+            if ![sort_code, substitute]
+                .iter()
+                .all(|column| column.len() == 6 && column.chars().all(|c| c.is_ascii_digit()))
+            {
+                return Err(invalid());
+            }
+            let mut digits = [0; 6];
+            for (digit, c) in digits.iter_mut().zip(substitute.chars()) {
+                *digit = c.to_digit(10).unwrap_or(0);
+            }
+            self.substitutions
+                .insert(sort_code.parse().map_err(|_| invalid())?, digits);
+        }
+        Ok(self)
+    }
+
+    /// Checks an 8 digit account number at a 6 digit sort code
+    pub fn check(&self, sort_code: &str, account_number: &str) -> bool {
+        let Ok(code) = sort_code.parse::<u32>() else {
+            return false;
+        };
+        let mut digits = [0; 14];
+        for (digit, c) in digits
+            .iter_mut()
+            .zip(sort_code.chars().chain(account_number.chars()))
+        {
+            *digit = c.to_digit(10).unwrap_or(0);
+        }
+        let rows: Vec<&WeightRow> = self
+            .rows
+            .iter()
+            .filter(|row| (row.start..=row.end).contains(&code))
+            .take(2)
+            .collect();
+        if rows.iter().any(|row| row.exception == Some(5)) {
+            if let Some(substitute) = self.substitutions.get(&code) {
+                digits[..6].copy_from_slice(substitute);
+            }
+        }
+        let [a, c, g, h] = [digits[6], digits[8], digits[12], digits[13]];
+        // foreign currency accounts, which cannot be checked
+        if rows.iter().any(|row| row.exception == Some(6)) && (4..=8).contains(&a) && g == h {
+            return true;
+        }
+        match rows[..] {
+            [] => true,
+            [row] => row.check(digits),
+            [first, second] => {
+                let first_valid = first.check(digits);
+                match (first.exception, second.exception) {
+                    (Some(2), Some(9)) => {
+                        first_valid || {
+                            digits[..6].copy_from_slice(&EXCEPTION_9_SORT_CODE);
+                            second.check(digits)
+                        }
+                    }
+                    (Some(10), Some(11)) | (Some(12), Some(13)) => {
+                        first_valid || second.check(digits)
+                    }
+                    (_, Some(3)) if c == 6 || c == 9 => first_valid,
+                    _ => first_valid && second.check(digits),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl WeightRow {
+    fn check(&self, mut digits: [u32; 14]) -> bool {
+        let mut weights = self.weights;
+        let [a, b, g, h] = [digits[6], digits[7], digits[12], digits[13]];
+        match self.exception {
+            Some(2) if a != 0 && g != 9 => weights = [0, 0, 1, 2, 5, 3, 6, 4, 8, 7, 10, 9, 3, 1],
+            Some(2) if a != 0 => weights = [0, 0, 0, 0, 0, 0, 0, 0, 8, 7, 10, 9, 3, 1],
+            Some(7) if g == 9 => weights[..8].fill(0),
+            Some(10) if ((a, b) == (0, 9) || (a, b) == (9, 9)) && g == 9 => weights[..8].fill(0),
+            Some(8) => digits[..6].copy_from_slice(&EXCEPTION_8_SORT_CODE),
+            _ => (),
+        }
+        let products = digits
+            .iter()
+            .zip(weights)
+            .map(|(digit, weight)| digit * weight);
+        let mut total: u32 = match self.method {
+            Method::DoubleAlternate => products.map(|product| product / 10 + product % 10).sum(),
+            _ => products.sum(),
+        };
+        if self.exception == Some(1) {
+            total += 27;
+        }
+        match (self.method, self.exception) {
+            (Method::Mod11, Some(4)) => total % 11 == g * 10 + h,
+            (Method::Mod11, Some(5)) => match total % 11 {
+                0 => g == 0,
+                1 => false,
+                remainder => 11 - remainder == g,
+            },
+            (Method::DoubleAlternate, Some(5)) => match total % 10 {
+                0 => h == 0,
+                remainder => 10 - remainder == h,
+            },
+            (Method::Mod11, Some(14)) if !total.is_multiple_of(11) => {
+                // the last digit may be a suffix, in which case the real account number is shifted right
+                [0, 1, 9].contains(&h) && {
+                    digits.copy_within(6..13, 7);
+                    digits[6] = 0;
+                    Self {
+                        exception: None,
+                        ..self.clone()
+                    }
+                    .check(digits)
+                }
+            }
+            (Method::Mod11, _) => total.is_multiple_of(11),
+            (Method::Mod10 | Method::DoubleAlternate, _) => total.is_multiple_of(10),
+        }
+    }
+}
+
+static TABLE: LazyLock<RwLock<Option<Arc<ModulusTable>>>> = LazyLock::new(|| RwLock::new(None));
+
+/// Sets the table accounts are checked against, until then no account can be checked
+pub fn install(table: ModulusTable) {
+    *TABLE.write().expect("modulus table poisoned") = Some(Arc::new(table));
+}
+
+pub(crate) fn installed() -> Result<Arc<ModulusTable>> {
+    TABLE
+        .read()
+        .expect("modulus table poisoned")
+        .clone()
+        .ok_or(GatewayError::ValidationError(
+            "No modulus table installed".into(),
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let table = ModulusTable::parse(
+            "089000 089999 MOD10    0    0    0    0    0    0    7    1    3    7    1    3    7    1\n\
+             107999 107999 MOD11    0    0    0    0    0    0    8    7    6    5    4    3    2    1\n\
+             118765 118765 DBLAL    2    1    2    1    2    1    2    1    2    1    2    1    2    1    1\n\
+             134020 134020 MOD11    0    0    0    0    0    0    7    6    5    4    3    2    0    0    4\n\
+             200915 200915 MOD11    0    0    0    0    0    0    8    7    6    5    4    3    2    1    6\n\
+             200915 200915 DBLAL    2    1    2    1    2    1    2    1    2    1    2    1    2    1    6\n\
+             820000 829999 MOD11    0    0    0    0    0    0    8    7    6    5    4    3    2    1\n\
+             820000 829999 DBLAL    2    1    2    1    2    1    2    1    2    1    2    1    2    1    3\n\
+             871427 871427 MOD11    0    0    0    0    0    0    8    7    6    5    4    3    2    1   10\n\
+             871427 871427 MOD11    0    0    0    0    0    0    7    6    5    4    3    2    1    0   11\n\
+             180002 180002 MOD11    0    0    0    0    0    0    8    7    6    5    4    3    2    1   14\n",
+        )
+        .unwrap();
+        let tests = [
+            ("089999", "66374958", true),
+            ("089999", "66374959", false),
+            ("107999", "88837491", true),
+            ("107999", "88837493", false),
+            // not in the table so cannot be checked
+            ("119999", "12345678", true),
+            ("118765", "64371303", true),
+            ("118765", "64371304", false),
+            ("134020", "63849204", true),
+            ("134020", "63849203", false),
+            // foreign currency account, never checked
+            ("200915", "41011166", true),
+            ("820000", "12340049", true),
+            ("827101", "12300039", false),
+            // the double alternate check is skipped when c is 6 or 9
+            ("827999", "73988618", true),
+            ("827999", "73988619", false),
+            ("871427", "46238510", true),
+            ("871427", "46238590", false),
+            ("871427", "09123498", true),
+            ("180002", "00000190", true),
+            ("180002", "00000192", false),
+        ];
+        for (i, (sort_code, account_number, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(
+                expected,
+                table.check(sort_code, account_number),
+                "Case number {}",
+                i + 1
+            );
+        }
+    }
+
+    #[test]
+    fn test_substitutions() {
+        let table = ModulusTable::parse(
+            "938000 938999 MOD11    7    6    5    4    3    2    7    6    5    4    3    2    0    0    5\n\
+             938000 938999 DBLAL    2    1    2    1    2    1    2    1    2    1    2    1    0    0    5\n",
+        )
+        .unwrap();
+        let substituted = table.clone().with_substitutions("938173 938017\n").unwrap();
+        let tests = [
+            ("938017", "10000090", true, true),
+            // checked as 938017 once the substitution is loaded
+            ("938173", "10000090", false, true),
+            ("938173", "10000060", true, false),
+        ];
+        for (i, (sort_code, account_number, expected, expected_substituted)) in
+            tests.into_iter().enumerate()
+        {
+            assert_eq!(
+                (expected, expected_substituted),
+                (
+                    table.check(sort_code, account_number),
+                    substituted.check(sort_code, account_number)
+                ),
+                "Case number {}",
+                i + 1
+            );
+        }
+        let tests = [
+            "938173",
+            "938173 93801",
+            "938173 938017 938018",
+            "93817a 938017",
+        ];
+        for line in tests.into_iter() {
+            assert_eq!(
+                Err(GatewayError::FieldError(
+                    "Invalid sort code substitution line 1: expected two 6 digit sort codes".into()
+                )),
+                table.clone().with_substitutions(line),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let tests = [
+            (
+                "089000 089999 MOD10 0 0 0 0 0 0 7 1 3 7 1 3 7",
+                "Invalid modulus table line 1: expected 17 or 18 columns",
+            ),
+            (
+                "08900 089999 MOD10 0 0 0 0 0 0 7 1 3 7 1 3 7 1",
+                "Invalid modulus table line 1: sort codes must be 6 digits",
+            ),
+            (
+                "089000 089999 MOD12 0 0 0 0 0 0 7 1 3 7 1 3 7 1",
+                "Invalid modulus method: MOD12",
+            ),
+            (
+                "089000 089999 MOD10 0 0 0 0 0 0 7 1 3 7 1 3 7 x",
+                "Invalid modulus table line 1: weights must be numbers",
+            ),
+        ];
+        for (line, expected) in tests.into_iter() {
+            assert_eq!(
+                Err(GatewayError::FieldError(expected.into())),
+                ModulusTable::parse(line)
+            );
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::fmt::Display;
+
+use crate::{currency::Currency, GatewayError, Result};
+
+/// An amount of a currency, held in its minor units so 12345 GBP is £123.45 and 12345 JPY is ¥12345
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Money {
+    minor_units: i64,
+    currency: Currency,
+}
+
+fn invalid(reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid amount: {reason}"))
+}
+
+impl Money {
+    pub fn new(minor_units: i64, currency: Currency) -> Self {
+        Self {
+            minor_units,
+            currency,
+        }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self::new(0, currency)
+    }
+
+    /// Parses a decimal amount in major units such as `123.45`, which may not have more decimal
+    /// places than the currency's minor unit
+    pub fn parse(amount: &str, currency: Currency) -> Result<Self> {
+        let (negative, unsigned) = match amount.strip_prefix('-') {
+            Some(unsigned) => (true, unsigned),
+            None => (false, amount),
+        };
+        let (major, minor) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if major.is_empty() || !is_digits(major) || !is_digits(minor) || unsigned.ends_with('.') {
+            return Err(invalid(&format!("{amount} is not a decimal number")));
+        }
+        let exponent = currency.exponent() as usize;
+        if minor.len() > exponent {
+            return Err(invalid(&format!(
+                "{currency} has {exponent} decimal places but {amount} has {}",
+                minor.len()
+            )));
+        }
+        let digits = format!("{major}{minor:0<exponent$}");
+        let minor_units: i64 = digits
+            .parse()
+            .map_err(|_| invalid(&format!("{amount} is too large")))?;
+        Ok(Self::new(
+            if negative { -minor_units } else { minor_units },
+            currency,
+        ))
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.minor_units
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor_units < 0
+    }
+
+    fn same_currency(&self, other: &Money, operation: &str) -> Result<()> {
+        if self.currency != other.currency {
+            return Err(GatewayError::ValidationError(format!(
+                "Cannot {operation} {} and {} amounts",
+                self.currency, other.currency
+            )));
+        }
+        Ok(())
+    }
+
+    fn overflow(&self) -> GatewayError {
+        GatewayError::ValidationError(format!("{} amount overflowed", self.currency))
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money> {
+        self.same_currency(other, "add")?;
+        let sum = self.minor_units.checked_add(other.minor_units);
+        Ok(Self::new(
+            sum.ok_or_else(|| self.overflow())?,
+            self.currency,
+        ))
+    }
+
+    pub fn checked_sub(&self, other: &Money) -> Result<Money> {
+        self.same_currency(other, "subtract")?;
+        let difference = self.minor_units.checked_sub(other.minor_units);
+        Ok(Self::new(
+            difference.ok_or_else(|| self.overflow())?,
+            self.currency,
+        ))
+    }
+
+    pub fn checked_mul(&self, quantity: i64) -> Result<Money> {
+        let product = self.minor_units.checked_mul(quantity);
+        Ok(Self::new(
+            product.ok_or_else(|| self.overflow())?,
+            self.currency,
+        ))
+    }
+
+    /// The amount in major units with the currency's decimal places, e.g. `123.45`
+    pub fn to_decimal_string(&self) -> String {
+        let exponent = self.currency.exponent() as usize;
+        let sign = if self.is_negative() { "-" } else { "" };
+        let digits = format!(
+            "{:0>width$}",
+            self.minor_units.unsigned_abs(),
+            width = exponent + 1
+        );
+        let (major, minor) = digits.split_at(digits.len() - exponent);
+        match exponent {
+            0 => format!("{sign}{major}"),
+            _ => format!("{sign}{major}.{minor}"),
+        }
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.to_decimal_string(), self.currency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        let tests = [
+            (("123.45", Currency::GBP), Ok((12345, "123.45 GBP"))),
+            (("123.4", Currency::GBP), Ok((12340, "123.40 GBP"))),
+            (("123", Currency::GBP), Ok((12300, "123.00 GBP"))),
+            (("0.05", Currency::USD), Ok((5, "0.05 USD"))),
+            (("-1.50", Currency::EUR), Ok((-150, "-1.50 EUR"))),
+            (("12345", Currency::JPY), Ok((12345, "12345 JPY"))),
+            (("1.234", Currency::BHD), Ok((1234, "1.234 BHD"))),
+            (
+                ("123.456", Currency::GBP),
+                Err("Invalid amount: GBP has 2 decimal places but 123.456 has 3"),
+            ),
+            (
+                ("1.5", Currency::JPY),
+                Err("Invalid amount: JPY has 0 decimal places but 1.5 has 1"),
+            ),
+            (
+                ("12a.00", Currency::GBP),
+                Err("Invalid amount: 12a.00 is not a decimal number"),
+            ),
+            (
+                ("123.", Currency::GBP),
+                Err("Invalid amount: 123. is not a decimal number"),
+            ),
+            (
+                (".5", Currency::GBP),
+                Err("Invalid amount: .5 is not a decimal number"),
+            ),
+            (
+                ("99999999999999999999", Currency::GBP),
+                Err("Invalid amount: 99999999999999999999 is too large"),
+            ),
+        ];
+        for (i, ((amount, currency), expected)) in tests.into_iter().enumerate() {
+            let actual = Money::parse(amount, currency)
+                .map(|money| (money.minor_units(), money.to_string()));
+            let expected = expected
+                .map(|(minor_units, display)| (minor_units, display.to_string()))
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let pounds = |minor_units| Money::new(minor_units, Currency::GBP);
+        let dollars = Money::new(100, Currency::USD);
+        let tests = [
+            (pounds(100).checked_add(&pounds(250)), Ok(pounds(350))),
+            (pounds(100).checked_sub(&pounds(250)), Ok(pounds(-150))),
+            (pounds(250).checked_mul(3), Ok(pounds(750))),
+            (
+                pounds(100).checked_add(&dollars),
+                Err("Cannot add GBP and USD amounts"),
+            ),
+            (
+                pounds(100).checked_sub(&dollars),
+                Err("Cannot subtract GBP and USD amounts"),
+            ),
+            (
+                pounds(i64::MAX).checked_add(&pounds(1)),
+                Err("GBP amount overflowed"),
+            ),
+            (
+                pounds(i64::MIN).checked_sub(&pounds(1)),
+                Err("GBP amount overflowed"),
+            ),
+            (
+                pounds(i64::MAX).checked_mul(2),
+                Err("GBP amount overflowed"),
+            ),
+        ];
+        for (i, (actual, expected)) in tests.into_iter().enumerate() {
+            let expected = expected.map_err(|err| GatewayError::ValidationError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+    }
+}
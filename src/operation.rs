@@ -1,8 +1,22 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use crate::{
-    bank::Bank, merchant::Merchant, payment::Payment, transaction::Transaction, GatewayError,
-    Result,
+    bank::Bank,
+    card::CardNumber,
+    card_present::{CardPresent, Track2},
+    emv::EmvData,
+    clock::{Clock, SystemClock},
+    currency::Currency,
+    customer::{Address, Customer},
+    fx::DccQuote,
+    merchant::Merchant,
+    money::Money,
+    payment::Payment,
+    soft_descriptor::{validate_order_reference, SoftDescriptor},
+    three_d_secure::ThreeDSecure,
+    transaction::Transaction,
+    vault::{self, Vault},
+    GatewayError, Result,
 };
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -10,6 +24,8 @@ pub enum RequestType {
     Auth,
     Refund,
     AccountCheck,
+    /// Cancels a previous request, identified by its trace number
+    Reversal,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -19,18 +35,119 @@ pub struct Operation {
     pub payment: Option<Payment>,
     pub transaction: Option<Transaction>,
     pub merchant: Option<Merchant>,
+    /// Identifies this request to the bank so it can be referenced later, e.g. by a reversal
+    pub trace_number: Option<String>,
+    /// The trace number of the request a reversal is cancelling
+    pub original_trace_number: Option<String>,
+    /// The 3-D Secure result for card payments that went through strong customer authentication
+    pub three_d_secure: Option<ThreeDSecure>,
+    /// Terminal details for card-present transactions, absent for e-commerce
+    pub card_present: Option<CardPresent>,
+    /// The conversion the cardholder accepted to pay in their own currency
+    pub dcc: Option<DccQuote>,
+    /// The merchant's own reference for the order
+    pub order_reference: Option<String>,
+    /// What the cardholder's statement shows, in place of the merchant's registered details
+    pub soft_descriptor: Option<SoftDescriptor>,
 }
 
 impl Operation {
     pub fn encode(&self) -> Result<String> {
+        self.encode_at(&SystemClock)
+    }
+
+    /// Encodes the operation for its bank, any DCC quote must still be current when it is sent
+    pub fn encode_at(&self, clock: &dyn Clock) -> Result<String> {
         match self.bank {
-            Some(bank) => bank.encode_request(&self),
-            None => Err(GatewayError::EncodingError(format!(
-                "This operation has no bank! Are you sure it needs to be encoded?"
-            ))),
+            Some(bank) => {
+                bank.capabilities()?.check(self)?;
+                if self.dcc.as_ref().is_some_and(|quote| quote.is_expired(clock)) {
+                    return Err(GatewayError::ValidationError("DCC quote has expired".into()));
+                }
+                bank.encode_request(self)
+            }
+            None => Err(GatewayError::EncodingError(
+                "This operation has no bank! Are you sure it needs to be encoded?".into(),
+            )),
         }
     }
 
+    /// Encodes the operation with any card token detokenised by `vault` rather than the installed one
+    pub fn encode_with_vault(&self, vault: Arc<dyn Vault>) -> Result<String> {
+        vault::with_vault(vault, || self.encode())
+    }
+
+    /// Builds the reversal of this operation, which carries the same details but references this operation's trace number
+    pub fn reversal(&self) -> Result<Operation> {
+        let trace_number = self.trace_number.clone().ok_or(GatewayError::ValidationError(
+            "Cannot reverse an operation without a trace number".into(),
+        ))?;
+        Ok(Operation {
+            request_type: Some(RequestType::Reversal),
+            trace_number: None,
+            original_trace_number: Some(trace_number),
+            ..self.clone()
+        })
+    }
+
+    /// Replaces the card's PAN with the merchant's vault token, so the operation can be stored
+    pub fn tokenise(&mut self, vault: &dyn Vault) -> Result<()> {
+        let merchant = self.merchant.as_ref().ok_or(GatewayError::ValidationError(
+            "Cannot tokenise an operation without a merchant".into(),
+        ))?;
+        if let Some(payment) = self.payment.take() {
+            self.payment = Some(payment.tokenise(vault, &merchant.mid)?);
+        }
+        Ok(())
+    }
+
+    /// Adds the terminal's details, any track 2 it read must be for the card being paid with
+    pub fn with_card_present(self, card_present: CardPresent) -> Result<Self> {
+        let track2_pan = card_present.track2.as_ref().map(|track2| &track2.pan);
+        if let (
+            Some(track2_pan),
+            Some(Payment::Card {
+                number: CardNumber::Pan(pan),
+                ..
+            }),
+        ) = (track2_pan, &self.payment)
+        {
+            if track2_pan != pan {
+                return Err(GatewayError::FieldError(
+                    "Invalid track2: pan does not match the card's pan".into(),
+                ));
+            }
+        }
+        Ok(Self {
+            card_present: Some(card_present),
+            ..self
+        })
+    }
+
+    pub fn with_dcc(self, quote: DccQuote) -> Result<Self> {
+        self.with_dcc_at(quote, &SystemClock)
+    }
+
+    /// Charges the cardholder in their own currency, the quote must be for the transaction's amount
+    pub fn with_dcc_at(self, quote: DccQuote, clock: &dyn Clock) -> Result<Self> {
+        let transaction = self.transaction.as_ref().ok_or(GatewayError::ValidationError(
+            "Cannot apply DCC to an operation without a transaction".into(),
+        ))?;
+        if quote.merchant_amount != transaction.amount {
+            return Err(GatewayError::ValidationError(format!(
+                "DCC quote is for {} but the transaction is for {}",
+                quote.merchant_amount, transaction.amount
+            )));
+        }
+        if quote.is_expired(clock) {
+            return Err(GatewayError::ValidationError("DCC quote has expired".into()));
+        }
+        Ok(Self {
+            dcc: Some(quote),
+            ..self
+        })
+    }
+
     // pub fn decode(&mut self, encoded_string: &str) {
     //     let _decoded: HashMap<String, String> = self.bank.decode_response_string(encoded_string);
     // }
@@ -38,29 +155,114 @@ impl Operation {
 
 impl TryFrom<HashMap<&str, String>> for Operation {
     fn try_from(v: HashMap<&str, String>) -> Result<Self> {
-        let payment = Payment::card(
-            v.get("pan")
-                .ok_or(GatewayError::FieldError(format!("Missing pan")))?,
-            v.get("expirydate")
-                .ok_or(GatewayError::FieldError(format!("Missing expirydate")))?,
-            v.get("securitycode")
-                .ok_or(GatewayError::FieldError(format!("Missing securitycode")))?,
-            v.get("billingname")
-                .ok_or(GatewayError::FieldError(format!("Missing billingname")))?,
-        );
-        let transaction =
-            Transaction {
-                amount: v.get("baseamount").unwrap().parse().map_err(|err| {
+        let get = |key: &str| {
+            v.get(key)
+                .ok_or(GatewayError::FieldError(format!("Missing {key}")))
+        };
+        let billing_name = get("billingname")?;
+        let track2: Option<Track2> = v.get("track2").map(|track2| track2.parse()).transpose()?;
+        // a stored card can be sent as its token instead of the pan, wallets send a device pan,
+        // bank accounts their number and sort code, and a swiped card only has its track 2
+        let payment = if let Some(pan) = v.get("pan") {
+            Payment::card(pan, get("expirydate")?, get("securitycode")?, billing_name)?
+        } else if let Some(token) = v.get("cardtoken") {
+            Payment::tokenised_card(token, get("expirydate")?, get("securitycode")?, billing_name)?
+        } else if let Some(dpan) = v.get("dpan") {
+            Payment::wallet(
+                dpan,
+                get("expirydate")?,
+                get("cryptogram")?,
+                get("eci")?,
+                get("wallettype")?.parse()?,
+            )?
+        } else if let Some(iban) = v.get("iban") {
+            Payment::iban(iban, v.get("bic").map(String::as_str), billing_name)?
+        } else if let Some(account_number) = v.get("accountnumber") {
+            Payment::account(account_number, get("sortcode")?, billing_name)?
+        } else if let Some(track2) = &track2 {
+            Payment::swiped_card(track2, billing_name)?
+        } else {
+            return Err(GatewayError::FieldError("Missing pan".into()));
+        };
+        let three_d_secure = match (v.get("threedsstatus"), payment.network()) {
+            (Some(status), Some(network)) => Some(ThreeDSecure::new(
+                network,
+                status.parse()?,
+                get("eci")?,
+                v.get("cavv").map(String::as_str),
+                get("dstransactionid")?,
+                get("threedsversion")?,
+            )?),
+            _ => None,
+        };
+        let currency: Currency = get("currencyiso3a")?.parse()?;
+        // baseamount is in minor units, mainamount is a decimal amount in major units
+        let amount = match (v.get("baseamount"), v.get("mainamount")) {
+            (Some(baseamount), _) => Money::new(
+                baseamount.parse().map_err(|err| {
                     GatewayError::FieldError(format!("Invalid baseamount: {err}"))
                 })?,
-                currency: v
-                    .get("currencyiso3a")
-                    .ok_or(GatewayError::FieldError(format!("Missing currencyiso3a")))?
-                    .parse()?,
-                billingname: v.get("billingname").unwrap_or(&"".into()).into(),
-            };
+                currency,
+            ),
+            (None, Some(mainamount)) => Money::parse(mainamount, currency)?,
+            (None, None) => return Err(GatewayError::FieldError("Missing baseamount".into())),
+        };
+        let opt = |key: &str| v.get(key).map(String::as_str);
+        // track 2 is only read at a terminal, so it needs the terminal's details too
+        let card_present = if v.contains_key("posentrymode") || track2.is_some() {
+            Some(CardPresent::new(
+                get("posentrymode")?.parse()?,
+                track2.clone(),
+                get("terminalid")?,
+                get("terminalcapabilities")?.parse()?,
+                get("cvm")?.parse()?,
+                opt("emvdata").map(EmvData::from_hex).transpose()?,
+            )?)
+        } else {
+            None
+        };
+        // billing* is the cardholder's address, customer* is where the order is shipped to
+        let address = |prefix: &str| -> Result<Option<Address>> {
+            let key = |name: &str| format!("{prefix}{name}");
+            let fields = ["premise", "street", "town", "county", "postcode", "countryiso2a"];
+            if !fields.iter().any(|name| v.contains_key(key(name).as_str())) {
+                return Ok(None);
+            }
+            Ok(Some(Address::new(
+                get(&key("premise"))?,
+                opt(&key("street")),
+                opt(&key("town")),
+                opt(&key("county")),
+                get(&key("postcode"))?,
+                get(&key("countryiso2a"))?,
+            )?))
+        };
+        let (billing_address, shipping_address) = (address("billing")?, address("customer")?);
+        let mut transaction = Transaction::new(amount, opt("billingname"))?;
+        if billing_address.is_some()
+            || shipping_address.is_some()
+            || v.contains_key("billingemail")
+            || v.contains_key("billingtelephone")
+        {
+            transaction = transaction.with_customer(Customer {
+                billing_address,
+                shipping_address,
+                ..Customer::new(opt("billingemail"), opt("billingtelephone"))?
+            });
+        }
+        let order_reference = opt("orderreference").map(validate_order_reference).transpose()?;
+        let soft_descriptor = opt("descriptorname")
+            .map(|name| {
+                SoftDescriptor::new(
+                    payment.network(),
+                    name,
+                    opt("descriptorcity"),
+                    opt("descriptorphone"),
+                )
+            })
+            .transpose()?;
 
-        Ok(Operation {
+        let op = Operation {
             request_type: Some(RequestType::Auth),
             bank: Some(Bank::Ems),
             payment: Some(payment),
@@ -70,46 +272,112 @@ impl TryFrom<HashMap<&str, String>> for Operation {
                 "000104912345678",
                 "test@merchant.com",
             )),
-        })
+            trace_number: v.get("tracenumber").cloned(),
+            original_trace_number: None,
+            three_d_secure,
+            card_present: None,
+            dcc: None,
+            order_reference,
+            soft_descriptor,
+        };
+        match card_present {
+            Some(card_present) => op.with_card_present(card_present),
+            None => Ok(op),
+        }
     }
 
     type Error = GatewayError;
 }
 
+#[cfg(test)]
+pub fn example_operation() -> crate::operation::Operation {
+    use crate::merchant::test_merchant;
+
+    crate::operation::Operation {
+        payment: Some(crate::payment::Payment::card_at(
+            "4111111111111111",
+            "12/2024",
+            "123",
+            "Ben Jones",
+            &crate::clock::test_clock(),
+        )
+        .unwrap()),
+        transaction: Some(crate::transaction::Transaction {
+            amount: crate::money::Money::new(12345, crate::currency::Currency::GBP),
+            billingname: "Ben Jones".into(),
+            customer: None,
+            purchasing_data: None,
+        }),
+        merchant: Some(test_merchant()),
+        bank: Some(crate::bank::Bank::Ems),
+        request_type: Some(crate::operation::RequestType::Auth),
+        trace_number: None,
+        original_trace_number: None,
+        three_d_secure: None,
+        card_present: None,
+        dcc: None,
+        order_reference: None,
+        soft_descriptor: None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use core::assert_eq;
 
     use crate::{
-        bank::Bank, currency::Currency, map, merchant::test_merchant, payment::Payment,
-        transaction::Transaction, GatewayError, Result,
+        bank::Bank,
+        clock::{test_clock, Clock},
+        currency::Currency,
+        map,
+        merchant::test_merchant,
+        money::Money,
+        payment::Payment,
+        transaction::Transaction,
+        GatewayError, Result,
     };
 
     use super::{example_operation, Operation, RequestType};
 
+    type EncodingCase = (Payment, Result<Transaction>, Bank, RequestType, Result<String>);
+
+    fn card(pan: &str, security_code: &str) -> Payment {
+        Payment::card_at(pan, "12/2024", security_code, "Ben Jones", &test_clock()).unwrap()
+    }
+
     #[test]
     fn test_card_auth_encoding() {
-        let tests: Vec<(Payment, Result<Transaction>, Bank, RequestType, Result<String>)> = vec![
+        let tests: Vec<EncodingCase> = vec![
             (
-                Payment::card("5100000000000000", "12/2024", "123", "Ben Jones"),
-                Transaction::new(12345, Currency::GBP, "Ben Jones".into()),
+                card("5100000000000008", "123"),
+                Transaction::new(Money::new(12345, Currency::GBP), "Ben Jones".into()),
                 Bank::Ems,
                 RequestType::Auth,
-                Ok("0103abc0204AUTH0342011651000000000000000201M030612202404031230434011000000123450203GBP0309Ben Jones052001160000104912345678".to_string()),
+                Ok("0103abc0204AUTH0342011651000000000000080201M030612202404031230434011000000123450203GBP0309Ben Jones052001160000104912345678".to_string()),
             ),
             (
-                Payment::card("5100000000000000", "12/2024", "123", "Ben Jones"),
-                Transaction::new(12345, Currency::GBP, "Ben Jones".into()),
+                card("5100000000000008", "123"),
+                Transaction::new(Money::new(12345, Currency::GBP), "Ben Jones".into()),
                 Bank::Stfs,
                 RequestType::Auth,
-                Ok("01031230204AUTH0342011651000000000000000201M030612202404031230434011000000123450203GBP0309Ben Jones052001160000104912345678".to_string()),
+                Ok("01031230204AUTH0342011651000000000000080201M030612202404031230434011000000123450203GBP0309Ben Jones052001160000104912345678".to_string()),
             ),
             (
-                Payment::card("5100000000000000", "12/2024", "123123", "Ben Jones"),
-                Transaction::new(12345, Currency::GBP, "Ben Jones".into()),
+                // bypasses the security code validation to check the encoder's own length check
+                match card("5100000000000008", "123") {
+                    Payment::Card { number, expiry_date, name, bin, .. } => Payment::Card {
+                        number,
+                        expiry_date,
+                        security_code: Some("123123".into()),
+                        name,
+                        bin,
+                    },
+                    account => account,
+                },
+                Transaction::new(Money::new(12345, Currency::GBP), "Ben Jones".into()),
                 Bank::Stfs,
                 RequestType::Auth,
-                Err(GatewayError::EncodingError("value '123123' too long (6) for bitfield '3.4' (4)".into())),
+                Err(GatewayError::EncodingError("value too long (6) for bitfield '3.4' (4)".into())),
             ),
         ];
         for (i, (payment, transaction, bank, request_type, expected)) in
@@ -121,33 +389,206 @@ mod tests {
                 bank: Some(bank),
                 request_type: Some(request_type),
                 merchant: Some(test_merchant()),
+                trace_number: None,
+                original_trace_number: None,
+                three_d_secure: None,
+                card_present: None,
+                dcc: None,
+                order_reference: None,
+                soft_descriptor: None,
             };
             let request_string = op.encode();
             assert_eq!(expected, request_string, "Case number {}", i + 1);
         }
     }
 
+    #[test]
+    fn test_capabilities() {
+        let account = Payment::Account {
+            account_number: "12345678".into(),
+            sort_code: "123456".into(),
+            name: "Ben Jones".into(),
+            bank_name: "Lloyds".into(),
+        };
+        let tests = [
+            (Bank::Ems, Currency::USD, example_operation().payment.unwrap(), Ok(())),
+            (
+                Bank::Cardnet,
+                Currency::USD,
+                example_operation().payment.unwrap(),
+                Err(GatewayError::CapabilityError("currency USD".into())),
+            ),
+            (
+                Bank::Ems,
+                Currency::GBP,
+                account.clone(),
+                Err(GatewayError::CapabilityError("payment type Account".into())),
+            ),
+            (
+                Bank::Lloyds,
+                Currency::GBP,
+                account,
+                Err(GatewayError::CapabilityError("request type Auth".into())),
+            ),
+            (
+                Bank::Hsbc,
+                Currency::GBP,
+                example_operation().payment.unwrap(),
+                Err(GatewayError::CapabilityError("request type Auth".into())),
+            ),
+        ];
+        for (i, (bank, currency, payment, expected)) in tests.into_iter().enumerate() {
+            let mut op = example_operation();
+            op.bank = Some(bank);
+            op.payment = Some(payment);
+            op.transaction.as_mut().unwrap().amount = Money::new(12345, currency);
+            let actual = bank.capabilities().unwrap().check(&op);
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+            match expected {
+                Ok(()) => assert!(op.encode().is_ok(), "Case number {}", i + 1),
+                Err(err) => assert_eq!(Err(err), op.encode(), "Case number {}", i + 1),
+            }
+        }
+
+        for request_type in [RequestType::Refund, RequestType::AccountCheck] {
+            let op = Operation {
+                request_type: Some(request_type),
+                ..example_operation()
+            };
+            assert_eq!(
+                Err(GatewayError::CapabilityError(format!(
+                    "request type {request_type:?}"
+                ))),
+                op.encode()
+            );
+            assert_eq!(
+                Err(GatewayError::EncodingError(format!(
+                    "{request_type:?} requests cannot be encoded yet"
+                ))),
+                Bank::Ems.encode_request(&op)
+            );
+        }
+        let op = Operation {
+            bank: Some(Bank::Hsbc),
+            ..example_operation()
+        };
+        assert_eq!(
+            Err(GatewayError::EncodingError(
+                "APACS messages cannot be encoded yet".into()
+            )),
+            Bank::Hsbc.encode_request(&op)
+        );
+    }
+
+    #[test]
+    fn test_with_dcc() {
+        use crate::{
+            clock::FixedTime,
+            fx::{test_rate_provider, DccQuote, QUOTE_VALIDITY},
+        };
+
+        let quote = |minor_units| {
+            DccQuote::new_at(
+                &test_rate_provider(),
+                Money::new(minor_units, Currency::GBP),
+                Currency::EUR,
+                350,
+                &test_clock(),
+            )
+            .unwrap()
+        };
+        let quoted_at = test_clock().now();
+        let expires_at = quoted_at + QUOTE_VALIDITY.as_secs();
+        let tests = [
+            (quote(12345), FixedTime(quoted_at), Ok(())),
+            (
+                quote(10000),
+                FixedTime(quoted_at),
+                Err("DCC quote is for 100.00 GBP but the transaction is for 123.45 GBP"),
+            ),
+            (quote(12345), FixedTime(expires_at), Err("DCC quote has expired")),
+        ];
+        for (i, (quote, clock, expected)) in tests.into_iter().enumerate() {
+            let actual = example_operation().with_dcc_at(quote.clone(), &clock);
+            let expected = expected
+                .map(|_| Operation { dcc: Some(quote), ..example_operation() })
+                .map_err(|err| GatewayError::ValidationError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+        let no_transaction = Operation { transaction: None, ..example_operation() };
+        assert_eq!(
+            Err(GatewayError::ValidationError(
+                "Cannot apply DCC to an operation without a transaction".into()
+            )),
+            no_transaction.with_dcc_at(quote(12345), &test_clock())
+        );
+
+        let op = example_operation().with_dcc_at(quote(12345), &test_clock()).unwrap();
+        let tests = [
+            (Bank::Ems, FixedTime(expires_at - 1), Ok(())),
+            (
+                Bank::Ems,
+                FixedTime(expires_at),
+                Err(GatewayError::ValidationError("DCC quote has expired".into())),
+            ),
+            (
+                Bank::Cardnet,
+                FixedTime(quoted_at),
+                Err(GatewayError::CapabilityError("dynamic currency conversion".into())),
+            ),
+        ];
+        for (i, (bank, clock, expected)) in tests.into_iter().enumerate() {
+            let op = Operation { bank: Some(bank), ..op.clone() };
+            let actual = op.encode_at(&clock).map(|_| ());
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+    }
+
+    fn swiped() -> crate::card_present::Track2 {
+        "4111111111111111=99122011234567890".parse().unwrap()
+    }
+
     #[test]
     fn test_operation_from_hashmap() {
+        use crate::card_present::{CardPresent, Cvm, PosEntryMode};
+
         let tests = [
             (
                 map! {
                     "billingname"   => "Ben Jones".to_string(),
                     "currencyiso3a" => "GBP".to_string(),
                     "baseamount"    => "12345".to_string(),
-                    "pan"           => "4000000000000000".to_string(),
-                    "expirydate"    => "12/2024".to_string(),
+                    "pan"           => "4111111111111111".to_string(),
+                    "expirydate"    => "12/2099".to_string(),
+                    "securitycode"  => "123".to_string(),
+                },
+                Ok(Operation {
+                    payment: Some(
+                        Payment::card("4111111111111111", "12/2099", "123", "Ben Jones").unwrap(),
+                    ),
+                    ..example_operation()
+                }),
+            ),
+            (
+                map! {
+                    "billingname"   => "Ben Jones".to_string(),
+                    "currencyiso3a" => "GBP".to_string(),
+                    "baseamount"    => "12345".to_string(),
+                    "pan"           => "4111111111111111".to_string(),
+                    "expirydate"    => "12/2020".to_string(),
                     "securitycode"  => "123".to_string(),
                 },
-                Ok(example_operation()),
+                Err(GatewayError::FieldError(
+                    "Invalid expirydate: card has expired".into(),
+                )),
             ),
             (
                 map! {
                     "billingname"   => "Ben Jones".to_string(),
                     "currencyiso3a" => "GBP".to_string(),
                     "baseamount"    => "123oops".to_string(),
-                    "pan"           => "4000000000000000".to_string(),
-                    "expirydate"    => "12/2024".to_string(),
+                    "pan"           => "4111111111111111".to_string(),
+                    "expirydate"    => "12/2099".to_string(),
                     "securitycode"  => "123".to_string(),
                 },
                 Err(GatewayError::FieldError(
@@ -158,12 +599,297 @@ mod tests {
                 map! {
                     "billingname"   => "Ben Jones".to_string(),
                     "baseamount"    => "12345".to_string(),
-                    "pan"           => "4000000000000000".to_string(),
-                    "expirydate"    => "12/2024".to_string(),
+                    "pan"           => "4111111111111111".to_string(),
+                    "expirydate"    => "12/2099".to_string(),
                     "securitycode"  => "123".to_string(),
                 },
                 Err(GatewayError::FieldError("Missing currencyiso3a".into())),
             ),
+            (
+                map! {
+                    "billingname"   => "Ben Jones".to_string(),
+                    "currencyiso3a" => "GBP".to_string(),
+                    "mainamount"    => "123.45".to_string(),
+                    "pan"           => "4111111111111111".to_string(),
+                    "expirydate"    => "12/2099".to_string(),
+                    "securitycode"  => "123".to_string(),
+                },
+                Ok(Operation {
+                    payment: Some(
+                        Payment::card("4111111111111111", "12/2099", "123", "Ben Jones").unwrap(),
+                    ),
+                    ..example_operation()
+                }),
+            ),
+            (
+                map! {
+                    "billingname"   => "Ben Jones".to_string(),
+                    "currencyiso3a" => "JPY".to_string(),
+                    "mainamount"    => "123.45".to_string(),
+                    "pan"           => "4111111111111111".to_string(),
+                    "expirydate"    => "12/2099".to_string(),
+                    "securitycode"  => "123".to_string(),
+                },
+                Err(GatewayError::FieldError(
+                    "Invalid amount: JPY has 0 decimal places but 123.45 has 2".into(),
+                )),
+            ),
+            (
+                map! {
+                    "billingname"   => "Ben Jones".to_string(),
+                    "currencyiso3a" => "GBP".to_string(),
+                    "baseamount"    => "-100".to_string(),
+                    "pan"           => "4111111111111111".to_string(),
+                    "expirydate"    => "12/2099".to_string(),
+                    "securitycode"  => "123".to_string(),
+                },
+                Err(GatewayError::FieldError(
+                    "Invalid amount: must not be negative".into(),
+                )),
+            ),
+            (
+                map! {
+                    "billingname"   => "Ben Jones".to_string(),
+                    "currencyiso3a" => "GBP".to_string(),
+                    "baseamount"    => "12345".to_string(),
+                    "cardtoken"     => "4111111111111111".to_string(),
+                    "expirydate"    => "12/2099".to_string(),
+                    "securitycode"  => "123".to_string(),
+                },
+                Err(GatewayError::FieldError(
+                    "Invalid cardtoken: passes Luhn check, is it a pan?".into(),
+                )),
+            ),
+            (
+                map! {
+                    "billingname"     => "Ben Jones".to_string(),
+                    "currencyiso3a"   => "GBP".to_string(),
+                    "baseamount"      => "12345".to_string(),
+                    "pan"             => "4111111111111111".to_string(),
+                    "expirydate"      => "12/2099".to_string(),
+                    "securitycode"    => "123".to_string(),
+                    "threedsstatus"   => "N".to_string(),
+                    "eci"             => "07".to_string(),
+                    "dstransactionid" => "f25084f0-5b16-4c0a-ae5d-b24808a95e4b".to_string(),
+                    "threedsversion"  => "2.2.0".to_string(),
+                },
+                Ok(Operation {
+                    payment: Some(
+                        Payment::card("4111111111111111", "12/2099", "123", "Ben Jones").unwrap(),
+                    ),
+                    three_d_secure: Some(
+                        crate::three_d_secure::ThreeDSecure::new(
+                            crate::card::CardNetwork::Visa,
+                            crate::three_d_secure::AuthenticationStatus::NotAuthenticated,
+                            "07",
+                            None,
+                            "f25084f0-5b16-4c0a-ae5d-b24808a95e4b",
+                            "2.2.0",
+                        )
+                        .unwrap(),
+                    ),
+                    ..example_operation()
+                }),
+            ),
+            (
+                map! {
+                    "billingname"   => "Ben Jones".to_string(),
+                    "currencyiso3a" => "GBP".to_string(),
+                    "baseamount"    => "12345".to_string(),
+                    "dpan"          => "4111111111111111".to_string(),
+                    "expirydate"    => "12/2099".to_string(),
+                    "cryptogram"    => "AgAAAAAABk4DWZ4C28yUQAAAAAA=".to_string(),
+                    "eci"           => "05".to_string(),
+                    "wallettype"    => "GOOGLEPAY".to_string(),
+                },
+                Ok(Operation {
+                    payment: Some(
+                        Payment::wallet(
+                            "4111111111111111",
+                            "12/2099",
+                            "AgAAAAAABk4DWZ4C28yUQAAAAAA=",
+                            "05",
+                            crate::card::WalletType::GooglePay,
+                        )
+                        .unwrap(),
+                    ),
+                    ..example_operation()
+                }),
+            ),
+            (
+                map! {
+                    "billingname"   => "Ben Jones".to_string(),
+                    "currencyiso3a" => "GBP".to_string(),
+                    "baseamount"    => "12345".to_string(),
+                    "iban"          => "DE89370400440532013000".to_string(),
+                    "bic"           => "DEUTDEFF".to_string(),
+                },
+                Ok(Operation {
+                    payment: Some(
+                        Payment::iban("DE89370400440532013000", Some("DEUTDEFF"), "Ben Jones")
+                            .unwrap(),
+                    ),
+                    ..example_operation()
+                }),
+            ),
+            (
+                map! {
+                    "billingname"   => "Ben Jones".to_string(),
+                    "currencyiso3a" => "GBP".to_string(),
+                    "baseamount"    => "12345".to_string(),
+                    "accountnumber" => "12345678".to_string(),
+                },
+                Err(GatewayError::FieldError("Missing sortcode".into())),
+            ),
+            (
+                map! {
+                    "billingname"   => "Ben Jones".to_string(),
+                    "currencyiso3a" => "GBP".to_string(),
+                    "baseamount"    => "12345".to_string(),
+                    "accountnumber" => "12345678".to_string(),
+                    "sortcode"      => "30-12-34".to_string(),
+                },
+                Err(GatewayError::ValidationError("No modulus table installed".into())),
+            ),
+            (
+                map! {
+                    "billingname"         => "Ben Jones".to_string(),
+                    "currencyiso3a"       => "GBP".to_string(),
+                    "baseamount"          => "12345".to_string(),
+                    "pan"                 => "4111111111111111".to_string(),
+                    "expirydate"          => "12/2099".to_string(),
+                    "securitycode"        => "123".to_string(),
+                    "billingpremise"      => "10".to_string(),
+                    "billingstreet"       => "Downing Street".to_string(),
+                    "billingtown"         => "London".to_string(),
+                    "billingpostcode"     => "sw1a 2aa".to_string(),
+                    "billingcountryiso2a" => "gb".to_string(),
+                    "billingemail"        => "ben@example.com".to_string(),
+                },
+                Ok(Operation {
+                    payment: Some(
+                        Payment::card("4111111111111111", "12/2099", "123", "Ben Jones").unwrap(),
+                    ),
+                    transaction: example_operation().transaction.map(|transaction| {
+                        transaction.with_customer(
+                            crate::customer::Customer::new(Some("ben@example.com"), None)
+                                .unwrap()
+                                .with_billing_address(crate::customer::test_address()),
+                        )
+                    }),
+                    ..example_operation()
+                }),
+            ),
+            (
+                map! {
+                    "billingname"          => "Ben Jones".to_string(),
+                    "currencyiso3a"        => "GBP".to_string(),
+                    "baseamount"           => "12345".to_string(),
+                    "pan"                  => "4111111111111111".to_string(),
+                    "expirydate"           => "12/2099".to_string(),
+                    "securitycode"         => "123".to_string(),
+                    "customerpremise"      => "10".to_string(),
+                    "customercountryiso2a" => "GB".to_string(),
+                },
+                Err(GatewayError::FieldError("Missing customerpostcode".into())),
+            ),
+            (
+                map! {
+                    "billingname"    => "Ben Jones".to_string(),
+                    "currencyiso3a"  => "GBP".to_string(),
+                    "baseamount"     => "12345".to_string(),
+                    "pan"            => "4111111111111111".to_string(),
+                    "expirydate"     => "12/2099".to_string(),
+                    "securitycode"   => "123".to_string(),
+                    "orderreference" => "ORD-2024-0001".to_string(),
+                    "descriptorname" => "Acme*Order 42".to_string(),
+                    "descriptorcity" => "London".to_string(),
+                },
+                Ok(Operation {
+                    payment: Some(
+                        Payment::card("4111111111111111", "12/2099", "123", "Ben Jones").unwrap(),
+                    ),
+                    order_reference: Some("ORD-2024-0001".into()),
+                    soft_descriptor: Some(
+                        crate::soft_descriptor::SoftDescriptor::new(
+                            Some(crate::card::CardNetwork::Visa),
+                            "ACME*ORDER 42",
+                            Some("LONDON"),
+                            None,
+                        )
+                        .unwrap(),
+                    ),
+                    ..example_operation()
+                }),
+            ),
+            (
+                map! {
+                    "billingname"    => "Ben Jones".to_string(),
+                    "currencyiso3a"  => "GBP".to_string(),
+                    "baseamount"     => "12345".to_string(),
+                    "pan"            => "5555555555554444".to_string(),
+                    "expirydate"     => "12/2099".to_string(),
+                    "securitycode"   => "123".to_string(),
+                    "descriptorname" => "Acme Widgets #42".to_string(),
+                },
+                Err(GatewayError::FieldError(
+                    "Invalid descriptorname: '#' cannot be shown by MASTERCARD".into(),
+                )),
+            ),
+            (
+                map! {
+                    "billingname"          => "Ben Jones".to_string(),
+                    "currencyiso3a"        => "GBP".to_string(),
+                    "baseamount"           => "12345".to_string(),
+                    "track2"               => ";4111111111111111=99122011234567890?".to_string(),
+                    "posentrymode"         => "SWIPE".to_string(),
+                    "terminalid"           => "TERM0001".to_string(),
+                    "terminalcapabilities" => "CSP".to_string(),
+                    "cvm"                  => "SIGNATURE".to_string(),
+                },
+                Ok(Operation {
+                    payment: Some(Payment::swiped_card(&swiped(), "Ben Jones").unwrap()),
+                    card_present: Some(
+                        CardPresent::new(
+                            PosEntryMode::Swipe,
+                            Some(swiped()),
+                            "TERM0001",
+                            "CSP".parse().unwrap(),
+                            Cvm::Signature,
+                            None,
+                        )
+                        .unwrap(),
+                    ),
+                    ..example_operation()
+                }),
+            ),
+            (
+                map! {
+                    "billingname"          => "Ben Jones".to_string(),
+                    "currencyiso3a"        => "GBP".to_string(),
+                    "baseamount"           => "12345".to_string(),
+                    "pan"                  => "5555555555554444".to_string(),
+                    "expirydate"           => "12/2099".to_string(),
+                    "securitycode"         => "123".to_string(),
+                    "track2"               => "4111111111111111=99122011234567890".to_string(),
+                    "posentrymode"         => "SWIPE".to_string(),
+                    "terminalid"           => "TERM0001".to_string(),
+                    "terminalcapabilities" => "S".to_string(),
+                    "cvm"                  => "SIGNATURE".to_string(),
+                },
+                Err(GatewayError::FieldError(
+                    "Invalid track2: pan does not match the card's pan".into(),
+                )),
+            ),
+            (
+                map! {
+                    "billingname"   => "Ben Jones".to_string(),
+                    "currencyiso3a" => "GBP".to_string(),
+                    "baseamount"    => "12345".to_string(),
+                    "track2"        => "4111111111111111=99122011234567890".to_string(),
+                },
+                Err(GatewayError::FieldError("Missing posentrymode".into())),
+            ),
         ];
         for (hm, expected) in tests.into_iter() {
             let res = Operation::try_from(hm);
@@ -171,25 +897,3 @@ mod tests {
         }
     }
 }
-
-#[cfg(test)]
-pub fn example_operation() -> crate::operation::Operation {
-    use crate::merchant::test_merchant;
-
-    crate::operation::Operation {
-        payment: Some(crate::payment::Payment::card(
-            "4000000000000000",
-            "12/2024",
-            "123",
-            "Ben Jones",
-        )),
-        transaction: Some(crate::transaction::Transaction {
-            amount: 12345,
-            currency: crate::currency::Currency::GBP,
-            billingname: "Ben Jones".into(),
-        }),
-        merchant: Some(test_merchant()),
-        bank: Some(crate::bank::Bank::Ems),
-        request_type: Some(crate::operation::RequestType::Auth),
-    }
-}
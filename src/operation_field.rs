@@ -6,9 +6,15 @@ use crate::{bank::Bank, GatewayError};
 #[derive(Debug, Clone, PartialEq)]
 pub struct Mid(String);
 
+impl std::fmt::Display for Mid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 macro_rules! regex {
-    ($name:ident, $pattern:expr) => {
-        const $name: std::sync::LazyLock<regex::Regex> =
+    ($vis:vis $name:ident, $pattern:expr) => {
+        $vis static $name: std::sync::LazyLock<regex::Regex> =
             std::sync::LazyLock::new(|| regex::Regex::new($pattern).unwrap());
     };
 }
@@ -19,7 +25,6 @@ macro_rules! get_ctx {
         *$ctx.unwrap().downcast::<$type>().unwrap()
     };
 }
-pub(crate) use get_ctx;
 
 macro_rules! ctx {
     () => {
@@ -33,8 +38,7 @@ pub(crate) use ctx;
 
 type Ctx = Option<Box<dyn Any>>;
 
-regex!(MID_REGEX, "[0-9]+");
-regex!(STFS_MID_REGEX, "^0001049[0-9]{8}$");
+regex!(pub(crate) MID_REGEX, "[0-9]+");
 
 pub struct ValidationContext {}
 
@@ -47,12 +51,13 @@ impl Validator for Mid {
     type Output = Mid;
 
     fn validate(value: &str, ctx: Ctx) -> Result<Mid> {
-        let bank = get_ctx!(ctx, Bank);
+        // merchants created outside of a bank context only get the generic check
+        let bank = ctx.is_some().then(|| get_ctx!(ctx, Bank));
         let ptn = match bank {
-            Bank::Stfs => STFS_MID_REGEX,
-            _ => MID_REGEX,
+            Some(bank) => bank.acquirer()?.mid_pattern(),
+            None => &MID_REGEX,
         };
-        if !ptn.is_match(&value) {
+        if !ptn.is_match(value) {
             Err(GatewayError::ValidationError(format!(
                 "mid '{value}' does not match regex {}",
                 ptn.as_str()
@@ -73,9 +78,9 @@ mod tests {
         let mid = Mid::validate("00010491231231289", ctx!(bank));
         assert_eq!(
             mid,
-            Err(GatewayError::ValidationError(format!(
-                "mid '00010491231231289' does not match regex ^0001049[0-9]{{8}}$"
-            )))
+            Err(GatewayError::ValidationError(
+                "mid '00010491231231289' does not match regex ^0001049[0-9]{8}$".into()
+            ))
         );
         let mid = Mid::validate("000104912312312", ctx!(bank));
         assert_eq!(mid, Ok(Mid("000104912312312".into())));
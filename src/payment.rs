@@ -1,37 +1,577 @@
+use crate::{
+    bin_range::{self, BinInfo},
+    card::{CardExpiry, CardNetwork, CardNumber, WalletType},
+    card_present::Track2,
+    clock::{Clock, SystemClock},
+    iban,
+    modulus::{self, ModulusTable},
+    operation_field::Mid,
+    secret::Secret,
+    sort_code,
+    three_d_secure::CAVV_REGEX,
+    vault::Vault,
+    GatewayError, Result,
+};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Payment {
     Card {
-        pan: String,
-        expiry_date: String,
-        security_code: String,
+        number: CardNumber,
+        expiry_date: CardExpiry,
+        /// Absent when the card was swiped, as track 2 has no security code
+        security_code: Option<Secret>,
         name: String,
-        network: String
+        bin: BinInfo,
     },
     Account {
-        account_number: String,
+        account_number: Secret,
         sort_code: String,
         name: String,
         bank_name: String,
     },
+    /// A SEPA account
+    Iban {
+        iban: Secret,
+        bic: Option<String>,
+        name: String,
+    },
+    /// A device PAN from a wallet or network token, authenticated by its cryptogram instead of a security code
+    Wallet {
+        dpan: Secret,
+        expiry_date: CardExpiry,
+        cryptogram: Secret,
+        eci: String,
+        wallet: WalletType,
+        bin: BinInfo,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PaymentType {
+    Card,
+    Account,
+    Iban,
+    Wallet,
 }
 
 impl Payment {
-    pub fn card(pan: &str, expiry_date: &str, security_code: &str, name: &str) -> Self {
-        Self::Card {
-            pan: pan.into(),
-            expiry_date: expiry_date.into(),
-            security_code: security_code.into(),
+    pub fn payment_type(&self) -> PaymentType {
+        match self {
+            Payment::Card { .. } => PaymentType::Card,
+            Payment::Account { .. } => PaymentType::Account,
+            Payment::Iban { .. } => PaymentType::Iban,
+            Payment::Wallet { .. } => PaymentType::Wallet,
+        }
+    }
+
+    pub fn network(&self) -> Option<CardNetwork> {
+        match self {
+            Payment::Card { bin, .. } | Payment::Wallet { bin, .. } => Some(bin.network),
+            Payment::Account { .. } | Payment::Iban { .. } => None,
+        }
+    }
+
+    pub fn card(pan: &str, expiry_date: &str, security_code: &str, name: &str) -> Result<Self> {
+        Self::card_at(pan, expiry_date, security_code, name, &SystemClock)
+    }
+
+    /// Validates and builds a card payment, checking the expiry date against `clock`
+    pub fn card_at(
+        pan: &str,
+        expiry_date: &str,
+        security_code: &str,
+        name: &str,
+        clock: &dyn Clock,
+    ) -> Result<Self> {
+        let bin = validate_card_number("pan", pan, true)?;
+        Self::validated_card(
+            CardNumber::Pan(pan.into()),
+            bin,
+            expiry_date,
+            security_code,
+            name,
+            clock,
+        )
+    }
+
+    pub fn tokenised_card(
+        token: &str,
+        expiry_date: &str,
+        security_code: &str,
+        name: &str,
+    ) -> Result<Self> {
+        Self::tokenised_card_at(token, expiry_date, security_code, name, &SystemClock)
+    }
+
+    /// Builds a card payment from a vault token, which is validated like a PAN except that it must fail the Luhn check
+    pub fn tokenised_card_at(
+        token: &str,
+        expiry_date: &str,
+        security_code: &str,
+        name: &str,
+        clock: &dyn Clock,
+    ) -> Result<Self> {
+        let bin = validate_card_number("cardtoken", token, false)?;
+        Self::validated_card(
+            CardNumber::Token(token.into()),
+            bin,
+            expiry_date,
+            security_code,
+            name,
+            clock,
+        )
+    }
+
+    fn validated_card(
+        number: CardNumber,
+        bin: BinInfo,
+        expiry_date: &str,
+        security_code: &str,
+        name: &str,
+        clock: &dyn Clock,
+    ) -> Result<Self> {
+        let expiry_date: CardExpiry = expiry_date.parse()?;
+        if expiry_date.is_expired(clock) {
+            return Err(invalid("expirydate", "card has expired"));
+        }
+        validate_security_code(security_code, bin.network)?;
+        Ok(Self::Card {
+            number,
+            expiry_date,
+            security_code: Some(security_code.into()),
             name: name.into(),
-            network: get_network_from_pan(&pan)
+            bin,
+        })
+    }
+
+    pub fn swiped_card(track2: &Track2, name: &str) -> Result<Self> {
+        Self::swiped_card_at(track2, name, &SystemClock)
+    }
+
+    /// Builds a card payment from track 2 data read at a terminal, which has no security code
+    pub fn swiped_card_at(track2: &Track2, name: &str, clock: &dyn Clock) -> Result<Self> {
+        let bin = validate_card_number("pan", track2.pan.reveal(), true)?;
+        if track2.expiry_date.is_expired(clock) {
+            return Err(invalid("expirydate", "card has expired"));
+        }
+        Ok(Self::Card {
+            number: CardNumber::Pan(track2.pan.clone()),
+            expiry_date: track2.expiry_date,
+            security_code: None,
+            name: name.into(),
+            bin,
+        })
+    }
+
+    pub fn wallet(
+        dpan: &str,
+        expiry_date: &str,
+        cryptogram: &str,
+        eci: &str,
+        wallet: WalletType,
+    ) -> Result<Self> {
+        Self::wallet_at(dpan, expiry_date, cryptogram, eci, wallet, &SystemClock)
+    }
+
+    /// Validates and builds a wallet payment, the device PAN is checked like any other PAN
+    pub fn wallet_at(
+        dpan: &str,
+        expiry_date: &str,
+        cryptogram: &str,
+        eci: &str,
+        wallet: WalletType,
+        clock: &dyn Clock,
+    ) -> Result<Self> {
+        let bin = validate_card_number("dpan", dpan, true)?;
+        let expiry_date: CardExpiry = expiry_date.parse()?;
+        if expiry_date.is_expired(clock) {
+            return Err(invalid("expirydate", "card has expired"));
+        }
+        if !CAVV_REGEX.is_match(cryptogram) {
+            return Err(invalid("cryptogram", "must be 20 bytes of base64"));
+        }
+        if eci.len() != 2 || !eci.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid("eci", "must be 2 digits"));
+        }
+        Ok(Self::Wallet {
+            dpan: dpan.into(),
+            expiry_date,
+            cryptogram: cryptogram.into(),
+            eci: eci.into(),
+            wallet,
+            bin,
+        })
+    }
+
+    /// Validates and builds a UK bank account payment, the bank name comes from the sort code directory
+    pub fn account(account_number: &str, sort_code: &str, name: &str) -> Result<Self> {
+        Self::account_with_table(account_number, sort_code, name, &*modulus::installed()?)
+    }
+
+    /// As [`Payment::account`], checking the account number against `table` rather than the installed one
+    pub fn account_with_table(
+        account_number: &str,
+        sort_code: &str,
+        name: &str,
+        table: &ModulusTable,
+    ) -> Result<Self> {
+        let sort_code = sort_code.replace('-', "");
+        if sort_code.len() != 6 || !sort_code.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid("sortcode", "must be 6 digits"));
+        }
+        if !(6..=8).contains(&account_number.len())
+            || !account_number.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid("accountnumber", "must be 6 to 8 digits"));
+        }
+        // shorter account numbers are padded to 8 digits before being checked
+        let account_number = format!("{account_number:0>8}");
+        if !table.check(&sort_code, &account_number) {
+            return Err(invalid("accountnumber", "fails modulus check"));
+        }
+        Ok(Self::Account {
+            bank_name: sort_code::lookup(&sort_code)?,
+            account_number: account_number.as_str().into(),
+            sort_code,
+            name: name.into(),
+        })
+    }
+
+    /// Validates and builds a SEPA account payment, the BIC is optional
+    pub fn iban(iban: &str, bic: Option<&str>, name: &str) -> Result<Self> {
+        Ok(Self::Iban {
+            iban: iban::validate_iban(iban)?.as_str().into(),
+            bic: bic.map(iban::validate_bic).transpose()?,
+            name: name.into(),
+        })
+    }
+
+    /// Swaps a card's PAN for the merchant's token for it, anything else is returned unchanged
+    pub fn tokenise(self, vault: &dyn Vault, mid: &Mid) -> Result<Self> {
+        match self {
+            Payment::Card {
+                number: CardNumber::Pan(pan),
+                expiry_date,
+                security_code,
+                name,
+                bin,
+            } => Ok(Payment::Card {
+                number: CardNumber::Token(vault.tokenise(pan.reveal(), mid)?),
+                expiry_date,
+                security_code,
+                name,
+                bin,
+            }),
+            payment => Ok(payment),
         }
     }
 }
 
-fn get_network_from_pan(pan: &str) -> String {
-    match pan[0..4].parse::<i32>().expect("invalid pan") {
-        4000..5000 => "VISA",
-        5000..7000 => "MASTERCARD",
-        _ => panic!("invalid pan")
-    }.into()
+fn invalid(field: &str, reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid {field}: {reason}"))
+}
 
+/// Checks a PAN, or a token standing in for one which must fail the Luhn check instead
+fn validate_card_number(field: &str, number: &str, is_pan: bool) -> Result<BinInfo> {
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid(field, "must only contain digits"));
+    }
+    match (is_pan, luhn(number)) {
+        (true, false) => return Err(invalid(field, "fails Luhn check")),
+        (false, true) => return Err(invalid(field, "passes Luhn check, is it a pan?")),
+        _ => (),
+    }
+    let bin = bin_range::lookup(number)?;
+    if !bin.network.pan_lengths().contains(&number.len()) {
+        return Err(invalid(
+            field,
+            &format!("length {} is not valid for {}", number.len(), bin.network),
+        ));
+    }
+    Ok(bin)
+}
+
+pub(crate) fn luhn(pan: &str) -> bool {
+    let sum: u32 = pan
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| match (i % 2, d * 2) {
+            (1, doubled) if doubled > 9 => doubled - 9,
+            (1, doubled) => doubled,
+            _ => d,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+fn validate_security_code(security_code: &str, network: CardNetwork) -> Result<()> {
+    let length = network.security_code_length();
+    if security_code.len() != length || !security_code.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid(
+            "securitycode",
+            &format!("must be {length} digits for {network}"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        clock::test_clock,
+        operation_field::{ctx, Validator},
+        vault::test_vault,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_card() {
+        let tests = [
+            (("4111111111111111", "12/2024", "123"), Ok(())),
+            (("4111111111111111", "06/24", "123"), Ok(())),
+            (("371449635398431", "01/30", "1234"), Ok(())),
+            (("5555555555554444", "12/2099", "999"), Ok(())),
+            (
+                ("4111111111111112", "12/2024", "123"),
+                Err("Invalid pan: fails Luhn check"),
+            ),
+            (
+                ("4111 1111", "12/2024", "123"),
+                Err("Invalid pan: must only contain digits"),
+            ),
+            (
+                ("", "12/2024", "123"),
+                Err("Invalid pan: must only contain digits"),
+            ),
+            (
+                ("4111111111111111111", "12/2024", "123"),
+                Err("Invalid pan: fails Luhn check"),
+            ),
+            (
+                ("41111111111114", "12/2024", "123"),
+                Err("Invalid pan: length 14 is not valid for VISA"),
+            ),
+            (
+                ("9111111111111110", "12/2024", "123"),
+                Err("Invalid pan: no BIN range matches it"),
+            ),
+            (
+                ("4111111111111111", "05/2024", "123"),
+                Err("Invalid expirydate: card has expired"),
+            ),
+            (
+                ("4111111111111111", "13/2024", "123"),
+                Err("Invalid expirydate: month must be between 01 and 12"),
+            ),
+            (
+                ("4111111111111111", "12-2024", "123"),
+                Err("Invalid expirydate: must be MM/YY or MM/YYYY"),
+            ),
+            (
+                ("4111111111111111", "1/2024", "123"),
+                Err("Invalid expirydate: must be MM/YY or MM/YYYY"),
+            ),
+            (
+                ("4111111111111111", "12/2024", "1234"),
+                Err("Invalid securitycode: must be 3 digits for VISA"),
+            ),
+            (
+                ("371449635398431", "12/2024", "123"),
+                Err("Invalid securitycode: must be 4 digits for AMEX"),
+            ),
+            (
+                ("4111111111111111", "12/2024", "12a"),
+                Err("Invalid securitycode: must be 3 digits for VISA"),
+            ),
+        ];
+        for (i, ((pan, expiry_date, security_code), expected)) in tests.into_iter().enumerate() {
+            let actual =
+                Payment::card_at(pan, expiry_date, security_code, "Ben Jones", &test_clock());
+            assert_eq!(
+                expected.map_err(|err| GatewayError::FieldError(err.into())),
+                actual.map(|_| ()),
+                "Case number {}",
+                i + 1
+            );
+        }
+    }
+
+    #[test]
+    fn test_tokenise() {
+        let vault = test_vault("payment");
+        let mid = Mid::validate("000104912345678", ctx!()).unwrap();
+        let card = Payment::card_at(
+            "4111111111111111",
+            "12/24",
+            "123",
+            "Ben Jones",
+            &test_clock(),
+        )
+        .unwrap();
+        let tokenised = card.tokenise(&vault, &mid).unwrap();
+        let Payment::Card {
+            number: CardNumber::Token(token),
+            ..
+        } = &tokenised
+        else {
+            panic!("card was not tokenised: {tokenised:?}");
+        };
+        assert_eq!(
+            Ok(tokenised.clone()),
+            Payment::tokenised_card_at(token, "12/24", "123", "Ben Jones", &test_clock())
+        );
+        assert_eq!(
+            Ok(tokenised.clone()),
+            tokenised.clone().tokenise(&vault, &mid)
+        );
+        assert_eq!(
+            Err(GatewayError::FieldError(
+                "Invalid cardtoken: passes Luhn check, is it a pan?".into()
+            )),
+            Payment::tokenised_card_at(
+                "4111111111111111",
+                "12/24",
+                "123",
+                "Ben Jones",
+                &test_clock()
+            )
+        );
+    }
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let card = Payment::card_at(
+            "4111111111111111",
+            "12/24",
+            "987",
+            "Ben Jones",
+            &test_clock(),
+        )
+        .unwrap();
+        let debug = format!("{card:?}");
+        assert!(!debug.contains("4111111111111111"), "{debug}");
+        assert!(!debug.contains("987"), "{debug}");
+    }
+
+    #[test]
+    fn test_account() {
+        let tests = [
+            (
+                ("12345678", "30-12-34"),
+                Ok(("12345678", "301234", "Lloyds Bank")),
+            ),
+            (
+                ("1234567", "401234"),
+                Ok(("01234567", "401234", "HSBC UK Bank")),
+            ),
+            (
+                ("12345678", "3012345"),
+                Err("Invalid sortcode: must be 6 digits"),
+            ),
+            (
+                ("12345", "301234"),
+                Err("Invalid accountnumber: must be 6 to 8 digits"),
+            ),
+            (
+                ("1234567a", "301234"),
+                Err("Invalid accountnumber: must be 6 to 8 digits"),
+            ),
+            (
+                ("66374959", "089999"),
+                Err("Invalid accountnumber: fails modulus check"),
+            ),
+            (
+                ("12345678", "990000"),
+                Err("Invalid sortcode: not in the sort code directory"),
+            ),
+        ];
+        let table =
+            ModulusTable::parse("089000 089999 MOD10 0 0 0 0 0 0 7 1 3 7 1 3 7 1").unwrap();
+        for (i, ((account_number, sort_code), expected)) in tests.into_iter().enumerate() {
+            let actual =
+                Payment::account_with_table(account_number, sort_code, "Ben Jones", &table);
+            let expected = expected
+                .map(|(account_number, sort_code, bank_name)| Payment::Account {
+                    account_number: account_number.into(),
+                    sort_code: sort_code.into(),
+                    name: "Ben Jones".into(),
+                    bank_name: bank_name.into(),
+                })
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+        assert_eq!(
+            Err(GatewayError::ValidationError("No modulus table installed".into())),
+            Payment::account("12345678", "301234", "Ben Jones")
+        );
+    }
+
+    #[test]
+    fn test_iban() {
+        let tests = [
+            (("DE89 3704 0044 0532 0130 00", Some("DEUTDEFF")), Ok(())),
+            (("DE89370400440532013000", None), Ok(())),
+            (
+                ("DE89370400440532013001", None),
+                Err("Invalid iban: fails checksum"),
+            ),
+            (
+                ("DE89370400440532013000", Some("DEUTDEF")),
+                Err("Invalid bic: must be 8 or 11 characters"),
+            ),
+        ];
+        for (i, ((iban, bic), expected)) in tests.into_iter().enumerate() {
+            let actual = Payment::iban(iban, bic, "Ben Jones");
+            let expected = expected
+                .map(|_| Payment::Iban {
+                    iban: "DE89370400440532013000".into(),
+                    bic: bic.map(String::from),
+                    name: "Ben Jones".into(),
+                })
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_wallet() {
+        let cryptogram = "AgAAAAAABk4DWZ4C28yUQAAAAAA=";
+        let tests = [
+            (("4111111111111111", "12/24", cryptogram, "05"), Ok(())),
+            (
+                ("4111111111111112", "12/24", cryptogram, "05"),
+                Err("Invalid dpan: fails Luhn check"),
+            ),
+            (
+                ("4111111111111111", "05/24", cryptogram, "05"),
+                Err("Invalid expirydate: card has expired"),
+            ),
+            (
+                ("4111111111111111", "12/24", "123", "05"),
+                Err("Invalid cryptogram: must be 20 bytes of base64"),
+            ),
+            (
+                ("4111111111111111", "12/24", cryptogram, "5"),
+                Err("Invalid eci: must be 2 digits"),
+            ),
+        ];
+        for (i, ((dpan, expiry_date, cryptogram, eci), expected)) in tests.into_iter().enumerate() {
+            let actual = Payment::wallet_at(
+                dpan,
+                expiry_date,
+                cryptogram,
+                eci,
+                WalletType::ApplePay,
+                &test_clock(),
+            )
+            .map(|payment| (payment.payment_type(), payment.network()));
+            let expected = expected
+                .map(|_| (PaymentType::Wallet, Some(CardNetwork::Visa)))
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+    }
 }
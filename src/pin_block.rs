@@ -0,0 +1,353 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use zeroize::Zeroizing;
+
+use crate::{
+    key_store::{KeyAlgorithm, KeyStore},
+    secret::Secret,
+    GatewayError, Result,
+};
+
+fn invalid(reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid pinblock: {reason}"))
+}
+
+/// The ISO 9564-1 formats a PIN can be sent in
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PinBlockFormat {
+    /// The PIN padded with `F` and combined with the PAN
+    Iso0,
+    /// The PIN padded with random digits, for when the PAN is not available
+    Iso1,
+    /// The PIN padded with random `A` to `F` and combined with the PAN
+    Iso3,
+    /// The 16 byte AES format, the PIN and PAN are combined between two encryptions
+    Iso4,
+}
+
+impl PinBlockFormat {
+    pub fn code(&self) -> &'static str {
+        match self {
+            PinBlockFormat::Iso0 => "0",
+            PinBlockFormat::Iso1 => "1",
+            PinBlockFormat::Iso3 => "3",
+            PinBlockFormat::Iso4 => "4",
+        }
+    }
+
+    /// Formats 0 to 3 are encrypted with TDES, format 4 with AES
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        match self {
+            PinBlockFormat::Iso4 => KeyAlgorithm::Aes,
+            _ => KeyAlgorithm::Tdes,
+        }
+    }
+
+    fn control(&self) -> u8 {
+        self.code().as_bytes()[0] - b'0'
+    }
+}
+
+/// A PIN block encrypted under a PIN encryption or zone key, the PIN itself is never kept
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptedPinBlock {
+    pub format: PinBlockFormat,
+    /// The name of the key in the [`KeyStore`] the block is encrypted under
+    pub key_name: String,
+    pub block: Vec<u8>,
+}
+
+impl EncryptedPinBlock {
+    /// Builds the PIN block for `pin` and `pan` then encrypts it under `key_name`
+    pub fn new(
+        key_store: &dyn KeyStore,
+        key_name: &str,
+        format: PinBlockFormat,
+        pin: &Secret,
+        pan: &Secret,
+    ) -> Result<Self> {
+        check_key(key_store, key_name, format)?;
+        let pin_field = pin_field(format, pin.reveal())?;
+        let block = match format {
+            PinBlockFormat::Iso4 => {
+                let mut intermediate = key_store.encrypt(key_name, &pin_field)?;
+                xor(&mut intermediate, &iso4_pan_field(pan.reveal())?);
+                key_store.encrypt(key_name, &intermediate)?
+            }
+            _ => {
+                let mut clear = Zeroizing::new(pin_field.to_vec());
+                if format != PinBlockFormat::Iso1 {
+                    xor(&mut clear, &pan_field(pan.reveal())?);
+                }
+                key_store.encrypt(key_name, &clear)?
+            }
+        };
+        Ok(Self {
+            format,
+            key_name: key_name.into(),
+            block,
+        })
+    }
+
+    /// Decrypts the block and checks its layout, `pan` must be the one it was built with
+    pub fn pin(&self, key_store: &dyn KeyStore, pan: &Secret) -> Result<Secret> {
+        check_key(key_store, &self.key_name, self.format)?;
+        let clear = match self.format {
+            PinBlockFormat::Iso4 => {
+                let mut intermediate = key_store.decrypt(&self.key_name, &self.block)?;
+                xor(&mut intermediate, &iso4_pan_field(pan.reveal())?);
+                key_store.decrypt(&self.key_name, &intermediate)?
+            }
+            _ => {
+                let mut clear = key_store.decrypt(&self.key_name, &self.block)?;
+                if self.format != PinBlockFormat::Iso1 {
+                    xor(&mut clear, &pan_field(pan.reveal())?);
+                }
+                clear
+            }
+        };
+        extract_pin(self.format, &Zeroizing::new(clear))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode_upper(&self.block)
+    }
+}
+
+fn check_key(key_store: &dyn KeyStore, key_name: &str, format: PinBlockFormat) -> Result<()> {
+    let algorithm = key_store.algorithm(key_name)?;
+    if algorithm != format.algorithm() {
+        return Err(GatewayError::KeyError(format!(
+            "Key {key_name} is {algorithm:?} but format {} needs {:?}",
+            format.code(),
+            format.algorithm()
+        )));
+    }
+    Ok(())
+}
+
+fn xor(block: &mut [u8], field: &[u8]) {
+    for (byte, other) in block.iter_mut().zip(field) {
+        *byte ^= other;
+    }
+}
+
+/// Packs pairs of nibbles into bytes
+fn pack(nibbles: &[u8]) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(
+        nibbles
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect(),
+    )
+}
+
+fn pin_field(format: PinBlockFormat, pin: &str) -> Result<Zeroizing<Vec<u8>>> {
+    if !(4..=12).contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(GatewayError::FieldError(
+            "Invalid pin: must be 4 to 12 digits".into(),
+        ));
+    }
+    let mut random = Zeroizing::new([0u8; 16]);
+    OsRng.fill_bytes(random.as_mut());
+    let mut nibbles = Zeroizing::new(vec![format.control(), pin.len() as u8]);
+    nibbles.extend(pin.bytes().map(|digit| digit - b'0'));
+    for i in nibbles.len()..16 {
+        nibbles.push(match format {
+            PinBlockFormat::Iso0 => 0xF,
+            PinBlockFormat::Iso1 => random[i] & 0xF,
+            PinBlockFormat::Iso3 => 0xA + random[i] % 6,
+            PinBlockFormat::Iso4 => 0xA,
+        });
+    }
+    let mut field = pack(&nibbles);
+    if format == PinBlockFormat::Iso4 {
+        // the second half of a format 4 PIN field is random
+        field.extend(&random[..8]);
+    }
+    Ok(field)
+}
+
+fn pan_digits(pan: &str, min_length: usize) -> Result<Vec<u8>> {
+    if pan.len() < min_length || !pan.chars().all(|c| c.is_ascii_digit()) {
+        return Err(GatewayError::FieldError(format!(
+            "Invalid pan: must be at least {min_length} digits"
+        )));
+    }
+    Ok(pan.bytes().map(|digit| digit - b'0').collect())
+}
+
+/// `0000` then the rightmost 12 digits of the PAN excluding the check digit, so the PAN needs 13
+fn pan_field(pan: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let digits = pan_digits(pan, 13)?;
+    let account = &digits[digits.len() - 13..digits.len() - 1];
+    let nibbles: Vec<u8> = [0; 4].iter().chain(account).copied().collect();
+    Ok(pack(&nibbles))
+}
+
+/// How many digits the PAN has beyond 12, then the whole PAN padded with zeros to 16 bytes
+fn iso4_pan_field(pan: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let digits = pan_digits(pan, 12)?;
+    if digits.len() > 19 {
+        return Err(GatewayError::FieldError(
+            "Invalid pan: must be at most 19 digits".into(),
+        ));
+    }
+    let mut nibbles = vec![(digits.len() - 12) as u8];
+    nibbles.extend(digits);
+    nibbles.resize(32, 0);
+    Ok(pack(&nibbles))
+}
+
+fn extract_pin(format: PinBlockFormat, clear: &[u8]) -> Result<Secret> {
+    let nibbles: Zeroizing<Vec<u8>> = Zeroizing::new(
+        clear[..8]
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0xF])
+            .collect(),
+    );
+    if nibbles[0] != format.control() {
+        return Err(invalid(&format!("not format {}", format.code())));
+    }
+    let length = usize::from(nibbles[1]);
+    if !(4..=12).contains(&length) {
+        return Err(invalid("PIN length must be 4 to 12"));
+    }
+    let (pin, fill) = nibbles[2..].split_at(length);
+    let fill_is_valid = fill.iter().all(|nibble| match format {
+        PinBlockFormat::Iso0 => *nibble == 0xF,
+        PinBlockFormat::Iso1 => true,
+        PinBlockFormat::Iso3 => *nibble >= 0xA,
+        PinBlockFormat::Iso4 => *nibble == 0xA,
+    });
+    if !pin.iter().all(|digit| *digit <= 9) || !fill_is_valid {
+        return Err(invalid("wrong key or PAN"));
+    }
+    let pin = Zeroizing::new(
+        pin.iter()
+            .map(|digit| char::from(b'0' + digit))
+            .collect::<String>(),
+    );
+    Ok(Secret::new(&pin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_store::test_key_store;
+
+    const PAN: &str = "4111111111111111";
+
+    #[test]
+    fn test_clear_fields() {
+        let tests = [
+            (pin_field(PinBlockFormat::Iso0, "1234"), "041234FFFFFFFFFF"),
+            (pan_field(PAN), "0000111111111111"),
+            (pan_field("5100000000000008"), "0000000000000000"),
+            (iso4_pan_field(PAN), "44111111111111111000000000000000"),
+            (
+                iso4_pan_field("371449635398431"),
+                "33714496353984310000000000000000",
+            ),
+        ];
+        for (i, (actual, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(
+                expected,
+                hex::encode_upper(actual.unwrap().as_slice()),
+                "Case number {}",
+                i + 1
+            );
+        }
+        let iso3 = hex::encode_upper(pin_field(PinBlockFormat::Iso3, "12345").unwrap().as_slice());
+        assert!(iso3.starts_with("3512345"));
+        assert!(iso3[7..].chars().all(|c| ('A'..='F').contains(&c)));
+        let iso4 = pin_field(PinBlockFormat::Iso4, "1234").unwrap();
+        assert_eq!("441234AAAAAAAAAA", hex::encode_upper(&iso4[..8]));
+    }
+
+    #[test]
+    fn test_encrypted_pin_block() {
+        let key_store = test_key_store();
+        let (pin, pan) = (Secret::new("1234"), Secret::new(PAN));
+
+        let iso0 =
+            EncryptedPinBlock::new(&key_store, "tdes", PinBlockFormat::Iso0, &pin, &pan).unwrap();
+        assert_eq!("2A3D408A1977DDE9", iso0.to_hex());
+
+        let tests = [
+            (PinBlockFormat::Iso0, "tdes"),
+            (PinBlockFormat::Iso1, "tdes"),
+            (PinBlockFormat::Iso3, "tdes"),
+            (PinBlockFormat::Iso4, "aes"),
+        ];
+        for (i, (format, key_name)) in tests.into_iter().enumerate() {
+            let block = EncryptedPinBlock::new(&key_store, key_name, format, &pin, &pan).unwrap();
+            assert_eq!(
+                format.algorithm().block_size(),
+                block.block.len(),
+                "Case number {}",
+                i + 1
+            );
+            assert_eq!(
+                Ok(pin.clone()),
+                block.pin(&key_store, &pan),
+                "Case number {}",
+                i + 1
+            );
+        }
+
+        let other_pan = Secret::new("5555555555554444");
+        let tests = [
+            (
+                EncryptedPinBlock::new(
+                    &key_store,
+                    "tdes",
+                    PinBlockFormat::Iso0,
+                    &"12".into(),
+                    &pan,
+                ),
+                GatewayError::FieldError("Invalid pin: must be 4 to 12 digits".into()),
+            ),
+            (
+                EncryptedPinBlock::new(&key_store, "aes", PinBlockFormat::Iso0, &pin, &pan),
+                GatewayError::KeyError("Key aes is Aes but format 0 needs Tdes".into()),
+            ),
+            (
+                EncryptedPinBlock::new(
+                    &key_store,
+                    "tdes",
+                    PinBlockFormat::Iso3,
+                    &pin,
+                    &"4111".into(),
+                ),
+                GatewayError::FieldError("Invalid pan: must be at least 13 digits".into()),
+            ),
+            (
+                EncryptedPinBlock::new(
+                    &key_store,
+                    "tdes",
+                    PinBlockFormat::Iso0,
+                    &pin,
+                    &"411111111111".into(),
+                ),
+                GatewayError::FieldError("Invalid pan: must be at least 13 digits".into()),
+            ),
+            (
+                EncryptedPinBlock::new(
+                    &key_store,
+                    "aes",
+                    PinBlockFormat::Iso4,
+                    &pin,
+                    &"41111111111".into(),
+                ),
+                GatewayError::FieldError("Invalid pan: must be at least 12 digits".into()),
+            ),
+            (
+                iso0.pin(&key_store, &other_pan).map(|_| iso0.clone()),
+                GatewayError::FieldError("Invalid pinblock: wrong key or PAN".into()),
+            ),
+        ];
+        for (i, (actual, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(Err(expected), actual, "Case number {}", i + 1);
+        }
+    }
+}
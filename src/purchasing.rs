@@ -0,0 +1,362 @@
+use crate::{messaging_specification::MessagingSpecification, money::Money, GatewayError, Result};
+
+/// The most the addendum can carry, its length is sent as 3 digits
+pub const MAX_ADDENDUM_LENGTH: usize = 999;
+
+/// Where the line items start in the addendum, one field per item
+const LINE_ITEM_POSITION: usize = 10;
+
+/// The longest order reference the acquirers carry, in settlement reports and the addendum alike
+pub const MAX_ORDER_REFERENCE_LENGTH: usize = 25;
+
+fn invalid(field: &str, reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid {field}: {reason}"))
+}
+
+/// Trims a merchant supplied reference, which must be printable ASCII so every acquirer can carry it
+pub(crate) fn reference(field: &str, value: &str, max_length: usize) -> Result<String> {
+    let value = value.trim();
+    if value.is_empty()
+        || value.len() > max_length
+        || !value.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+    {
+        return Err(invalid(
+            field,
+            &format!("must be 1 to {max_length} printable characters"),
+        ));
+    }
+    Ok(value.into())
+}
+
+fn not_negative(field: &str, amount: &Money) -> Result<()> {
+    if amount.is_negative() {
+        return Err(invalid(field, "must not be negative"));
+    }
+    Ok(())
+}
+
+/// One line of a Level 3 order
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineItem {
+    pub description: String,
+    pub quantity: u32,
+    pub unit_price: Money,
+    /// The UNSPSC or NIGP code of what was bought
+    pub commodity_code: String,
+    /// The tax on the whole line, not per unit
+    pub tax: Money,
+}
+
+impl LineItem {
+    pub fn new(
+        description: &str,
+        quantity: u32,
+        unit_price: Money,
+        commodity_code: &str,
+        tax: Money,
+    ) -> Result<Self> {
+        let description = reference("description", description, 26)?;
+        let commodity_code = commodity_code.trim();
+        if commodity_code.is_empty()
+            || commodity_code.len() > 12
+            || !commodity_code.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return Err(invalid(
+                "commoditycode",
+                "must be 1 to 12 letters or digits",
+            ));
+        }
+        if quantity == 0 {
+            return Err(invalid("quantity", "must be at least 1"));
+        }
+        not_negative("unitprice", &unit_price)?;
+        not_negative("tax", &tax)?;
+        if tax.currency() != unit_price.currency() {
+            return Err(invalid(
+                "tax",
+                &format!("must be in {} like the unit price", unit_price.currency()),
+            ));
+        }
+        Ok(Self {
+            description,
+            quantity,
+            unit_price,
+            commodity_code: commodity_code.into(),
+            tax,
+        })
+    }
+
+    /// What the line costs including its tax
+    pub fn total(&self) -> Result<Money> {
+        self.unit_price
+            .checked_mul(self.quantity.into())?
+            .checked_add(&self.tax)
+    }
+}
+
+/// Level 2 and 3 data for purchasing cards, which gets B2B merchants lower interchange rates
+#[derive(Debug, Clone, PartialEq)]
+pub struct PurchasingData {
+    /// The purchase order number the cardholder's company raised
+    pub order_reference: String,
+    /// The cardholder's own reference, such as a cost centre
+    pub customer_reference: Option<String>,
+    pub tax_amount: Money,
+    /// Level 3 detail, empty for Level 2
+    pub line_items: Vec<LineItem>,
+}
+
+impl PurchasingData {
+    /// Validates the data on its own, the totals are checked against the amount by
+    /// [`crate::transaction::Transaction::with_purchasing_data`]
+    pub fn new(
+        order_reference: &str,
+        customer_reference: Option<&str>,
+        tax_amount: Money,
+        line_items: Vec<LineItem>,
+    ) -> Result<Self> {
+        not_negative("taxamount", &tax_amount)?;
+        if !line_items.is_empty() {
+            let line_tax = line_items
+                .iter()
+                .try_fold(Money::zero(tax_amount.currency()), |sum, item| {
+                    sum.checked_add(&item.tax)
+                })?;
+            if line_tax != tax_amount {
+                return Err(invalid(
+                    "taxamount",
+                    &format!("{tax_amount} does not match the line items' tax of {line_tax}"),
+                ));
+            }
+        }
+        let data = Self {
+            order_reference: reference(
+                "orderreference",
+                order_reference,
+                MAX_ORDER_REFERENCE_LENGTH,
+            )?,
+            customer_reference: customer_reference
+                .map(|value| reference("customerreference", value, 25))
+                .transpose()?,
+            tax_amount,
+            line_items,
+        };
+        // this also keeps the line item positions to 2 digits, as no more than 34 items could fit
+        let length = data.to_iso8583()?.len();
+        if length > MAX_ADDENDUM_LENGTH {
+            return Err(invalid(
+                "lineitems",
+                &format!("the addendum would be {length} characters but at most {MAX_ADDENDUM_LENGTH} fit"),
+            ));
+        }
+        Ok(data)
+    }
+
+    /// The addendum ISO 8583 banks take the data in, each line item nests its details in one field
+    pub fn to_iso8583(&self) -> Result<String> {
+        let field =
+            |position, value: &str| MessagingSpecification::Iso8853.encode_field(position, value);
+        let mut addendum = field(1, &self.order_reference)?;
+        if let Some(customer_reference) = &self.customer_reference {
+            addendum.push_str(&field(2, customer_reference)?);
+        }
+        addendum.push_str(&field(3, &self.tax_amount.minor_units().to_string())?);
+        for (i, item) in self.line_items.iter().enumerate() {
+            let details = [
+                field(1, &item.commodity_code)?,
+                field(2, &item.description)?,
+                field(3, &item.quantity.to_string())?,
+                field(4, &item.unit_price.minor_units().to_string())?,
+                field(5, &item.tax.minor_units().to_string())?,
+            ]
+            .concat();
+            addendum.push_str(&field(LINE_ITEM_POSITION + i, &details)?);
+        }
+        Ok(addendum)
+    }
+
+    pub fn level(&self) -> u8 {
+        match self.line_items.is_empty() {
+            true => 2,
+            false => 3,
+        }
+    }
+
+    /// Checks the tax fits within `amount` and the line items add up to it exactly
+    pub fn reconcile(&self, amount: &Money) -> Result<()> {
+        if amount.checked_sub(&self.tax_amount)?.is_negative() {
+            return Err(invalid(
+                "taxamount",
+                &format!("{} is more than the amount {amount}", self.tax_amount),
+            ));
+        }
+        if self.line_items.is_empty() {
+            return Ok(());
+        }
+        let total = self
+            .line_items
+            .iter()
+            .try_fold(Money::zero(amount.currency()), |sum, item| {
+                sum.checked_add(&item.total()?)
+            })?;
+        if total != *amount {
+            return Err(invalid(
+                "lineitems",
+                &format!("total {total} does not match the amount {amount}"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub fn test_purchasing_data() -> PurchasingData {
+    use crate::currency::Currency;
+
+    let pounds = |minor_units| Money::new(minor_units, Currency::GBP);
+    PurchasingData::new(
+        "PO-1234",
+        Some("CC-42"),
+        pounds(2058),
+        vec![
+            LineItem::new("A4 paper", 5, pounds(1000), "14111507", pounds(1000)).unwrap(),
+            LineItem::new("Toner", 1, pounds(5287), "44103103", pounds(1058)).unwrap(),
+        ],
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::Currency;
+
+    fn pounds(minor_units: i64) -> Money {
+        Money::new(minor_units, Currency::GBP)
+    }
+
+    #[test]
+    fn test_line_item() {
+        let tests = [
+            (
+                LineItem::new("A4 paper", 5, pounds(1000), "14111507", pounds(1000)),
+                Ok(pounds(6000)),
+            ),
+            (
+                LineItem::new("", 5, pounds(1000), "14111507", pounds(0)),
+                Err("Invalid description: must be 1 to 26 printable characters"),
+            ),
+            (
+                LineItem::new("A4 paper", 0, pounds(1000), "14111507", pounds(0)),
+                Err("Invalid quantity: must be at least 1"),
+            ),
+            (
+                LineItem::new("A4 paper", 5, pounds(1000), "1411-1507", pounds(0)),
+                Err("Invalid commoditycode: must be 1 to 12 letters or digits"),
+            ),
+            (
+                LineItem::new("A4 paper", 5, pounds(-1000), "14111507", pounds(0)),
+                Err("Invalid unitprice: must not be negative"),
+            ),
+            (
+                LineItem::new(
+                    "A4 paper",
+                    5,
+                    pounds(1000),
+                    "14111507",
+                    Money::new(0, Currency::EUR),
+                ),
+                Err("Invalid tax: must be in GBP like the unit price"),
+            ),
+        ];
+        for (i, (item, expected)) in tests.into_iter().enumerate() {
+            let actual = item.and_then(|item| item.total());
+            let expected = expected.map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+    }
+
+    /// Items with the longest description, each taking 64 characters of the addendum
+    fn line_items(count: usize) -> Vec<LineItem> {
+        let item = LineItem::new(&"x".repeat(26), 1, pounds(1000), "14111507", pounds(0)).unwrap();
+        vec![item; count]
+    }
+
+    #[test]
+    fn test_addendum_capacity() {
+        let data = PurchasingData::new("PO-1234", None, pounds(0), line_items(15)).unwrap();
+        let addendum = data.to_iso8583().unwrap();
+        assert_eq!(976, addendum.len());
+        assert!(
+            addendum.ends_with("24600108141115070226xxxxxxxxxxxxxxxxxxxxxxxxxx030110404100005010")
+        );
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let level2 = PurchasingData::new("PO-1234", None, pounds(2058), vec![]).unwrap();
+        assert_eq!(2, level2.level());
+        assert_eq!(3, test_purchasing_data().level());
+        let tests = [
+            (test_purchasing_data(), pounds(12345), Ok(())),
+            (level2.clone(), pounds(12345), Ok(())),
+            (
+                level2.clone(),
+                pounds(2000),
+                Err(GatewayError::FieldError(
+                    "Invalid taxamount: 20.58 GBP is more than the amount 20.00 GBP".into(),
+                )),
+            ),
+            (
+                level2,
+                Money::new(12345, Currency::USD),
+                Err(GatewayError::ValidationError(
+                    "Cannot subtract USD and GBP amounts".into(),
+                )),
+            ),
+            (
+                test_purchasing_data(),
+                pounds(12344),
+                Err(GatewayError::FieldError(
+                    "Invalid lineitems: total 123.45 GBP does not match the amount 123.44 GBP"
+                        .into(),
+                )),
+            ),
+        ];
+        for (i, (data, amount, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(expected, data.reconcile(&amount), "Case number {}", i + 1);
+        }
+
+        let tests = [
+            (
+                PurchasingData::new(
+                    "PO-1234",
+                    None,
+                    pounds(100),
+                    test_purchasing_data().line_items,
+                ),
+                "Invalid taxamount: 1.00 GBP does not match the line items' tax of 20.58 GBP",
+            ),
+            (
+                PurchasingData::new("", None, pounds(0), vec![]),
+                "Invalid orderreference: must be 1 to 25 printable characters",
+            ),
+            (
+                PurchasingData::new("PO-1234", Some(&"x".repeat(26)), pounds(0), vec![]),
+                "Invalid customerreference: must be 1 to 25 printable characters",
+            ),
+            (
+                PurchasingData::new("PO-1234", None, pounds(0), line_items(16)),
+                "Invalid lineitems: the addendum would be 1040 characters but at most 999 fit",
+            ),
+        ];
+        for (i, (actual, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(
+                Err(GatewayError::FieldError(expected.into())),
+                actual,
+                "Case number {}",
+                i + 1
+            );
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use std::{collections::HashMap, thread, time::Duration};
+
+use crate::{
+    authorisation::Outcome,
+    messaging_specification::ResponseField,
+    operation::{Operation, RequestType},
+    transport::Transport,
+    GatewayError, Result,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// How long to wait for each response
+    pub timeout: Duration,
+    /// How many times a reversal is sent before giving up on it
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait after the given (1-based) failed attempt, doubling each time up to `max_backoff`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReversalOutcome {
+    /// The bank approved the reversal
+    Acknowledged { response_code: Option<String> },
+    /// The reversal could not be built, an attempt failed in a way retrying cannot fix, or every
+    /// attempt failed, so the operation needs to be reconciled by hand
+    Unacknowledged { last_error: GatewayError },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReversalRecord {
+    pub original_trace_number: String,
+    pub attempts: u32,
+    pub outcome: ReversalOutcome,
+}
+
+/// Sends operations to their bank, reversing any authorisation whose outcome is unknown
+pub struct Dispatcher<T: Transport> {
+    transport: T,
+    policy: RetryPolicy,
+    reversals: Vec<ReversalRecord>,
+}
+
+impl<T: Transport> Dispatcher<T> {
+    pub fn new(transport: T, policy: RetryPolicy) -> Self {
+        Self {
+            transport,
+            policy,
+            reversals: Vec::new(),
+        }
+    }
+
+    /// Every reversal sent by this dispatcher, in the order they were sent
+    pub fn reversals(&self) -> &[ReversalRecord] {
+        &self.reversals
+    }
+
+    /// Sends the operation and decodes the bank's response.
+    ///
+    /// If an authorisation times out, loses its connection after being sent or its response
+    /// cannot be read or authenticated, it is reversed before the original error is returned.
+    pub fn send(&mut self, op: &Operation) -> Result<HashMap<String, String>> {
+        let is_auth = op.request_type == Some(RequestType::Auth);
+        if is_auth && op.trace_number.is_none() {
+            return Err(GatewayError::ValidationError(
+                "An auth needs a trace number so it can be reversed".into(),
+            ));
+        }
+        match self.exchange(op) {
+            Err(err) if is_auth && outcome_unknown(&err) => {
+                // how the reversal went is in its record, the caller needs to know why it was sent
+                self.reverse(op);
+                Err(err)
+            }
+            result => result,
+        }
+    }
+
+    /// Sends the reversal of `op`, retrying with backoff until the bank approves it or the
+    /// policy's attempts run out, and records the outcome
+    pub fn reverse(&mut self, op: &Operation) -> ReversalRecord {
+        let (attempts, outcome) = match op.reversal() {
+            Ok(reversal) => self.send_reversal(&reversal),
+            Err(err) => (0, ReversalOutcome::Unacknowledged { last_error: err }),
+        };
+        let record = ReversalRecord {
+            original_trace_number: op.trace_number.clone().unwrap_or_default(),
+            attempts,
+            outcome,
+        };
+        self.reversals.push(record.clone());
+        record
+    }
+
+    fn send_reversal(&mut self, reversal: &Operation) -> (u32, ReversalOutcome) {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let err = match self.exchange(reversal) {
+                Ok(response) => {
+                    let response_code = response.get(ResponseField::ResponseCode.name()).cloned();
+                    let outcome = response_code.as_deref().map(|code| {
+                        // encode() has already checked the reversal has a bank
+                        reversal.bank.unwrap().outcome_for(code)
+                    });
+                    if outcome == Some(Outcome::Approved) {
+                        return (attempts, ReversalOutcome::Acknowledged { response_code });
+                    }
+                    GatewayError::DeclinedError(format!(
+                        "Reversal was answered with response code {}",
+                        response_code.as_deref().unwrap_or("none")
+                    ))
+                }
+                Err(err) => err,
+            };
+            if attempts >= self.policy.max_attempts || !worth_retrying(&err) {
+                return (attempts, ReversalOutcome::Unacknowledged { last_error: err });
+            }
+            thread::sleep(self.policy.backoff(attempts));
+        }
+    }
+
+    fn exchange(&mut self, op: &Operation) -> Result<HashMap<String, String>> {
+        let request = op.encode()?;
+        let response = self.transport.send(&request, self.policy.timeout)?;
+        // encode() has already checked the operation has a bank
+        op.bank.unwrap().decode_response_string(&response)
+    }
+}
+
+/// Errors after which the bank may have processed the request without us knowing its answer
+fn outcome_unknown(err: &GatewayError) -> bool {
+    matches!(
+        err,
+        GatewayError::TimeoutError(_)
+            | GatewayError::ConnectionError(_)
+            | GatewayError::DecodingError(_)
+            | GatewayError::MacError(_)
+    )
+}
+
+/// Errors a later attempt could succeed after, anything wrong with the request itself is not
+fn worth_retrying(err: &GatewayError) -> bool {
+    outcome_unknown(err)
+        || matches!(
+            err,
+            GatewayError::TransportError(_) | GatewayError::DeclinedError(_)
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use crate::operation::example_operation;
+
+    use super::*;
+
+    /// Answers each request with the next scripted result
+    struct ScriptedTransport {
+        responses: VecDeque<Result<String>>,
+        requests: Vec<String>,
+    }
+
+    impl Transport for ScriptedTransport {
+        fn send(&mut self, request: &str, _timeout: Duration) -> Result<String> {
+            self.requests.push(request.into());
+            self.responses.pop_front().expect("no scripted response left")
+        }
+    }
+
+    fn dispatcher(responses: Vec<Result<String>>) -> Dispatcher<ScriptedTransport> {
+        let transport = ScriptedTransport {
+            responses: responses.into(),
+            requests: Vec::new(),
+        };
+        let policy = RetryPolicy {
+            timeout: Duration::from_millis(10),
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+        };
+        Dispatcher::new(transport, policy)
+    }
+
+    fn timeout() -> GatewayError {
+        GatewayError::TimeoutError("no response".into())
+    }
+
+    fn mac_mismatch() -> GatewayError {
+        GatewayError::MacError("Message MAC does not match its contents".into())
+    }
+
+    /// The bank closing the connection after the request was written
+    fn connection_lost() -> GatewayError {
+        GatewayError::ConnectionError("127.0.0.1:8583 closed the connection".into())
+    }
+
+    #[test]
+    fn test_send() {
+        let tests = [
+            (
+                vec![Ok("0103abc0202000306TEST01".into())],
+                Ok(crate::map! {
+                    "transactionidentifier".to_string() => "abc".to_string(),
+                    "responsecode".to_string() => "00".to_string(),
+                    "authcode".to_string() => "TEST01".to_string(),
+                }),
+                vec![],
+            ),
+            (
+                vec![Err(timeout()), Ok("020200".into())],
+                Err(timeout()),
+                vec![ReversalRecord {
+                    original_trace_number: "000123".into(),
+                    attempts: 1,
+                    outcome: ReversalOutcome::Acknowledged {
+                        response_code: Some("00".into()),
+                    },
+                }],
+            ),
+            (
+                vec![Ok("ERR".into()), Err(timeout()), Ok("ERR".into()), Ok("020200".into())],
+                Err(GatewayError::DecodingError("truncated field header 'ERR'".into())),
+                vec![ReversalRecord {
+                    original_trace_number: "000123".into(),
+                    attempts: 3,
+                    outcome: ReversalOutcome::Acknowledged {
+                        response_code: Some("00".into()),
+                    },
+                }],
+            ),
+            (
+                vec![Err(connection_lost()), Ok("020200".into())],
+                Err(connection_lost()),
+                vec![ReversalRecord {
+                    original_trace_number: "000123".into(),
+                    attempts: 1,
+                    outcome: ReversalOutcome::Acknowledged {
+                        response_code: Some("00".into()),
+                    },
+                }],
+            ),
+            (
+                vec![Err(mac_mismatch()), Ok("020200".into())],
+                Err(mac_mismatch()),
+                vec![ReversalRecord {
+                    original_trace_number: "000123".into(),
+                    attempts: 1,
+                    outcome: ReversalOutcome::Acknowledged {
+                        response_code: Some("00".into()),
+                    },
+                }],
+            ),
+            (
+                vec![Err(timeout()), Ok("020205".into()), Ok("020200".into())],
+                Err(timeout()),
+                vec![ReversalRecord {
+                    original_trace_number: "000123".into(),
+                    attempts: 2,
+                    outcome: ReversalOutcome::Acknowledged {
+                        response_code: Some("00".into()),
+                    },
+                }],
+            ),
+            (
+                vec![
+                    Err(timeout()),
+                    Ok("020205".into()),
+                    Ok("020205".into()),
+                    Ok("020205".into()),
+                ],
+                Err(timeout()),
+                vec![ReversalRecord {
+                    original_trace_number: "000123".into(),
+                    attempts: 3,
+                    outcome: ReversalOutcome::Unacknowledged {
+                        last_error: GatewayError::DeclinedError(
+                            "Reversal was answered with response code 05".into(),
+                        ),
+                    },
+                }],
+            ),
+            (
+                vec![Err(GatewayError::TransportError("connection refused".into()))],
+                Err(GatewayError::TransportError("connection refused".into())),
+                vec![],
+            ),
+            (
+                vec![Err(timeout()), Err(timeout()), Err(timeout()), Err(timeout())],
+                Err(timeout()),
+                vec![ReversalRecord {
+                    original_trace_number: "000123".into(),
+                    attempts: 3,
+                    outcome: ReversalOutcome::Unacknowledged {
+                        last_error: timeout(),
+                    },
+                }],
+            ),
+        ];
+        for (i, (responses, expected, expected_reversals)) in tests.into_iter().enumerate() {
+            let mut dispatcher = dispatcher(responses);
+            let op = Operation {
+                trace_number: Some("000123".into()),
+                ..example_operation()
+            };
+            assert_eq!(expected, dispatcher.send(&op), "Case number {}", i + 1);
+            assert_eq!(expected_reversals, dispatcher.reversals(), "Case number {}", i + 1);
+            for reversal in dispatcher.transport.requests.iter().skip(1) {
+                assert!(reversal.contains("0204RVSL"), "Case number {}", i + 1);
+                assert!(reversal.ends_with("0706000123"), "Case number {}", i + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reverse_gives_up_on_unfixable_errors() {
+        let tests = [
+            (
+                Operation {
+                    bank: Some(crate::bank::Bank::Hsbc),
+                    trace_number: Some("000123".into()),
+                    ..example_operation()
+                },
+                ReversalRecord {
+                    original_trace_number: "000123".into(),
+                    attempts: 1,
+                    outcome: ReversalOutcome::Unacknowledged {
+                        last_error: GatewayError::CapabilityError("request type Reversal".into()),
+                    },
+                },
+            ),
+            (
+                example_operation(),
+                ReversalRecord {
+                    original_trace_number: "".into(),
+                    attempts: 0,
+                    outcome: ReversalOutcome::Unacknowledged {
+                        last_error: GatewayError::ValidationError(
+                            "Cannot reverse an operation without a trace number".into(),
+                        ),
+                    },
+                },
+            ),
+        ];
+        for (i, (op, expected)) in tests.into_iter().enumerate() {
+            // nothing is scripted, so sending anything would panic
+            let mut dispatcher = dispatcher(vec![]);
+            assert_eq!(expected, dispatcher.reverse(&op), "Case number {}", i + 1);
+            assert_eq!([expected], dispatcher.reversals(), "Case number {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_send_requires_trace_number() {
+        let mut dispatcher = dispatcher(vec![]);
+        assert_eq!(
+            Err(GatewayError::ValidationError(
+                "An auth needs a trace number so it can be reversed".into()
+            )),
+            dispatcher.send(&example_operation())
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let tests = [(1, 1), (2, 2), (3, 4), (4, 5), (40, 5)];
+        for (attempt, expected) in tests.into_iter() {
+            assert_eq!(Duration::from_secs(expected), policy.backoff(attempt));
+        }
+    }
+}
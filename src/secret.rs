@@ -0,0 +1,66 @@
+use std::fmt::{Debug, Display};
+
+use zeroize::Zeroize;
+
+/// Sensitive data such as a PAN or security code.
+///
+/// It is redacted when formatted and wiped from memory when dropped, the value can only be
+/// read with [`Secret::reveal`].
+#[derive(Clone, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: &str) -> Self {
+        Self(value.into())
+    }
+
+    /// The real value, only call this where it has to leave the gateway, e.g. when encoding
+    pub fn reveal(&self) -> &str {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted() {
+        let secret = Secret::new("4111111111111111");
+        assert_eq!("4111111111111111", secret.reveal());
+        assert_eq!("[REDACTED]", secret.to_string());
+        assert_eq!("Secret([REDACTED])", format!("{secret:?}"));
+    }
+}
@@ -0,0 +1,416 @@
+use std::{collections::HashMap, fs, str::FromStr, time::Duration};
+
+use crate::{
+    bank::Bank,
+    messaging_specification::{MessagingSpecification, ResponseField},
+    GatewayError, Result,
+};
+
+/// Positions of the request fields the simulator looks at when picking a behaviour
+const TRANSACTION_IDENTIFIER_FIELD: &str = "1";
+const REQUEST_TYPE_FIELD: &str = "2";
+const PAN_FIELD: &str = "3.1";
+const AMOUNT_FIELD: &str = "4.1";
+const AVS_POSTCODE_FIELD: &str = "13.1";
+
+/// Fields holding card details or sensitive authentication data (the CAVV or wallet cryptogram
+/// and the PIN block), which are masked before a request is logged
+const CARD_DETAIL_FIELDS: &[&str] = &["3", "8.3", "9.2", "10", "11"];
+
+/// Response code sent back when a request cannot be decoded
+const FORMAT_ERROR_CODE: &str = "30";
+const MALFORMED_REPLY: &str = "ERR";
+
+/// How the simulator answers a request
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Behaviour {
+    Approve,
+    Decline,
+    Referral,
+    /// Hold the request without ever answering it
+    Timeout,
+    /// Answer with something that is not a valid message
+    Malformed,
+}
+
+impl FromStr for Behaviour {
+    type Err = GatewayError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "approve" => Ok(Behaviour::Approve),
+            "decline" => Ok(Behaviour::Decline),
+            "referral" => Ok(Behaviour::Referral),
+            "timeout" => Ok(Behaviour::Timeout),
+            "malformed" => Ok(Behaviour::Malformed),
+            invalid => Err(GatewayError::FieldError(format!("Invalid behaviour: {invalid}"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatorConfig {
+    pub bank: Bank,
+    pub approval_code: String,
+    pub decline_code: String,
+    pub referral_code: String,
+    pub auth_code: String,
+    /// Sent back for approved requests that carry address verification data
+    pub avs_result: String,
+    /// How long a timed out request is held before the connection moves on
+    pub timeout: Duration,
+    /// Behaviours triggered by the trailing digits of the amount
+    pub amount_rules: HashMap<String, Behaviour>,
+    /// Behaviours triggered by a specific PAN, these win over the amount rules
+    pub pan_rules: HashMap<String, Behaviour>,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        Self {
+            bank: Bank::Ems,
+            approval_code: "00".into(),
+            decline_code: "05".into(),
+            referral_code: "01".into(),
+            auth_code: "TEST01".into(),
+            avs_result: "MM".into(),
+            timeout: Duration::from_secs(60),
+            amount_rules: HashMap::from([
+                ("01".into(), Behaviour::Referral),
+                ("05".into(), Behaviour::Decline),
+                ("08".into(), Behaviour::Timeout),
+                ("99".into(), Behaviour::Malformed),
+            ]),
+            pan_rules: HashMap::from([
+                ("4000000000000002".into(), Behaviour::Decline),
+                ("4000000000000010".into(), Behaviour::Referral),
+                ("4000000000000028".into(), Behaviour::Timeout),
+                ("4000000000000036".into(), Behaviour::Malformed),
+            ]),
+        }
+    }
+}
+
+impl SimulatorConfig {
+    /// Loads a config from `key = value` lines, any key not given keeps its default.
+    ///
+    /// Magic values are given as `amount.<suffix> = <behaviour>` or `pan.<pan> = <behaviour>`.
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| GatewayError::FieldError(format!("Cannot read {path}: {err}")))?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .ok_or(GatewayError::FieldError(format!("Invalid config line: {line}")))?;
+            match key.split_once('.') {
+                Some(("amount", suffix)) => {
+                    config.amount_rules.insert(suffix.into(), value.parse()?);
+                }
+                Some(("pan", pan)) => {
+                    config.pan_rules.insert(pan.into(), value.parse()?);
+                }
+                _ => match key {
+                    "bank" => config.bank = simulated_bank(value)?,
+                    "approvalcode" => config.approval_code = value.into(),
+                    "declinecode" => config.decline_code = value.into(),
+                    "referralcode" => config.referral_code = value.into(),
+                    "authcode" => config.auth_code = value.into(),
+                    "avsresult" => config.avs_result = value.into(),
+                    "timeoutms" => {
+                        config.timeout = Duration::from_millis(value.parse().map_err(|err| {
+                            GatewayError::FieldError(format!("Invalid timeoutms: {err}"))
+                        })?)
+                    }
+                    invalid => {
+                        return Err(GatewayError::FieldError(format!(
+                            "Invalid config key: {invalid}"
+                        )))
+                    }
+                },
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Only banks whose messages can be decoded and answered can be simulated
+fn simulated_bank(name: &str) -> Result<Bank> {
+    let bank: Bank = name.parse()?;
+    if bank.spec()? == MessagingSpecification::Apacs {
+        return Err(GatewayError::FieldError(format!(
+            "Invalid bank: {name} speaks APACS, which cannot be simulated yet"
+        )));
+    }
+    Ok(bank)
+}
+
+/// What the simulator does with a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Message(String),
+    NoReply(Duration),
+}
+
+pub struct Simulator {
+    config: SimulatorConfig,
+}
+
+impl Simulator {
+    pub fn new(config: SimulatorConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &SimulatorConfig {
+        &self.config
+    }
+
+    pub fn respond(&self, request: &str) -> Reply {
+        let spec = match self.config.bank.spec() {
+            Ok(spec) => spec,
+            Err(_) => return Reply::Message(MALFORMED_REPLY.into()),
+        };
+        let decoded = match spec.decode_request(request) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                let fields = HashMap::from([
+                    (ResponseField::ResponseCode, FORMAT_ERROR_CODE.to_string()),
+                    (ResponseField::Message, message_for(&err)),
+                ]);
+                return Reply::Message(spec.encode_response(&fields).unwrap_or_default());
+            }
+        };
+        let code = match self.behaviour_for(&decoded) {
+            Behaviour::Timeout => return Reply::NoReply(self.config.timeout),
+            Behaviour::Malformed => return Reply::Message(MALFORMED_REPLY.into()),
+            Behaviour::Approve => &self.config.approval_code,
+            Behaviour::Decline => &self.config.decline_code,
+            Behaviour::Referral => &self.config.referral_code,
+        };
+        let mut fields = HashMap::from([(ResponseField::ResponseCode, code.clone())]);
+        if let Some(id) = decoded.get(TRANSACTION_IDENTIFIER_FIELD) {
+            fields.insert(ResponseField::TransactionIdentifier, id.clone());
+        }
+        if *code == self.config.approval_code {
+            fields.insert(ResponseField::AuthCode, self.config.auth_code.clone());
+            if decoded.contains_key(AVS_POSTCODE_FIELD) {
+                fields.insert(ResponseField::AvsResult, self.config.avs_result.clone());
+            }
+        }
+        match spec.encode_response(&fields) {
+            Ok(response) => Reply::Message(response),
+            Err(_) => Reply::Message(MALFORMED_REPLY.into()),
+        }
+    }
+
+    /// The decoded request as it is safe to log, with the card details masked
+    pub fn masked(&self, request: &str) -> String {
+        let decoded = match self.config.bank.spec().and_then(|spec| spec.decode_request(request)) {
+            Ok(decoded) => decoded,
+            // a request that cannot be decoded may still hold card details, so none of it is shown
+            Err(_) => return format!("<{} undecodable characters>", request.len()),
+        };
+        let mut fields: Vec<(Vec<usize>, String)> = decoded
+            .into_iter()
+            .map(|(key, value)| {
+                let sensitive = CARD_DETAIL_FIELDS.iter().any(|field| {
+                    key == *field || key.starts_with(&format!("{field}."))
+                });
+                let value = match sensitive {
+                    true => "*".repeat(value.chars().count()),
+                    false => value,
+                };
+                let position = key.split('.').filter_map(|part| part.parse().ok()).collect();
+                (position, format!("{key}={value}"))
+            })
+            .collect();
+        fields.sort();
+        fields
+            .into_iter()
+            .map(|(_, field)| field)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn behaviour_for(&self, request: &HashMap<String, String>) -> Behaviour {
+        // reversals carry the details of the request they cancel, so must not trip its magic values
+        if request.get(REQUEST_TYPE_FIELD).is_some_and(|rt| rt == "RVSL") {
+            return Behaviour::Approve;
+        }
+        if let Some(behaviour) = request
+            .get(PAN_FIELD)
+            .and_then(|pan| self.config.pan_rules.get(pan))
+        {
+            return *behaviour;
+        }
+        if let Some(amount) = request.get(AMOUNT_FIELD) {
+            // the longest matching suffix wins so more specific rules can be layered on top
+            let mut matches: Vec<(&String, &Behaviour)> = self
+                .config
+                .amount_rules
+                .iter()
+                .filter(|(suffix, _)| amount.ends_with(suffix.as_str()))
+                .collect();
+            matches.sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+            if let Some((_, behaviour)) = matches.first() {
+                return **behaviour;
+            }
+        }
+        Behaviour::Approve
+    }
+}
+
+fn message_for(err: &GatewayError) -> String {
+    match err {
+        GatewayError::DecodingError(msg) => msg.chars().take(99).collect(),
+        _ => "unexpected error".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bank::Bank, clock::test_clock, currency::Currency, merchant::test_merchant, money::Money,
+        operation::Operation, payment::Payment, transaction::Transaction,
+    };
+
+    use super::*;
+
+    fn request(pan: &str, amount: u32) -> String {
+        Operation {
+            payment: Some(Payment::card_at(pan, "12/2024", "123", "Ben Jones", &test_clock()).unwrap()),
+            transaction: Some(Transaction::new(Money::new(amount.into(), Currency::GBP), Some("Ben Jones")).unwrap()),
+            bank: Some(Bank::Ems),
+            request_type: Some(crate::operation::RequestType::Auth),
+            merchant: Some(test_merchant()),
+            trace_number: None,
+            original_trace_number: None,
+            three_d_secure: None,
+            card_present: None,
+            dcc: None,
+            order_reference: None,
+            soft_descriptor: None,
+        }
+        .encode()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_respond() {
+        let simulator = Simulator::new(SimulatorConfig::default());
+        let tests = [
+            (request("4111111111111111", 12345), Reply::Message("0103abc0202000306TEST01".into())),
+            (request("4111111111111111", 12305), Reply::Message("0103abc020205".into())),
+            (request("4111111111111111", 12301), Reply::Message("0103abc020201".into())),
+            (request("4111111111111111", 12308), Reply::NoReply(Duration::from_secs(60))),
+            (request("4111111111111111", 12399), Reply::Message("ERR".into())),
+            (request("4000000000000002", 12345), Reply::Message("0103abc020205".into())),
+            (request("4000000000000010", 12345), Reply::Message("0103abc020201".into())),
+            (request("4000000000000028", 12345), Reply::NoReply(Duration::from_secs(60))),
+            (request("4000000000000036", 12345), Reply::Message("ERR".into())),
+            (
+                request("4111111111111111", 12308).replace("0204AUTH", "0204RVSL"),
+                Reply::Message("0103abc0202000306TEST01".into()),
+            ),
+            (
+                "0103ab".into(),
+                Reply::Message("0202300437field '1' shorter than its length (3)".into()),
+            ),
+        ];
+        for (i, (request, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(expected, simulator.respond(&request), "Case number {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_avs_result() {
+        use crate::{
+            customer::{test_address, Customer},
+            operation::example_operation,
+        };
+
+        let mut op = example_operation();
+        op.transaction = op.transaction.map(|transaction| {
+            transaction.with_customer(Customer::default().with_billing_address(test_address()))
+        });
+        let simulator = Simulator::new(SimulatorConfig::parse("avsresult = NM").unwrap());
+        assert_eq!(
+            Reply::Message("0103abc0202000306TEST010502NM".into()),
+            simulator.respond(&op.encode().unwrap())
+        );
+        op.transaction.as_mut().unwrap().amount = Money::new(12305, Currency::GBP);
+        assert_eq!(
+            Reply::Message("0103abc020205".into()),
+            simulator.respond(&op.encode().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_masked() {
+        let simulator = Simulator::new(SimulatorConfig::default());
+        let tests = [
+            (
+                request("4111111111111111", 12345),
+                "1=abc 2=AUTH 3.1=**************** 3.2=* 3.3=****** 3.4=*** 4.1=0000012345 4.2=GBP 4.3=Ben Jones 5.1=0000104912345678",
+            ),
+            (
+                "0103abc0342011641111111111111110201V0306122024040312".into(),
+                "<52 undecodable characters>",
+            ),
+            (
+                // 3-D Secure with a CAVV followed by an online PIN block
+                "0103abc0204AUTH0342011641111111111111110201V030612202404031230434011000000123450203GBP0309Ben Jones05200116000010491234567808920101Y0202050328AAABBEg0VhI0VniQEjRWAAAAAAA=0436f25084f0-5b16-4c0a-ae5d-b24808a95e4b05052.2.011250101002162A3D408A1977DDE9".into(),
+                "1=abc 2=AUTH 3.1=**************** 3.2=* 3.3=****** 3.4=*** 4.1=0000012345 4.2=GBP 4.3=Ben Jones 5.1=0000104912345678 8.1=Y 8.2=05 8.3=**************************** 8.4=f25084f0-5b16-4c0a-ae5d-b24808a95e4b 8.5=2.2.0 11.1=* 11.2=****************",
+            ),
+        ];
+        for (i, (request, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(expected, simulator.masked(&request), "Case number {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn test_config_parse() {
+        let config = SimulatorConfig::parse(
+            "# longer suffixes win\nbank = stfs\namount.105 = approve\npan.5100000000000000 = timeout\ntimeoutms = 10\n",
+        )
+        .unwrap();
+        assert_eq!(Bank::Stfs, config.bank);
+        assert_eq!(Duration::from_millis(10), config.timeout);
+        let simulator = Simulator::new(config);
+        let tests = [
+            (("4000000000000000", "00000012105"), Behaviour::Approve),
+            (("4000000000000000", "00000012205"), Behaviour::Decline),
+            (("5100000000000000", "00000012345"), Behaviour::Timeout),
+        ];
+        for ((pan, amount), expected) in tests.into_iter() {
+            let request = HashMap::from([
+                (PAN_FIELD.to_string(), pan.to_string()),
+                (AMOUNT_FIELD.to_string(), amount.to_string()),
+            ]);
+            assert_eq!(expected, simulator.behaviour_for(&request));
+        }
+        let tests = [
+            (
+                "amount.05 = explode",
+                GatewayError::FieldError("Invalid behaviour: explode".into()),
+            ),
+            (
+                "bank = hsbc",
+                GatewayError::FieldError(
+                    "Invalid bank: hsbc speaks APACS, which cannot be simulated yet".into(),
+                ),
+            ),
+        ];
+        for (i, (contents, expected)) in tests.into_iter().enumerate() {
+            assert_eq!(Err(expected), SimulatorConfig::parse(contents), "Case number {}", i + 1);
+        }
+    }
+}
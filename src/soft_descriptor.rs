@@ -0,0 +1,232 @@
+use crate::{
+    card::CardNetwork,
+    purchasing::{reference, MAX_ORDER_REFERENCE_LENGTH},
+    GatewayError, Result,
+};
+
+/// The longest city any network shows on a statement
+const MAX_CITY_LENGTH: usize = 13;
+/// The longest customer service number any network shows in place of the city
+const MAX_PHONE_LENGTH: usize = 13;
+
+fn invalid(field: &str, reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid {field}: {reason}"))
+}
+
+/// How long a descriptor name may be and which punctuation it may contain, beyond upper case
+/// letters, digits and spaces
+struct DescriptorRules {
+    max_name_length: usize,
+    punctuation: &'static str,
+}
+
+/// Bank transfers have no network, the 18 character Bacs reference is what the payer sees
+fn rules(network: Option<CardNetwork>) -> DescriptorRules {
+    match network {
+        Some(CardNetwork::Visa) => DescriptorRules {
+            max_name_length: 25,
+            punctuation: ".,*&'#/-",
+        },
+        Some(CardNetwork::Mastercard | CardNetwork::Maestro | CardNetwork::Discover) => {
+            DescriptorRules {
+                max_name_length: 22,
+                punctuation: ".,*&'/-",
+            }
+        }
+        Some(CardNetwork::Amex) => DescriptorRules {
+            max_name_length: 20,
+            punctuation: ".&'-",
+        },
+        None => DescriptorRules {
+            max_name_length: 18,
+            punctuation: ".&/-",
+        },
+    }
+}
+
+/// What the cardholder sees on their statement in place of the merchant's registered details
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoftDescriptor {
+    pub name: String,
+    pub city: Option<String>,
+    /// Shown in place of the city for card not present payments so the cardholder can get in touch
+    pub phone: Option<String>,
+}
+
+impl SoftDescriptor {
+    /// Upper cases the descriptor and checks it against `network`'s length and character rules
+    pub fn new(
+        network: Option<CardNetwork>,
+        name: &str,
+        city: Option<&str>,
+        phone: Option<&str>,
+    ) -> Result<Self> {
+        let rules = rules(network);
+        let shown_by = network.map_or("bank transfers".into(), |network| network.to_string());
+        let text = |field: &str, value: &str, max_length: usize| {
+            let value = value.trim().to_uppercase();
+            if value.is_empty() || value.chars().count() > max_length {
+                return Err(invalid(
+                    field,
+                    &format!("must be 1 to {max_length} characters for {shown_by}"),
+                ));
+            }
+            if let Some(c) = value.chars().find(|c| {
+                !(c.is_ascii_uppercase()
+                    || c.is_ascii_digit()
+                    || *c == ' '
+                    || rules.punctuation.contains(*c))
+            }) {
+                return Err(invalid(
+                    field,
+                    &format!("'{c}' cannot be shown by {shown_by}"),
+                ));
+            }
+            Ok(value)
+        };
+        let name = text("descriptorname", name, rules.max_name_length)?;
+        let city = city
+            .map(|city| text("descriptorcity", city, MAX_CITY_LENGTH))
+            .transpose()?;
+        let phone = phone.map(str::trim);
+        if let Some(phone) = phone {
+            if phone.is_empty()
+                || phone.len() > MAX_PHONE_LENGTH
+                || !phone
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c == '-' || c == '+')
+            {
+                return Err(invalid(
+                    "descriptorphone",
+                    &format!("must be 1 to {MAX_PHONE_LENGTH} digits, + or -"),
+                ));
+            }
+        }
+        Ok(Self {
+            name,
+            city,
+            phone: phone.map(String::from),
+        })
+    }
+}
+
+/// Checks the merchant's own reference for an order, sent so it can be matched in settlement reports
+pub fn validate_order_reference(order_reference: &str) -> Result<String> {
+    reference(
+        "orderreference",
+        order_reference,
+        MAX_ORDER_REFERENCE_LENGTH,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soft_descriptor() {
+        let tests = [
+            (
+                (
+                    Some(CardNetwork::Visa),
+                    "Acme Widgets*Order 42",
+                    Some("London"),
+                ),
+                Ok(("ACME WIDGETS*ORDER 42", Some("LONDON"))),
+            ),
+            (
+                (Some(CardNetwork::Visa), "Acme Widgets #42", None),
+                Ok(("ACME WIDGETS #42", None)),
+            ),
+            (
+                (Some(CardNetwork::Mastercard), "Acme Widgets #42", None),
+                Err("Invalid descriptorname: '#' cannot be shown by MASTERCARD"),
+            ),
+            (
+                (Some(CardNetwork::Mastercard), "Acme Widgets*Order 42", None),
+                Ok(("ACME WIDGETS*ORDER 42", None)),
+            ),
+            (
+                (
+                    Some(CardNetwork::Mastercard),
+                    "Acme Widgets*Order 42 UK",
+                    None,
+                ),
+                Err("Invalid descriptorname: must be 1 to 22 characters for MASTERCARD"),
+            ),
+            (
+                (Some(CardNetwork::Amex), "Acme Widgets*Order 42", None),
+                Err("Invalid descriptorname: must be 1 to 20 characters for AMEX"),
+            ),
+            (
+                (Some(CardNetwork::Amex), "Acme*Widgets", None),
+                Err("Invalid descriptorname: '*' cannot be shown by AMEX"),
+            ),
+            (
+                (None, "Acme Widgets Ltd", None),
+                Ok(("ACME WIDGETS LTD", None)),
+            ),
+            (
+                (None, "Acme Widgets Limited", None),
+                Err("Invalid descriptorname: must be 1 to 18 characters for bank transfers"),
+            ),
+            (
+                (Some(CardNetwork::Visa), "Café Ltd", None),
+                Err("Invalid descriptorname: 'É' cannot be shown by VISA"),
+            ),
+            (
+                (Some(CardNetwork::Visa), "Acme", Some("Newcastle upon Tyne")),
+                Err("Invalid descriptorcity: must be 1 to 13 characters for VISA"),
+            ),
+        ];
+        for (i, ((network, name, city), expected)) in tests.into_iter().enumerate() {
+            let actual = SoftDescriptor::new(network, name, city, None)
+                .map(|descriptor| (descriptor.name, descriptor.city));
+            let expected = expected
+                .map(|(name, city)| (name.to_string(), city.map(String::from)))
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+        assert_eq!(
+            Ok(Some("+442079460000".to_string())),
+            SoftDescriptor::new(None, "Acme", None, Some("+442079460000"))
+                .map(|descriptor| descriptor.phone)
+        );
+        assert_eq!(
+            Err(GatewayError::FieldError(
+                "Invalid descriptorphone: must be 1 to 13 digits, + or -".into()
+            )),
+            SoftDescriptor::new(None, "Acme", None, Some("call 0207 946 0000"))
+        );
+    }
+
+    #[test]
+    fn test_validate_order_reference() {
+        let tests = [
+            (" ORD-2024-0001 ", Ok("ORD-2024-0001")),
+            (
+                "",
+                Err("Invalid orderreference: must be 1 to 25 printable characters"),
+            ),
+            (
+                "ORD-2024-0001-0001-0001-01",
+                Err("Invalid orderreference: must be 1 to 25 printable characters"),
+            ),
+            (
+                "ORD\t1",
+                Err("Invalid orderreference: must be 1 to 25 printable characters"),
+            ),
+        ];
+        for (i, (order_reference, expected)) in tests.into_iter().enumerate() {
+            let expected = expected
+                .map(String::from)
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(
+                expected,
+                validate_order_reference(order_reference),
+                "Case number {}",
+                i + 1
+            );
+        }
+    }
+}
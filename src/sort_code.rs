@@ -0,0 +1,164 @@
+use std::{
+    fs,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use crate::{GatewayError, Result};
+
+/// Used when no directory has been installed, only knows the major clearing banks' ranges
+const DEFAULT_SORT_CODE_DIRECTORY: &str = "\
+start,end,bankname
+070000,079999,Nationwide Building Society
+090000,099999,Santander UK
+200000,299999,Barclays Bank
+300000,309999,Lloyds Bank
+400000,409999,HSBC UK Bank
+600000,609999,National Westminster Bank
+770000,779999,Lloyds Bank
+830000,839999,Royal Bank of Scotland
+";
+
+#[derive(Debug, Clone, PartialEq)]
+struct SortCodeRange {
+    start: u32,
+    end: u32,
+    bank_name: String,
+}
+
+/// Maps sort codes to the bank that owns them
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortCodeDirectory {
+    ranges: Vec<SortCodeRange>,
+}
+
+impl Default for SortCodeDirectory {
+    fn default() -> Self {
+        Self::parse(DEFAULT_SORT_CODE_DIRECTORY).expect("default sort code directory is invalid")
+    }
+}
+
+impl SortCodeDirectory {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| GatewayError::FieldError(format!("Cannot read {path}: {err}")))?;
+        Self::parse(&contents)
+    }
+
+    /// Parses CSV with a header line followed by `start,end,bankname` rows
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for (i, line) in contents.lines().enumerate().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |what: &str| {
+                GatewayError::FieldError(format!(
+                    "Invalid sort code directory line {}: {what}",
+                    i + 1
+                ))
+            };
+            let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [start, end, bank_name] = columns[..] else {
+                return Err(invalid("expected 3 columns"));
+            };
+            let sort_code = |column: &str| {
+                (column.len() == 6)
+                    .then(|| column.parse::<u32>().ok())
+                    .flatten()
+                    .ok_or_else(|| invalid("sort codes must be 6 digits"))
+            };
+            let (start, end) = (sort_code(start)?, sort_code(end)?);
+            if start > end {
+                return Err(invalid("start is after end"));
+            }
+            ranges.push(SortCodeRange {
+                start,
+                end,
+                bank_name: bank_name.into(),
+            });
+        }
+        Ok(Self { ranges })
+    }
+
+    /// Finds the bank owning the narrowest range containing the 6 digit sort code
+    pub fn lookup(&self, sort_code: &str) -> Result<String> {
+        let code: Option<u32> = sort_code.parse().ok();
+        self.ranges
+            .iter()
+            .filter(|range| code.is_some_and(|code| (range.start..=range.end).contains(&code)))
+            .min_by_key(|range| range.end - range.start)
+            .map(|range| range.bank_name.clone())
+            .ok_or(GatewayError::FieldError(
+                "Invalid sortcode: not in the sort code directory".into(),
+            ))
+    }
+}
+
+static DIRECTORY: LazyLock<RwLock<Arc<SortCodeDirectory>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(SortCodeDirectory::default())));
+
+/// Replaces the directory bank names are looked up in, e.g. with one loaded from the full EISCD extract
+pub fn install(directory: SortCodeDirectory) {
+    *DIRECTORY.write().expect("sort code directory poisoned") = Arc::new(directory);
+}
+
+pub fn lookup(sort_code: &str) -> Result<String> {
+    let directory = DIRECTORY
+        .read()
+        .expect("sort code directory poisoned")
+        .clone();
+    directory.lookup(sort_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let directory = SortCodeDirectory::parse(
+            "start,end,bankname\n\
+             300000,309999,Lloyds Bank\n\
+             309634,309634,Lloyds Bank Card Services\n",
+        )
+        .unwrap();
+        let tests = [
+            ("301234", Ok("Lloyds Bank")),
+            ("309634", Ok("Lloyds Bank Card Services")),
+            (
+                "401234",
+                Err("Invalid sortcode: not in the sort code directory"),
+            ),
+            (
+                "30-12-34",
+                Err("Invalid sortcode: not in the sort code directory"),
+            ),
+        ];
+        for (sort_code, expected) in tests.into_iter() {
+            let expected = expected
+                .map(String::from)
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, directory.lookup(sort_code), "{sort_code}");
+        }
+        let tests = [
+            (
+                "300000,309999",
+                "Invalid sort code directory line 2: expected 3 columns",
+            ),
+            (
+                "30000,309999,Lloyds Bank",
+                "Invalid sort code directory line 2: sort codes must be 6 digits",
+            ),
+            (
+                "309999,300000,Lloyds Bank",
+                "Invalid sort code directory line 2: start is after end",
+            ),
+        ];
+        for (line, expected) in tests.into_iter() {
+            assert_eq!(
+                Err(GatewayError::FieldError(expected.into())),
+                SortCodeDirectory::parse(&format!("header\n{line}"))
+            );
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::str::FromStr;
+
+use crate::{card::CardNetwork, operation_field::regex, GatewayError, Result};
+
+// Also the format of wallet cryptograms, which are the same kind of 20 byte authentication value
+regex!(pub(crate) CAVV_REGEX, "^[A-Za-z0-9+/]{27}=$");
+regex!(
+    DS_TRANSACTION_ID_REGEX,
+    "^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$"
+);
+regex!(PROTOCOL_VERSION_REGEX, "^2\\.[1-3]\\.[0-9]$");
+
+/// The `transStatus` the directory server gave the authentication
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AuthenticationStatus {
+    Authenticated,
+    Attempted,
+    NotAuthenticated,
+    Unavailable,
+    Rejected,
+}
+
+impl AuthenticationStatus {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthenticationStatus::Authenticated => "Y",
+            AuthenticationStatus::Attempted => "A",
+            AuthenticationStatus::NotAuthenticated => "N",
+            AuthenticationStatus::Unavailable => "U",
+            AuthenticationStatus::Rejected => "R",
+        }
+    }
+}
+
+impl FromStr for AuthenticationStatus {
+    type Err = GatewayError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "Y" => Ok(AuthenticationStatus::Authenticated),
+            "A" => Ok(AuthenticationStatus::Attempted),
+            "N" => Ok(AuthenticationStatus::NotAuthenticated),
+            "U" => Ok(AuthenticationStatus::Unavailable),
+            "R" => Ok(AuthenticationStatus::Rejected),
+            invalid => Err(GatewayError::FieldError(format!(
+                "Invalid authenticationstatus: {invalid}"
+            ))),
+        }
+    }
+}
+
+/// The result of a 3-D Secure v2 authentication, sent with the authorisation for SCA
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreeDSecure {
+    pub status: AuthenticationStatus,
+    /// Electronic commerce indicator, its values differ between networks
+    pub eci: String,
+    /// The CAVV (Visa), AAV (Mastercard) or AEVV (Amex), only present for authenticated and attempted
+    pub cavv: Option<String>,
+    pub ds_transaction_id: String,
+    pub protocol_version: String,
+}
+
+fn invalid(field: &str, reason: &str) -> GatewayError {
+    GatewayError::FieldError(format!("Invalid {field}: {reason}"))
+}
+
+impl ThreeDSecure {
+    /// Validates the authentication data against the rules of the card's network
+    pub fn new(
+        network: CardNetwork,
+        status: AuthenticationStatus,
+        eci: &str,
+        cavv: Option<&str>,
+        ds_transaction_id: &str,
+        protocol_version: &str,
+    ) -> Result<Self> {
+        let expected_eci = match (network, status) {
+            (_, AuthenticationStatus::Rejected) => {
+                return Err(invalid(
+                    "authenticationstatus",
+                    "rejected authentications cannot be authorised",
+                ))
+            }
+            (
+                CardNetwork::Mastercard | CardNetwork::Maestro,
+                AuthenticationStatus::Authenticated,
+            ) => "02",
+            (CardNetwork::Mastercard | CardNetwork::Maestro, AuthenticationStatus::Attempted) => {
+                "01"
+            }
+            (CardNetwork::Mastercard | CardNetwork::Maestro, _) => "00",
+            (_, AuthenticationStatus::Authenticated) => "05",
+            (_, AuthenticationStatus::Attempted) => "06",
+            (_, _) => "07",
+        };
+        if eci != expected_eci {
+            return Err(invalid(
+                "eci",
+                &format!(
+                    "must be {expected_eci} for {network} when status is {}",
+                    status.code()
+                ),
+            ));
+        }
+        let needs_cavv = matches!(
+            status,
+            AuthenticationStatus::Authenticated | AuthenticationStatus::Attempted
+        );
+        match cavv {
+            Some(cavv) if !CAVV_REGEX.is_match(cavv) => {
+                return Err(invalid("cavv", "must be 20 bytes of base64"))
+            }
+            Some(_) if !needs_cavv => {
+                return Err(invalid(
+                    "cavv",
+                    &format!("not expected when status is {}", status.code()),
+                ))
+            }
+            None if needs_cavv => {
+                return Err(invalid(
+                    "cavv",
+                    &format!("required when status is {}", status.code()),
+                ))
+            }
+            _ => (),
+        }
+        // some directory servers send upper case, it is always stored lower case
+        let ds_transaction_id = ds_transaction_id.to_ascii_lowercase();
+        if !DS_TRANSACTION_ID_REGEX.is_match(&ds_transaction_id) {
+            return Err(invalid("dstransactionid", "must be a UUID"));
+        }
+        if !PROTOCOL_VERSION_REGEX.is_match(protocol_version) {
+            return Err(invalid(
+                "threedsversion",
+                &format!("{protocol_version} is not a supported 3-D Secure v2 version"),
+            ));
+        }
+        Ok(Self {
+            status,
+            eci: eci.into(),
+            cavv: cavv.map(String::from),
+            ds_transaction_id,
+            protocol_version: protocol_version.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAVV: &str = "AAABBEg0VhI0VniQEjRWAAAAAAA=";
+    const DS_TRANSACTION_ID: &str = "f25084f0-5b16-4c0a-ae5d-b24808a95e4b";
+
+    #[test]
+    fn test_new() {
+        use AuthenticationStatus::*;
+        let tests = [
+            (
+                (CardNetwork::Visa, Authenticated, "05", Some(CAVV), "2.2.0"),
+                Ok(()),
+            ),
+            (
+                (CardNetwork::Visa, Attempted, "06", Some(CAVV), "2.1.0"),
+                Ok(()),
+            ),
+            (
+                (CardNetwork::Visa, NotAuthenticated, "07", None, "2.2.0"),
+                Ok(()),
+            ),
+            (
+                (
+                    CardNetwork::Mastercard,
+                    Authenticated,
+                    "02",
+                    Some(CAVV),
+                    "2.2.0",
+                ),
+                Ok(()),
+            ),
+            (
+                (CardNetwork::Maestro, Unavailable, "00", None, "2.3.1"),
+                Ok(()),
+            ),
+            (
+                (
+                    CardNetwork::Mastercard,
+                    Authenticated,
+                    "05",
+                    Some(CAVV),
+                    "2.2.0",
+                ),
+                Err("Invalid eci: must be 02 for MASTERCARD when status is Y"),
+            ),
+            (
+                (CardNetwork::Visa, Rejected, "07", None, "2.2.0"),
+                Err("Invalid authenticationstatus: rejected authentications cannot be authorised"),
+            ),
+            (
+                (CardNetwork::Visa, Authenticated, "05", None, "2.2.0"),
+                Err("Invalid cavv: required when status is Y"),
+            ),
+            (
+                (
+                    CardNetwork::Visa,
+                    NotAuthenticated,
+                    "07",
+                    Some(CAVV),
+                    "2.2.0",
+                ),
+                Err("Invalid cavv: not expected when status is N"),
+            ),
+            (
+                (
+                    CardNetwork::Visa,
+                    Authenticated,
+                    "05",
+                    Some("not base64"),
+                    "2.2.0",
+                ),
+                Err("Invalid cavv: must be 20 bytes of base64"),
+            ),
+            (
+                (CardNetwork::Visa, Authenticated, "05", Some(CAVV), "1.0.2"),
+                Err("Invalid threedsversion: 1.0.2 is not a supported 3-D Secure v2 version"),
+            ),
+        ];
+        for (i, ((network, status, eci, cavv, version), expected)) in tests.into_iter().enumerate()
+        {
+            let actual = ThreeDSecure::new(network, status, eci, cavv, DS_TRANSACTION_ID, version)
+                .map(|_| ());
+            let expected = expected.map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+        let tests = [
+            (DS_TRANSACTION_ID, Ok(DS_TRANSACTION_ID)),
+            ("F25084F0-5B16-4C0A-AE5D-B24808A95E4B", Ok(DS_TRANSACTION_ID)),
+            ("abc", Err("Invalid dstransactionid: must be a UUID")),
+            (
+                "f25084f0-5b16-4c0a-ae5d-b24808a95e4g",
+                Err("Invalid dstransactionid: must be a UUID"),
+            ),
+        ];
+        for (i, (ds_transaction_id, expected)) in tests.into_iter().enumerate() {
+            let actual = ThreeDSecure::new(
+                CardNetwork::Visa,
+                NotAuthenticated,
+                "07",
+                None,
+                ds_transaction_id,
+                "2.2.0",
+            )
+            .map(|three_d_secure| three_d_secure.ds_transaction_id);
+            let expected = expected
+                .map(String::from)
+                .map_err(|err| GatewayError::FieldError(err.into()));
+            assert_eq!(expected, actual, "Case number {}", i + 1);
+        }
+    }
+}
@@ -1,18 +1,43 @@
-use crate::{currency::Currency, Result};
+use crate::{customer::Customer, money::Money, purchasing::PurchasingData, GatewayError, Result};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Transaction {
-    pub amount: u32,
-    pub currency: Currency,
+    pub amount: Money,
     pub billingname: String,
+    /// Contact details and addresses, needed for address verification
+    pub customer: Option<Customer>,
+    /// Level 2 and 3 data, only sent to banks that accept it
+    pub purchasing_data: Option<PurchasingData>,
 }
 
 impl Transaction {
-    pub fn new(amount: u32, currency: Currency, billingname: Option<&str>) -> Result<Self> {
+    pub fn new(amount: Money, billingname: Option<&str>) -> Result<Self> {
+        if amount.is_negative() {
+            return Err(GatewayError::FieldError(
+                "Invalid amount: must not be negative".into(),
+            ));
+        }
         Ok(Transaction {
             amount,
-            currency,
             billingname: billingname.map_or("".into(), |s| s.into()),
+            customer: None,
+            purchasing_data: None,
+        })
+    }
+
+    pub fn with_customer(self, customer: Customer) -> Self {
+        Self {
+            customer: Some(customer),
+            ..self
+        }
+    }
+
+    /// Attaches purchasing card data, its tax and line items must reconcile with the amount
+    pub fn with_purchasing_data(self, purchasing_data: PurchasingData) -> Result<Self> {
+        purchasing_data.reconcile(&self.amount)?;
+        Ok(Self {
+            purchasing_data: Some(purchasing_data),
+            ..self
         })
     }
 }
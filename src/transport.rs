@@ -0,0 +1,97 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{GatewayError, Result};
+
+/// Carries encoded requests to a bank and brings back its raw response
+pub trait Transport {
+    /// Sends `request` and waits up to `timeout` for the response, failing with
+    /// `GatewayError::TimeoutError` if none arrives in time.
+    ///
+    /// Failures before the request is written are `GatewayError::TransportError`, failures after
+    /// it are `GatewayError::ConnectionError` as the bank may already be processing the request.
+    fn send(&mut self, request: &str, timeout: Duration) -> Result<String>;
+}
+
+/// Sends each request over a new TCP connection, messages are terminated by a newline
+pub struct TcpTransport {
+    address: String,
+}
+
+impl TcpTransport {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.into(),
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, request: &str, timeout: Duration) -> Result<String> {
+        let address = self
+            .address
+            .to_socket_addrs()
+            .map_err(|err| GatewayError::TransportError(format!("{}: {err}", self.address)))?
+            .next()
+            .ok_or(GatewayError::TransportError(format!(
+                "{} did not resolve",
+                self.address
+            )))?;
+        let mut stream = TcpStream::connect_timeout(&address, timeout)
+            .map_err(|err| GatewayError::TransportError(format!("{}: {err}", self.address)))?;
+        stream
+            .set_read_timeout(Some(timeout))
+            .map_err(|err| GatewayError::TransportError(err.to_string()))?;
+        writeln!(stream, "{request}").map_err(|err| GatewayError::TransportError(err.to_string()))?;
+        let mut response = String::new();
+        match BufReader::new(stream).read_line(&mut response) {
+            Ok(0) => Err(GatewayError::ConnectionError(format!(
+                "{} closed the connection",
+                self.address
+            ))),
+            Ok(_) => Ok(response.trim_end_matches(['\r', '\n']).into()),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Err(
+                GatewayError::TimeoutError(format!("no response from {} after {timeout:?}", self.address)),
+            ),
+            Err(err) => Err(GatewayError::ConnectionError(err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_send() {
+        // reads the request then closes the connection without answering
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            BufReader::new(stream).read_line(&mut request).unwrap();
+            request
+        });
+        let mut transport = TcpTransport::new(&address);
+        assert_eq!(
+            Err(GatewayError::ConnectionError(format!(
+                "{address} closed the connection"
+            ))),
+            transport.send("0103abc", Duration::from_secs(5))
+        );
+        assert_eq!("0103abc\n", server.join().unwrap());
+
+        // nothing listens once the listener is dropped, so the request is never written
+        let mut transport = TcpTransport::new(&address);
+        assert!(matches!(
+            transport.send("0103abc", Duration::from_secs(5)),
+            Err(GatewayError::TransportError(_))
+        ));
+    }
+}
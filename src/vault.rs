@@ -0,0 +1,346 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, RwLock},
+};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{operation_field::Mid, payment::luhn, GatewayError, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NONCE_LENGTH: usize = 12;
+
+/// Exchanges PANs for tokens so that PANs only need to exist while a request is being encoded
+pub trait Vault: Send + Sync {
+    /// Returns the token for `pan`, the same PAN always gets the same token for a merchant
+    fn tokenise(&self, pan: &str, mid: &Mid) -> Result<String>;
+
+    /// Returns the PAN behind one of the merchant's tokens
+    fn detokenise(&self, token: &str, mid: &Mid) -> Result<String>;
+}
+
+/// A vault persisted to a local file, PANs are stored encrypted with AES-256-GCM.
+///
+/// Tokens keep the PAN's length, first six and last four digits so they can still be used
+/// for BIN lookups and receipts, and always fail the Luhn check so they can never be
+/// mistaken for a PAN.
+pub struct FileVault {
+    path: PathBuf,
+    cipher: Aes256Gcm,
+    token_key: [u8; 32],
+    /// (mid, token) to nonce followed by the encrypted PAN
+    entries: Mutex<HashMap<(String, String), Vec<u8>>>,
+}
+
+impl FileVault {
+    /// Opens the vault at `path`, creating it on the first tokenisation if it does not exist
+    pub fn open(path: impl AsRef<Path>, key: &[u8; 32]) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries = HashMap::new();
+        if path.exists() {
+            let contents = fs::read_to_string(&path)
+                .map_err(|err| GatewayError::VaultError(format!("Cannot read vault: {err}")))?;
+            for line in contents.lines().filter(|line| !line.is_empty()) {
+                let corrupt = || GatewayError::VaultError("Vault file is corrupt".into());
+                let mut columns = line.split(',');
+                let (Some(mid), Some(token), Some(encrypted), None) = (
+                    columns.next(),
+                    columns.next(),
+                    columns.next(),
+                    columns.next(),
+                ) else {
+                    return Err(corrupt());
+                };
+                let encrypted = hex::decode(encrypted).map_err(|_| corrupt())?;
+                entries.insert((mid.into(), token.into()), encrypted);
+            }
+        }
+        Ok(Self {
+            path,
+            cipher: Aes256Gcm::new(&derive_key(key, b"encrypt").into()),
+            token_key: derive_key(key, b"token"),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    fn candidate_token(&self, pan: &str, mid: &str, attempt: u32) -> String {
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(&self.token_key).expect("any key length is valid");
+        mac.update(mid.as_bytes());
+        mac.update(b":");
+        mac.update(pan.as_bytes());
+        mac.update(&attempt.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        let middle_length = pan.len() - 10;
+        let mut middle: Vec<u8> = digest
+            .iter()
+            .cycle()
+            .take(middle_length)
+            .map(|b| b'0' + b % 10)
+            .collect();
+        let token = |middle: &[u8]| {
+            format!(
+                "{}{}{}",
+                &pan[..6],
+                String::from_utf8_lossy(middle),
+                &pan[pan.len() - 4..]
+            )
+        };
+        if luhn(&token(&middle)) {
+            // changing any single digit breaks the check
+            middle[0] = b'0' + (middle[0] - b'0' + 1) % 10;
+        }
+        token(&middle)
+    }
+
+    fn encrypt(&self, pan: &str, mid: &str, token: &str) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = format!("{mid}:{token}");
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: pan.as_bytes(),
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| GatewayError::VaultError("Cannot encrypt pan".into()))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    fn decrypt(&self, encrypted: &[u8], mid: &str, token: &str) -> Result<String> {
+        let cannot_decrypt = || GatewayError::VaultError("Cannot decrypt pan, wrong key?".into());
+        if encrypted.len() < NONCE_LENGTH {
+            return Err(cannot_decrypt());
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+        let aad = format!("{mid}:{token}");
+        let pan = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| cannot_decrypt())?;
+        String::from_utf8(pan).map_err(|_| cannot_decrypt())
+    }
+
+    fn persist(&self, entries: &HashMap<(String, String), Vec<u8>>) -> Result<()> {
+        let contents: String = entries
+            .iter()
+            .map(|((mid, token), encrypted)| format!("{mid},{token},{}\n", hex::encode(encrypted)))
+            .collect();
+        // write then rename so a crash cannot leave a half written vault behind
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, contents)
+            .and_then(|_| fs::rename(&temporary, &self.path))
+            .map_err(|err| GatewayError::VaultError(format!("Cannot write vault: {err}")))
+    }
+}
+
+impl Vault for FileVault {
+    fn tokenise(&self, pan: &str, mid: &Mid) -> Result<String> {
+        if pan.len() < 12 || !pan.chars().all(|c| c.is_ascii_digit()) {
+            return Err(GatewayError::FieldError(
+                "Invalid pan: cannot be tokenised".into(),
+            ));
+        }
+        let mid = mid.to_string();
+        let mut entries = self.entries.lock().expect("vault poisoned");
+        for attempt in 0.. {
+            let token = self.candidate_token(pan, &mid, attempt);
+            match entries.get(&(mid.clone(), token.clone())) {
+                Some(encrypted) if self.decrypt(encrypted, &mid, &token)? == pan => {
+                    return Ok(token)
+                }
+                // another PAN already has this token, so try the next candidate
+                Some(_) => continue,
+                None => {
+                    let encrypted = self.encrypt(pan, &mid, &token)?;
+                    entries.insert((mid, token.clone()), encrypted);
+                    self.persist(&entries)?;
+                    return Ok(token);
+                }
+            }
+        }
+        unreachable!()
+    }
+
+    fn detokenise(&self, token: &str, mid: &Mid) -> Result<String> {
+        let mid = mid.to_string();
+        let entries = self.entries.lock().expect("vault poisoned");
+        let encrypted = entries
+            .get(&(mid.clone(), token.into()))
+            .ok_or(GatewayError::VaultError("Unknown token".into()))?;
+        self.decrypt(encrypted, &mid, token)
+    }
+}
+
+fn derive_key(key: &[u8; 32], purpose: &[u8]) -> [u8; 32] {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("any key length is valid");
+    mac.update(purpose);
+    mac.finalize().into_bytes().into()
+}
+
+static VAULT: LazyLock<RwLock<Option<Arc<dyn Vault>>>> = LazyLock::new(|| RwLock::new(None));
+
+/// Sets the vault used to detokenise card tokens when encoding requests
+pub fn install(vault: Arc<dyn Vault>) {
+    *VAULT.write().expect("vault poisoned") = Some(vault);
+}
+
+thread_local! {
+    /// Used in place of the installed vault while `with_vault` runs on this thread
+    static SCOPED_VAULT: RefCell<Option<Arc<dyn Vault>>> = const { RefCell::new(None) };
+}
+
+/// Puts back whichever vault `with_vault` replaced, even if encoding panics
+struct ScopedVaultGuard(Option<Arc<dyn Vault>>);
+
+impl Drop for ScopedVaultGuard {
+    fn drop(&mut self) {
+        SCOPED_VAULT.with(|scoped| *scoped.borrow_mut() = self.0.take());
+    }
+}
+
+/// Runs `f` with tokens detokenised by `vault` rather than the installed one, on this thread only
+pub fn with_vault<T>(vault: Arc<dyn Vault>, f: impl FnOnce() -> T) -> T {
+    let _guard = ScopedVaultGuard(SCOPED_VAULT.with(|scoped| scoped.replace(Some(vault))));
+    f()
+}
+
+pub fn detokenise(token: &str, mid: &Mid) -> Result<String> {
+    let vault = SCOPED_VAULT
+        .with(|scoped| scoped.borrow().clone())
+        .or_else(|| VAULT.read().expect("vault poisoned").clone())
+        .ok_or(GatewayError::VaultError("No vault installed".into()))?;
+    vault.detokenise(token, mid)
+}
+
+/// A vault in the temp directory which is deleted when it is dropped
+#[cfg(test)]
+pub struct TestVault(FileVault);
+
+#[cfg(test)]
+impl std::ops::Deref for TestVault {
+    type Target = FileVault;
+
+    fn deref(&self) -> &FileVault {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Vault for TestVault {
+    fn tokenise(&self, pan: &str, mid: &Mid) -> Result<String> {
+        self.0.tokenise(pan, mid)
+    }
+
+    fn detokenise(&self, token: &str, mid: &Mid) -> Result<String> {
+        self.0.detokenise(token, mid)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestVault {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0.path);
+    }
+}
+
+#[cfg(test)]
+pub fn test_vault(name: &str) -> TestVault {
+    let path = std::env::temp_dir().join(format!("gateway-rs-{}-{name}.vault", std::process::id()));
+    let _ = fs::remove_file(&path);
+    TestVault(FileVault::open(path, &[7; 32]).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operation_field::{ctx, Validator};
+
+    use super::*;
+
+    #[test]
+    fn test_tokenise() {
+        let vault = test_vault("tokenise");
+        let mid = Mid::validate("000104912345678", ctx!()).unwrap();
+        let other_mid = Mid::validate("000104987654321", ctx!()).unwrap();
+        let pans = [
+            "4111111111111111",
+            "5555555555554444",
+            "371449635398431",
+            "6759649826438453",
+        ];
+        for pan in pans {
+            let token = vault.tokenise(pan, &mid).unwrap();
+            assert_eq!(pan.len(), token.len());
+            assert_eq!(pan[..6], token[..6]);
+            assert_eq!(pan[pan.len() - 4..], token[token.len() - 4..]);
+            assert_ne!(pan, token);
+            assert!(!luhn(&token), "{token} passes the Luhn check");
+            assert_eq!(token, vault.tokenise(pan, &mid).unwrap());
+            assert_ne!(token, vault.tokenise(pan, &other_mid).unwrap());
+            assert_eq!(Ok(pan.to_string()), vault.detokenise(&token, &mid));
+            assert_eq!(
+                Err(GatewayError::VaultError("Unknown token".into())),
+                vault.detokenise(&token, &other_mid)
+            );
+        }
+        assert_eq!(
+            Err(GatewayError::FieldError(
+                "Invalid pan: cannot be tokenised".into()
+            )),
+            vault.tokenise("4111", &mid)
+        );
+    }
+
+    #[test]
+    fn test_reopen() {
+        let vault = test_vault("reopen");
+        let mid = Mid::validate("000104912345678", ctx!()).unwrap();
+        let token = vault.tokenise("4111111111111111", &mid).unwrap();
+
+        let reopened = FileVault::open(&vault.path, &[7; 32]).unwrap();
+        assert_eq!(
+            Ok("4111111111111111".to_string()),
+            reopened.detokenise(&token, &mid)
+        );
+        assert_eq!(token, reopened.tokenise("4111111111111111", &mid).unwrap());
+        assert!(!fs::read_to_string(&vault.path)
+            .unwrap()
+            .contains("4111111111111111"));
+
+        let wrong_key = FileVault::open(&vault.path, &[8; 32]).unwrap();
+        assert_eq!(
+            Err(GatewayError::VaultError(
+                "Cannot decrypt pan, wrong key?".into()
+            )),
+            wrong_key.detokenise(&token, &mid)
+        );
+    }
+
+    #[test]
+    fn test_vault_is_removed() {
+        let vault = test_vault("removed");
+        let mid = Mid::validate("000104912345678", ctx!()).unwrap();
+        vault.tokenise("4111111111111111", &mid).unwrap();
+        let path = vault.path.clone();
+        assert!(path.exists());
+        drop(vault);
+        assert!(!path.exists());
+    }
+}